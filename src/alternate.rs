@@ -0,0 +1,129 @@
+use crate::{dirs::PollenDirs, entry::Entry, host::HostFacts};
+use std::{fs, path::PathBuf};
+
+/// Separates a stored name from its alternate suffix, as in `monitors.conf##host.laptop`
+pub const ALTERNATE_SEPARATOR: &str = "##";
+
+/// Alternate kinds from most to least specific
+const ALTERNATE_KINDS: [&str; 4] = ["host", "user", "distro", "os"];
+
+/// The stored copy of an entry chosen for this machine
+#[derive(Debug, Clone)]
+pub struct StoredCopy {
+    /// Path inside the files directory, which may not exist yet
+    pub path: PathBuf,
+    /// Alternate suffix in use, e.g. "host.laptop", or None for the plain stored copy
+    pub alternate: Option<String>,
+}
+
+/// Name an entry is stored under in the files directory
+pub fn stored_name(entry: &Entry) -> String {
+    entry.name.replace(['/', '\\'], "_")
+}
+
+/// Alternate suffixes present in the files directory for an entry, e.g. `["default", "os.linux"]`
+pub fn list_alternates(dirs: &PollenDirs, entry: &Entry) -> Vec<String> {
+    let prefix = format!("{}{}", stored_name(entry), ALTERNATE_SEPARATOR);
+
+    let mut alternates: Vec<String> = fs::read_dir(&dirs.files_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|file| file.file_name().to_str().and_then(|name| name.strip_prefix(&prefix)).map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    alternates.sort();
+    alternates
+}
+
+/// Pick the most specific stored copy for this machine.
+///
+/// Matching `host`, `user`, `distro` and `os` alternates win in that order, then the plain stored
+/// copy, then `##default`. With nothing stored, the plain path is returned so gather creates it.
+pub fn select_stored_copy(dirs: &PollenDirs, entry: &Entry, facts: &HostFacts) -> StoredCopy {
+    let name = stored_name(entry);
+    let alternates = list_alternates(dirs, entry);
+
+    for kind in ALTERNATE_KINDS {
+        let matched = alternates.iter().find(|alternate| {
+            alternate
+                .split_once('.')
+                .is_some_and(|(alternate_kind, value)| alternate_kind == kind && fact_matches(kind, value, facts))
+        });
+
+        if let Some(alternate) = matched {
+            return StoredCopy {
+                path: dirs.files_dir.join(format!("{}{}{}", name, ALTERNATE_SEPARATOR, alternate)),
+                alternate: Some(alternate.clone()),
+            };
+        }
+    }
+
+    let plain = dirs.files_dir.join(&name);
+    if !plain.exists() && alternates.iter().any(|alternate| alternate == "default") {
+        return StoredCopy {
+            path: dirs.files_dir.join(format!("{}{}default", name, ALTERNATE_SEPARATOR)),
+            alternate: Some("default".to_string()),
+        };
+    }
+
+    StoredCopy { path: plain, alternate: None }
+}
+
+fn fact_matches(kind: &str, value: &str, facts: &HostFacts) -> bool {
+    match kind {
+        "host" => value.eq_ignore_ascii_case(&facts.hostname),
+        "user" => value == facts.username,
+        "distro" => facts.distro.as_deref() == Some(value) || facts.distro_like.iter().any(|like| like == value),
+        "os" => value == facts.os,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::EntryArgument;
+
+    fn facts() -> HostFacts {
+        HostFacts {
+            hostname: "laptop".to_string(),
+            username: "ana".to_string(),
+            os: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            distro: Some("pop".to_string()),
+            distro_like: vec!["ubuntu".to_string()],
+        }
+    }
+
+    #[test]
+    fn most_specific_alternate_wins() {
+        let root = std::env::temp_dir().join(format!("pollen-alternates-{}", std::process::id()));
+        let dirs = PollenDirs::from_base_dirs(root.join("config"), root.join("state"), root.join("data"), root.join("cache")).unwrap();
+        let entry = Entry::new(EntryArgument { name: "monitors.conf".to_string(), path: root.join("home/monitors.conf") });
+        let store = |suffix: &str| fs::write(dirs.files_dir.join(format!("monitors.conf{}", suffix)), suffix).unwrap();
+        let selected = || select_stored_copy(&dirs, &entry, &facts()).alternate;
+
+        assert_eq!(selected(), None);
+        store("##default");
+        assert_eq!(selected().as_deref(), Some("default"));
+        store("");
+        assert_eq!(selected(), None);
+        store("##os.macos");
+        store("##os.linux");
+        assert_eq!(selected().as_deref(), Some("os.linux"));
+        store("##distro.ubuntu");
+        assert_eq!(selected().as_deref(), Some("distro.ubuntu"));
+        store("##user.ana");
+        assert_eq!(selected().as_deref(), Some("user.ana"));
+        store("##host.desktop");
+        assert_eq!(selected().as_deref(), Some("user.ana"));
+        store("##host.Laptop");
+        assert_eq!(selected().as_deref(), Some("host.Laptop"));
+
+        assert_eq!(list_alternates(&dirs, &entry).len(), 7);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
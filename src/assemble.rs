@@ -0,0 +1,301 @@
+use crate::{condition::Condition, error::PollenError, host::HostFacts, yaml_ext::{CanForceIntoBool, CanForceIntoString}};
+use serde_yaml::Value;
+use std::{
+    fs,
+    path::{Component, Path},
+};
+
+/// How an entry is assembled from fragments in the files directory, from `assemble_from:`
+///
+/// Written either as a list of fragments or as a mapping with `fragments` and the optional
+/// `header`, `separator`, `comment` and `markers` settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembly {
+    /// Fragments in the order they are concatenated
+    pub fragments: Vec<Fragment>,
+    /// Text put before the first fragment
+    pub header: Option<String>,
+    /// Text put between two fragments
+    pub separator: String,
+    /// Line comment prefix for the fragment markers, "#" by default
+    pub comment: String,
+    /// Whether each fragment is wrapped in markers, which lets gather map edits back
+    pub markers: bool,
+}
+
+/// One fragment of an assembled file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    /// Path relative to the files directory
+    pub file: String,
+    /// Condition for including the fragment on this machine
+    pub when: Option<Condition>,
+}
+
+impl Assembly {
+    /// Parse the value of an `assemble_from:` option
+    pub fn parse(value: &Value) -> Result<Assembly, PollenError> {
+        let mut assembly = Assembly {
+            fragments: Vec::new(),
+            header: None,
+            separator: "\n".to_string(),
+            comment: "#".to_string(),
+            markers: true,
+        };
+
+        let fragments = match value {
+            Value::Sequence(fragments) => fragments,
+            Value::Mapping(mapping) => {
+                for (key, value) in mapping {
+                    match key.force_into_string()?.as_str() {
+                        "fragments" => {}
+                        "header" => assembly.header = Some(value.force_into_string()?),
+                        "separator" => assembly.separator = value.force_into_string()?,
+                        "comment" => assembly.comment = value.force_into_string()?,
+                        "markers" => assembly.markers = value.force_into_bool()?,
+                        other => {
+                            return Err(PollenError::InvalidOption(format!(
+                                "Unknown assemble_from setting '{}' (expected fragments, header, separator, comment or markers)",
+                                other
+                            )));
+                        }
+                    }
+                }
+                mapping.get("fragments").and_then(Value::as_sequence).ok_or_else(|| {
+                    PollenError::InvalidOption("assemble_from needs a list of fragments".to_string())
+                })?
+            }
+            _ => {
+                return Err(PollenError::InvalidOption(
+                    "assemble_from must be a list of fragments or a mapping with 'fragments'".to_string()
+                ));
+            }
+        };
+
+        for fragment in fragments {
+            assembly.fragments.push(Fragment::parse(fragment)?);
+        }
+
+        if assembly.fragments.is_empty() {
+            return Err(PollenError::InvalidOption("assemble_from lists no fragments".to_string()));
+        }
+        Ok(assembly)
+    }
+
+    /// Fragments included on the machine described by `facts`
+    pub fn active_fragments(&self, facts: &HostFacts) -> Vec<&Fragment> {
+        self.fragments
+            .iter()
+            .filter(|fragment| fragment.when.as_ref().is_none_or(|condition| condition.evaluate(facts)))
+            .collect()
+    }
+
+    /// Concatenate the active fragments from `files_dir`
+    pub fn assemble(&self, files_dir: &Path, facts: &HostFacts) -> Result<String, PollenError> {
+        let mut contents = Vec::new();
+        for fragment in self.active_fragments(facts) {
+            let path = files_dir.join(&fragment.file);
+            let content = fs::read_to_string(&path).map_err(|e| {
+                PollenError::Io(std::io::Error::new(e.kind(), format!("Cannot read fragment {}: {}", path.display(), e)))
+            })?;
+            contents.push((fragment.file.as_str(), content));
+        }
+        Ok(self.join(&contents))
+    }
+
+    /// Split an assembled file back into the content of each active fragment using the markers.
+    ///
+    /// Fails when markers are off, missing or out of order, or when text outside the markers
+    /// was changed, since those edits would have nowhere to go.
+    pub fn split(&self, assembled: &str, facts: &HostFacts) -> Result<Vec<(String, String)>, String> {
+        if !self.markers {
+            return Err("markers are turned off".to_string());
+        }
+
+        let mut parts: Vec<(String, String)> = Vec::new();
+        let mut current: Option<(String, String)> = None;
+
+        for line in assembled.split_inclusive('\n') {
+            let trimmed = line.trim_end_matches(['\n', '\r']);
+            match &mut current {
+                None => {
+                    if let Some(file) = trimmed.strip_prefix(&self.begin_marker("")) {
+                        current = Some((file.to_string(), String::new()));
+                    }
+                }
+                Some((file, content)) => {
+                    if trimmed == self.end_marker(file) {
+                        parts.extend(current.take());
+                    } else if trimmed.starts_with(&self.begin_marker("")) {
+                        return Err(format!("fragment '{}' has no end marker", file));
+                    } else {
+                        content.push_str(line);
+                    }
+                }
+            }
+        }
+
+        if let Some((file, _)) = current {
+            return Err(format!("fragment '{}' has no end marker", file));
+        }
+
+        let expected: Vec<&str> = self.active_fragments(facts).iter().map(|fragment| fragment.file.as_str()).collect();
+        let found: Vec<&str> = parts.iter().map(|(file, _)| file.as_str()).collect();
+        if found != expected {
+            return Err(format!(
+                "expected fragments {} but found {}",
+                expected.join(", "),
+                if found.is_empty() { "none".to_string() } else { found.join(", ") }
+            ));
+        }
+
+        let borrowed: Vec<(&str, String)> = parts.iter().map(|(file, content)| (file.as_str(), content.clone())).collect();
+        if self.join(&borrowed) != assembled {
+            return Err("text outside the fragment markers was changed".to_string());
+        }
+
+        Ok(parts)
+    }
+
+    fn join(&self, contents: &[(&str, String)]) -> String {
+        let mut output = String::new();
+        if let Some(header) = &self.header {
+            output.push_str(header);
+            if !header.ends_with('\n') {
+                output.push('\n');
+            }
+        }
+
+        for (index, (file, content)) in contents.iter().enumerate() {
+            if index > 0 {
+                output.push_str(&self.separator);
+            }
+            if self.markers {
+                output.push_str(&self.begin_marker(file));
+                output.push('\n');
+            }
+            output.push_str(content);
+            if self.markers && !content.is_empty() && !content.ends_with('\n') {
+                output.push('\n');
+            }
+            if self.markers {
+                output.push_str(&self.end_marker(file));
+                output.push('\n');
+            }
+        }
+        output
+    }
+
+    fn begin_marker(&self, file: &str) -> String {
+        format!("{} >>> pollen fragment {}", self.comment, file)
+    }
+
+    fn end_marker(&self, file: &str) -> String {
+        format!("{} <<< pollen fragment {}", self.comment, file)
+    }
+}
+
+impl Fragment {
+    fn parse(value: &Value) -> Result<Fragment, PollenError> {
+        let fragment = match value {
+            Value::String(file) => Fragment { file: file.clone(), when: None },
+            Value::Mapping(mapping) => {
+                let mut file = None;
+                let mut when = None;
+                for (key, value) in mapping {
+                    match key.force_into_string()?.as_str() {
+                        "file" => file = Some(value.force_into_string()?),
+                        "when" => when = Some(Condition::parse(value)?),
+                        other => {
+                            return Err(PollenError::InvalidOption(format!(
+                                "Unknown fragment setting '{}' (expected file or when)", other
+                            )));
+                        }
+                    }
+                }
+                let file = file.ok_or_else(|| PollenError::InvalidOption("Fragment is missing 'file'".to_string()))?;
+                Fragment { file, when }
+            }
+            _ => {
+                return Err(PollenError::InvalidOption(
+                    "A fragment must be a file name or a mapping with 'file'".to_string()
+                ));
+            }
+        };
+
+        // Fragments live inside the files directory
+        let path = Path::new(&fragment.file);
+        if fragment.file.is_empty() || path.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return Err(PollenError::InvalidOption(format!(
+                "Fragment '{}' must be a relative path inside the files directory", fragment.file
+            )));
+        }
+        Ok(fragment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assembly(yaml: &str) -> Assembly {
+        Assembly::parse(&serde_yaml::from_str(yaml).unwrap()).unwrap()
+    }
+
+    fn facts(os: &str) -> HostFacts {
+        HostFacts {
+            hostname: "host".to_string(),
+            username: "user".to_string(),
+            os: os.to_string(),
+            arch: "x86_64".to_string(),
+            distro: None,
+            distro_like: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn split_inverts_join() {
+        let assembly = assembly("[base.sh, {file: linux.sh, when: {os: linux}}]");
+        let parts = vec![("base.sh", "a=1".to_string()), ("linux.sh", "b=2\n".to_string())];
+        let joined = assembly.join(&parts);
+        assert_eq!(
+            joined,
+            "# >>> pollen fragment base.sh\na=1\n# <<< pollen fragment base.sh\n\n\
+             # >>> pollen fragment linux.sh\nb=2\n# <<< pollen fragment linux.sh\n"
+        );
+
+        let split = assembly.split(&joined.replace("a=1", "a=3"), &facts("linux")).unwrap();
+        assert_eq!(split, vec![("base.sh".to_string(), "a=3\n".to_string()), ("linux.sh".to_string(), "b=2\n".to_string())]);
+    }
+
+    #[test]
+    fn conditions_pick_active_fragments() {
+        let assembly = assembly("[base.sh, {file: linux.sh, when: {os: linux}}]");
+        let files: Vec<&str> = assembly.active_fragments(&facts("macos")).iter().map(|f| f.file.as_str()).collect();
+        assert_eq!(files, ["base.sh"]);
+    }
+
+    #[test]
+    fn split_refuses_edits_it_cannot_place() {
+        let assembly = assembly("{fragments: [a, b], header: '# generated'}");
+        let joined = assembly.join(&[("a", "1\n".to_string()), ("b", "2\n".to_string())]);
+        let facts = facts("linux");
+
+        assert_eq!(
+            assembly.split(&joined.replace("# generated", "# edited"), &facts).unwrap_err(),
+            "text outside the fragment markers was changed"
+        );
+        assert_eq!(
+            assembly.split(&joined.replace("# <<< pollen fragment a\n", ""), &facts).unwrap_err(),
+            "fragment 'a' has no end marker"
+        );
+        assert_eq!(assembly.split("", &facts).unwrap_err(), "expected fragments a, b but found none");
+    }
+
+    #[test]
+    fn fragments_must_stay_inside_the_files_directory() {
+        for value in ["[../escape]", "[/etc/passwd]", "[]", "{fragments: [a], bogus: 1}"] {
+            assert!(Assembly::parse(&serde_yaml::from_str(value).unwrap()).is_err(), "{} parsed", value);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Managed blocks: a region of a file pollen owns, between marker lines, in a file it does not own.
+//!
+//! The rest of the file is left exactly as it is, so tools that edit the same file keep their edits.
+
+/// Line opening the block of the entry `name`
+pub fn begin_marker(name: &str) -> String {
+    format!("# >>> pollen:{} >>>", name)
+}
+
+/// Line closing the block of the entry `name`
+pub fn end_marker(name: &str) -> String {
+    format!("# <<< pollen:{} <<<", name)
+}
+
+/// Byte ranges of a block: the whole block including its markers, and the content between them
+struct Span {
+    start: usize,
+    content_start: usize,
+    content_end: usize,
+    end: usize,
+}
+
+/// The content of the block `name` in `text`, or None when the file has no such block
+pub fn extract(text: &str, name: &str) -> Result<Option<String>, String> {
+    Ok(find(text, name)?.map(|span| text[span.content_start..span.content_end].to_string()))
+}
+
+/// `text` with the block `name` set to `content`, appended at the end if the block is not there yet
+pub fn replace(text: &str, name: &str, content: &str) -> Result<String, String> {
+    let content = with_final_newline(content);
+    match find(text, name)? {
+        Some(span) => Ok(format!("{}{}{}", &text[..span.content_start], content, &text[span.content_end..])),
+        None => {
+            let mut output = text.to_string();
+            if !output.is_empty() && !output.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str(&begin_marker(name));
+            output.push('\n');
+            output.push_str(&content);
+            output.push_str(&end_marker(name));
+            output.push('\n');
+            Ok(output)
+        }
+    }
+}
+
+/// `text` without the block `name` and its markers
+pub fn remove(text: &str, name: &str) -> Result<String, String> {
+    match find(text, name)? {
+        Some(span) => Ok(format!("{}{}", &text[..span.start], &text[span.end..])),
+        None => Ok(text.to_string()),
+    }
+}
+
+/// Block content as it is written between the markers, which always end it with a newline
+pub fn with_final_newline(content: &str) -> String {
+    if content.is_empty() || content.ends_with('\n') {
+        content.to_string()
+    } else {
+        format!("{}\n", content)
+    }
+}
+
+fn find(text: &str, name: &str) -> Result<Option<Span>, String> {
+    let begin = begin_marker(name);
+    let end = end_marker(name);
+    let mut open: Option<(usize, usize)> = None;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let next = offset + line.len();
+        let trimmed = line.trim();
+        match open {
+            None if trimmed == begin => open = Some((offset, next)),
+            None if trimmed == end => return Err(format!("'{}' comes before its opening marker", end)),
+            Some(_) if trimmed == begin => return Err(format!("'{}' appears twice", begin)),
+            Some((start, content_start)) if trimmed == end => {
+                // A second block of the same name would be ambiguous
+                if text[next..].lines().any(|line| line.trim() == begin) {
+                    return Err(format!("'{}' appears twice", begin));
+                }
+                return Ok(Some(Span { start, content_start, content_end: offset, end: next }));
+            }
+            _ => {}
+        }
+        offset = next;
+    }
+
+    match open {
+        Some(_) => Err(format!("'{}' has no closing marker", begin)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_appends_a_missing_block() {
+        let text = replace("export A=1", "zsh", "alias l=ls").unwrap();
+        assert_eq!(text, "export A=1\n# >>> pollen:zsh >>>\nalias l=ls\n# <<< pollen:zsh <<<\n");
+        assert_eq!(extract(&text, "zsh").unwrap().as_deref(), Some("alias l=ls\n"));
+    }
+
+    #[test]
+    fn replace_keeps_text_around_the_block() {
+        let text = "before\n# >>> pollen:zsh >>>\nold\n# <<< pollen:zsh <<<\nafter\n";
+        assert_eq!(
+            replace(text, "zsh", "new\n").unwrap(),
+            "before\n# >>> pollen:zsh >>>\nnew\n# <<< pollen:zsh <<<\nafter\n"
+        );
+        assert_eq!(remove(text, "zsh").unwrap(), "before\nafter\n");
+        assert_eq!(extract(text, "git").unwrap(), None);
+    }
+
+    #[test]
+    fn broken_markers_are_errors() {
+        let unclosed = "# >>> pollen:zsh >>>\nold\n";
+        let twice = "# >>> pollen:zsh >>>\n# <<< pollen:zsh <<<\n# >>> pollen:zsh >>>\n# <<< pollen:zsh <<<\n";
+        let reversed = "# <<< pollen:zsh <<<\n";
+        assert_eq!(extract(unclosed, "zsh").unwrap_err(), "'# >>> pollen:zsh >>>' has no closing marker");
+        assert_eq!(extract(twice, "zsh").unwrap_err(), "'# >>> pollen:zsh >>>' appears twice");
+        assert_eq!(extract(reversed, "zsh").unwrap_err(), "'# <<< pollen:zsh <<<' comes before its opening marker");
+    }
+}
@@ -50,6 +50,62 @@ pub fn undo_action(c: &Context) {
     }
 }
 
+pub fn restore_action(c: &Context) {
+    if let Err(e) = restore::restore_files(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn status_action(c: &Context) {
+    if let Err(e) = status::show_status(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn diff_action(c: &Context) {
+    if let Err(e) = diff::show_diff(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn profile_action(c: &Context) {
+    if let Err(e) = profile::handle_profile_command(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn graph_action(c: &Context) {
+    if let Err(e) = graph::show_graph(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn render_action(c: &Context) {
+    if let Err(e) = render::render_templates(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn decrypt_action(c: &Context) {
+    if let Err(e) = decrypt::decrypt_file(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn trash_action(c: &Context) {
+    if let Err(e) = trash::handle_trash_command(c) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
 pub fn git_action(c: &Context) {
     if let Err(e) = git::handle_git_command(c) {
         eprintln!("Error: {}", e);
@@ -4,10 +4,12 @@ use std::env;
 
 use super::actions::*;
 use super::commands;
+use super::utils::set_config_files;
 
 /// Main CLI application entry point
 pub fn run() -> Result<(), PollenError> {
-    let args: Vec<String> = env::args().collect();
+    let (args, config_files) = take_config_flags(env::args().collect());
+    set_config_files(config_files);
     let app = App::new(env!("CARGO_PKG_NAME"))
         .description(env!("CARGO_PKG_DESCRIPTION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -15,7 +17,7 @@ pub fn run() -> Result<(), PollenError> {
         .usage("pollen [COMMAND] [OPTIONS]")
         .flag(
             Flag::new("config", FlagType::String)
-                .description("Configuration file to use")
+                .description("Track file to use; repeat to combine several")
                 .alias("c")
         )
         .flag(
@@ -44,6 +46,18 @@ pub fn run() -> Result<(), PollenError> {
                         .description("Show file paths instead of entry names")
                         .alias("p")
                 )
+                .flag(
+                    Flag::new("tag", FlagType::String)
+                        .description("Only operate on entries matching a tag expression, e.g. \"shell or editor\"")
+                )
+                .flag(
+                    Flag::new("exclude-tag", FlagType::String)
+                        .description("Skip entries matching a tag expression")
+                )
+                .flag(
+                    Flag::new("by-tag", FlagType::Bool)
+                        .description("Group entries by tag")
+                )
                 .action(list_action)
         )
         .command(
@@ -56,24 +70,193 @@ pub fn run() -> Result<(), PollenError> {
             Command::new("gather")
                 .description("Gather configuration files from the system into the files directory")
                 .usage("pollen gather [ENTRY_NAMES...]")
+                .flag(
+                    Flag::new("wait", FlagType::Bool)
+                        .description("Wait for another running pollen process instead of failing")
+                )
+                .flag(
+                    Flag::new("verbose", FlagType::Bool)
+                        .description("Enable verbose output")
+                        .alias("v")
+                )
+                .flag(
+                    Flag::new("profile", FlagType::String)
+                        .description("Only operate on the entries of this profile")
+                )
+                .flag(
+                    Flag::new("tag", FlagType::String)
+                        .description("Only operate on entries matching a tag expression, e.g. \"shell or editor\"")
+                )
+                .flag(
+                    Flag::new("exclude-tag", FlagType::String)
+                        .description("Skip entries matching a tag expression")
+                )
+                .flag(
+                    Flag::new("with-deps", FlagType::Bool)
+                        .description("Also operate on the entries the selected ones depend on")
+                )
+                .flag(
+                    Flag::new("with-dependents", FlagType::Bool)
+                        .description("Also operate on the entries that depend on the selected ones")
+                )
                 .action(gather_action)
         )
         .command(
             Command::new("scatter")
                 .description("Scatter files from the files directory to their target locations")
                 .usage("pollen scatter [ENTRY_NAMES...]")
+                .flag(
+                    Flag::new("wait", FlagType::Bool)
+                        .description("Wait for another running pollen process instead of failing")
+                )
+                .flag(
+                    Flag::new("verbose", FlagType::Bool)
+                        .description("Enable verbose output")
+                        .alias("v")
+                )
+                .flag(
+                    Flag::new("profile", FlagType::String)
+                        .description("Only operate on the entries of this profile")
+                )
+                .flag(
+                    Flag::new("tag", FlagType::String)
+                        .description("Only operate on entries matching a tag expression, e.g. \"shell or editor\"")
+                )
+                .flag(
+                    Flag::new("exclude-tag", FlagType::String)
+                        .description("Skip entries matching a tag expression")
+                )
+                .flag(
+                    Flag::new("with-deps", FlagType::Bool)
+                        .description("Also operate on the entries the selected ones depend on")
+                )
+                .flag(
+                    Flag::new("with-dependents", FlagType::Bool)
+                        .description("Also operate on the entries that depend on the selected ones")
+                )
                 .action(scatter_action)
         )
+        .command(
+            Command::new("status")
+                .description("Show which entries differ between the files directory and the system")
+                .usage("pollen status [ENTRY_NAMES...] [--profile NAME]")
+                .flag(
+                    Flag::new("profile", FlagType::String)
+                        .description("Only operate on the entries of this profile")
+                )
+                .flag(
+                    Flag::new("tag", FlagType::String)
+                        .description("Only operate on entries matching a tag expression, e.g. \"shell or editor\"")
+                )
+                .flag(
+                    Flag::new("exclude-tag", FlagType::String)
+                        .description("Skip entries matching a tag expression")
+                )
+                .action(status_action)
+        )
+        .command(
+            Command::new("diff")
+                .description("Show what scatter would change on the system")
+                .usage("pollen diff [ENTRY_NAMES...] [--profile NAME]")
+                .flag(
+                    Flag::new("profile", FlagType::String)
+                        .description("Only operate on the entries of this profile")
+                )
+                .flag(
+                    Flag::new("tag", FlagType::String)
+                        .description("Only operate on entries matching a tag expression, e.g. \"shell or editor\"")
+                )
+                .flag(
+                    Flag::new("exclude-tag", FlagType::String)
+                        .description("Skip entries matching a tag expression")
+                )
+                .action(diff_action)
+        )
+        .command(
+            Command::new("profile")
+                .description("List profiles or set the default profile for this machine")
+                .usage("pollen profile [list | show <NAME> | use <NAME> | clear]")
+                .action(profile_action)
+        )
         .command(
             Command::new("undo")
                 .description("Undo the last gather or scatter operation")
                 .usage("pollen undo")
+                .flag(
+                    Flag::new("wait", FlagType::Bool)
+                        .description("Wait for another running pollen process instead of failing")
+                )
+                .flag(
+                    Flag::new("verbose", FlagType::Bool)
+                        .description("Enable verbose output")
+                        .alias("v")
+                )
                 .action(undo_action)
         )
+        .command(
+            Command::new("restore")
+                .description("Restore entries to how they were at a date, operation or git revision")
+                .usage("pollen restore --at <DATE|OPERATION_ID|GIT_REV> [ENTRY_NAMES...]")
+                .flag(
+                    Flag::new("at", FlagType::String)
+                        .description("Point in time to restore: YYYY-MM-DD [HH:MM[:SS]], an operation ID or a git revision")
+                )
+                .flag(
+                    Flag::new("dry-run", FlagType::Bool)
+                        .description("Show the restore plan without changing anything")
+                        .alias("n")
+                )
+                .flag(
+                    Flag::new("wait", FlagType::Bool)
+                        .description("Wait for another running pollen process instead of failing")
+                )
+                .flag(
+                    Flag::new("verbose", FlagType::Bool)
+                        .description("Enable verbose output")
+                        .alias("v")
+                )
+                .action(restore_action)
+        )
+        .command(
+            Command::new("graph")
+                .description("Show how entries depend on each other")
+                .usage("pollen graph [ENTRY_NAMES...] [--format text|dot|mermaid]")
+                .flag(
+                    Flag::new("format", FlagType::String)
+                        .description("Output format: text (default), dot or mermaid")
+                )
+                .action(graph_action)
+        )
+        .command(
+            Command::new("render")
+                .description("Print templated entries as they would be scattered on this machine")
+                .usage("pollen render [ENTRY_NAMES...]")
+                .action(render_action)
+        )
+        .command(
+            Command::new("decrypt")
+                .description("Print an encrypted stored copy in plaintext (used by git diff)")
+                .usage("pollen decrypt <FILE>")
+                .action(decrypt_action)
+        )
+        .command(
+            Command::new("trash")
+                .description("List, restore or empty files Pollen moved to the trash")
+                .usage("pollen trash [list | restore <ID>... | empty]")
+                .flag(
+                    Flag::new("wait", FlagType::Bool)
+                        .description("Wait for another running pollen process instead of failing")
+                )
+                .action(trash_action)
+        )
         .command(
             Command::new("git")
                 .description("Git operations for the files directory")
                 .usage("pollen git [SUBCOMMAND]")
+                .flag(
+                    Flag::new("wait", FlagType::Bool)
+                        .description("Wait for another running pollen process instead of failing")
+                )
                 .action(git_action)
         )
         .command(
@@ -94,6 +277,38 @@ pub fn run() -> Result<(), PollenError> {
     Ok(())
 }
 
+/// Take every `--config`/`-c` out of the arguments.
+///
+/// seahorse only sees the first occurrence of a flag and only where it is declared, so the
+/// track files are collected here for all commands. Arguments to `pollen git` are left alone.
+fn take_config_flags(args: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let mut remaining = Vec::new();
+    let mut config_files = Vec::new();
+    let mut command_seen = false;
+    let mut pass_through = false;
+
+    let mut args = args.into_iter();
+    remaining.extend(args.next());
+
+    while let Some(arg) = args.next() {
+        if pass_through {
+            remaining.push(arg);
+        } else if arg == "--config" || arg == "-c" {
+            config_files.extend(args.next());
+        } else if let Some(file) = arg.strip_prefix("--config=") {
+            config_files.push(file.to_string());
+        } else {
+            if !command_seen && !arg.starts_with('-') {
+                command_seen = true;
+                pass_through = arg == "git";
+            }
+            remaining.push(arg);
+        }
+    }
+
+    (remaining, config_files)
+}
+
 fn default_action(c: &Context) {
     // If no command is specified, default to parse
     if let Err(e) = commands::parse::parse_config(c) {
@@ -4,11 +4,14 @@ use seahorse::Context;
 pub fn change_to_pollen_dir(c: &Context) -> Result<(), PollenError> {
     let dirs = PollenDirs::new()?;
     
-    let subdirectory = c.args.get(0).map(|s| s.as_str());
+    let subdirectory = c.args.first().map(|s| s.as_str());
     
     let target_dir = match subdirectory {
         Some("files") => dirs.files_dir.clone(),
+        Some("state") => dirs.state_dir.clone(),
+        Some("data") => dirs.data_dir.clone(),
         Some("cache") => dirs.cache_dir.clone(),
+        Some("backups") => dirs.backup_dir.clone(),
         Some("config") => dirs.config_dir.clone(),
         Some(other) => {
             // Check if it's a subdirectory within the pollen directory
@@ -31,7 +34,7 @@ pub fn change_to_pollen_dir(c: &Context) -> Result<(), PollenError> {
     
     // Detect the shell and provide the appropriate command
     let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
-    let shell_name = shell.split('/').last().unwrap_or("sh");
+    let shell_name = shell.split('/').next_back().unwrap_or("sh");
     
     match shell_name {
         "fish" => {
@@ -72,7 +75,10 @@ pub fn change_to_pollen_dir(c: &Context) -> Result<(), PollenError> {
     if subdirectory.is_none() {
         println!("Available subdirectories:");
         println!("  pollen cd files  - Go to files directory");
+        println!("  pollen cd state  - Go to state directory (operation history)");
+        println!("  pollen cd data   - Go to data directory");
         println!("  pollen cd cache  - Go to cache directory");
+        println!("  pollen cd backups - Go to backup directory");
         println!("  pollen cd config - Go to config directory (same as root)");
     }
     
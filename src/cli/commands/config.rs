@@ -1,4 +1,4 @@
-use crate::{PollenDirs, PollenError};
+use crate::{PollenDirs, PollenLock, PollenError};
 use seahorse::Context;
 use std::path::PathBuf;
 
@@ -17,10 +17,21 @@ pub fn show_config(_c: &Context) -> Result<(), PollenError> {
         println!("POLLEN_DIR: <not set> (using default)");
     }
     println!("Configuration directory: {}", dirs.config_dir.display());
+    println!("State directory: {}", dirs.state_dir.display());
+    println!("Data directory: {}", dirs.data_dir.display());
     println!("Cache directory: {}", dirs.cache_dir.display());
+    println!("Backup directory: {}", dirs.backup_dir.display());
+    println!("Trash directory: {}", dirs.trash_dir.display());
     println!("Files directory: {}", dirs.files_dir.display());
     println!("Track file: {}", dirs.track_file.display());
     println!("Config file: {}", dirs.pollen_config_file.display());
+    println!("Local settings: {}", dirs.local_config_file.display());
+    println!("Operation history: {}", dirs.operations_file.display());
+    println!("Lock file: {}", dirs.lock_file.display());
+    match PollenLock::holder(&dirs) {
+        Some(holder) => println!("Lock: held by PID {} ({})", holder.pid, holder.command),
+        None => println!("Lock: free"),
+    }
     println!();
     
     // Show environment variables
@@ -44,10 +55,43 @@ pub fn show_config(_c: &Context) -> Result<(), PollenError> {
     println!("  Verbose mode: {}", config.verbose.unwrap_or(false));
     println!("  Cache expiration: {} seconds", config.cache_expiration.unwrap_or(86400));
     println!("  Max cache entries: {}", config.max_cache_entries.unwrap_or(100));
+    println!("  Backup location: {}", config.backup_location.as_deref().unwrap_or("data"));
+    match config.trash_max_age {
+        Some(max_age) => println!("  Trash max age: {} seconds", max_age),
+        None => println!("  Trash max age: unlimited"),
+    }
+    match config.trash_max_size {
+        Some(max_size) => println!("  Trash max size: {} bytes", max_size),
+        None => println!("  Trash max size: unlimited"),
+    }
+    println!("  Include dependencies: {}", config.with_deps.unwrap_or(false));
     println!("  Auto-commit: {}", config.auto_commit.unwrap_or(false));
     if let Some(ref message) = config.auto_commit_message {
         println!("  Auto-commit message: \"{}\"", message);
     }
+    match (&config.encryption_key_file, &config.encryption_passphrase) {
+        (Some(key_file), _) => println!("  Encryption: key file {}", key_file),
+        (None, Some(_)) => println!("  Encryption: passphrase"),
+        (None, None) => println!("  Encryption: not configured"),
+    }
+    println!("  Secrets file: {}", config.secrets_file.as_deref().unwrap_or("secrets.yaml (default)"));
+    if let Some(ref command) = config.secret_command {
+        println!("  Secret command: {}", command);
+    }
+    match &config.filters {
+        Some(filters) if !filters.is_empty() => {
+            println!("  Filters: {}", filters.keys().cloned().collect::<Vec<_>>().join(", "));
+        }
+        _ => println!("  Filters: none"),
+    }
+    println!("  Secret scan: {}", config.secret_scan.as_deref().unwrap_or("commit"));
+    println!("  Secret allowlist: {}", config.secret_allowlist.as_deref().unwrap_or(".secret-allowlist (default)"));
+    println!();
+    
+    let local = dirs.load_local_config()?;
+    println!("Settings from local.yaml:");
+    println!("  Default profile: {}", local.profile.as_deref().unwrap_or("<none>"));
+    println!("  Template variables: {}", local.variables.as_ref().map(|v| v.len()).unwrap_or(0));
     println!();
     
     // Show Git status
@@ -0,0 +1,23 @@
+use crate::{PollenDirs, PollenError};
+use crate::crypt::{is_encrypted, Cipher};
+use seahorse::Context;
+use std::{fs, io::Write};
+
+/// Print a stored copy in plaintext; git runs this as the textconv of encrypted entries
+pub fn decrypt_file(c: &Context) -> Result<(), PollenError> {
+    let path = c.args.first().ok_or_else(|| {
+        PollenError::InvalidOption("Usage: pollen decrypt <FILE>".to_string())
+    })?;
+
+    let content = fs::read(path).map_err(PollenError::Io)?;
+
+    // Anything that is not encrypted is printed as it is, which keeps the textconv harmless
+    let plaintext = if is_encrypted(&content) {
+        let dirs = PollenDirs::new()?;
+        Cipher::from_config(&dirs, &dirs.load_config()?)?.decrypt(&content)?
+    } else {
+        content
+    };
+
+    std::io::stdout().write_all(&plaintext).map_err(PollenError::Io)
+}
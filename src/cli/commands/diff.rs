@@ -0,0 +1,68 @@
+use crate::{ConfigParser, PollenDirs, HostFacts, PollenError};
+use crate::cli::pipeline::render_staging_dir;
+use crate::cli::commands::status::{entry_state, EntryState};
+use crate::cli::selection::{active_profile, select_entries};
+use crate::overlay::{entry_layers, overlay_names};
+use crate::template::template_variables;
+use crate::cli::utils::track_files;
+use seahorse::Context;
+use std::{fs, path::Path, process::Command};
+
+pub fn show_diff(c: &Context) -> Result<(), PollenError> {
+    let dirs = PollenDirs::new()?;
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+
+    let config_paths = track_files(&dirs, &config)?;
+
+    let entries = parser.parse_files(&config_paths)?;
+    let entries = select_entries(c, &parser, &dirs, &config, &config_paths, entries)?;
+
+    let facts = HostFacts::detect();
+    let variables = template_variables(&dirs, &config, &facts)?;
+    let staging_dir = render_staging_dir(&dirs);
+    let overlays = overlay_names(&dirs, &facts, active_profile(c, &dirs)?.as_deref());
+    let mut result = Ok(());
+
+    for entry in entries.iter().filter(|entry| entry.is_enabled(&facts)) {
+        let layers = entry_layers(&dirs, entry, &overlays);
+        match entry_state(entry, &dirs, &facts, &layers, &variables, &staging_dir) {
+            Ok((EntryState::Modified(_), expected_path, system_path)) => {
+                println!("==> {} <==", entry.get_display_name());
+                if let Err(e) = print_diff(&expected_path, &system_path) {
+                    result = Err(e);
+                    break;
+                }
+            }
+            Ok((EntryState::MissingOnSystem, ..)) => {
+                println!("==> {} <==\nOnly in files directory: {}", entry.get_display_name(), entry.path.display());
+            }
+            Ok((EntryState::NotGathered, ..)) => {
+                println!("==> {} <==\nOnly on system: {}", entry.get_display_name(), entry.path.display());
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("✗ Failed to compare {}: {}", entry.get_display_name(), e),
+        }
+    }
+
+    let _ = fs::remove_dir_all(&staging_dir);
+    result
+}
+
+/// Show what scattering would change: stored (or rendered) content against the system
+fn print_diff(expected: &Path, actual: &Path) -> Result<(), PollenError> {
+    let status = Command::new("git")
+        .args(["--no-pager", "diff", "--no-index", "--"])
+        .arg(actual)
+        .arg(expected)
+        .status()
+        .map_err(PollenError::Io)?;
+
+    // git diff exits with 1 when there are differences
+    match status.code() {
+        Some(0) | Some(1) => Ok(()),
+        _ => Err(PollenError::InvalidEndpoint(format!(
+            "git diff failed for {}", actual.display()
+        ))),
+    }
+}
@@ -1,44 +1,46 @@
-use crate::{ConfigParser, PollenDirs, Entry, PollenError, Operation, OperationType, OperationEntry};
-use crate::cli::utils::{execute_shell_command, copy_dir_all};
+use crate::{ConfigParser, PollenDirs, PollenLock, Entry, HostFacts, PollenError, Operation, OperationType, OperationEntry, HookOperation};
+use crate::alternate::{select_stored_copy, StoredCopy};
+use crate::alternate::stored_name;
+use crate::assemble::Assembly;
+use crate::cli::pipeline::{assemble_entry, layer_entry, render_entry, decrypt_entry, encrypt_entry, render_staging_dir, stage_block, stage_captured, conceal_secrets, filter_entry};
+use crate::filter::FilterDirection;
+use crate::cli::selection::{active_profile, add_related_entries, pulled_in_note, select_entries};
+use crate::scan::{report, scan_path, Allowlist, ScanMode};
+use crate::overlay::{entry_layers, file_layers, overlay_names, relative_files, Layer, OVERLAYS_DIR};
+use crate::cli::utils::{run_after_hooks, run_before_hooks, copy_path, differing_paths, remove_path, track_files, HookContext};
+use crate::template::template_variables;
 use seahorse::Context;
-use std::{fs, time::SystemTime};
+use serde_yaml::Mapping;
+use std::{fs, path::{Path, PathBuf}};
 
 pub fn gather_files(c: &Context) -> Result<(), PollenError> {
-    let config_file = c.string_flag("config").ok();
-    let target_entries: Vec<String> = c.args.clone();
-    
-    let parser = ConfigParser::new()?;
     let dirs = PollenDirs::new()?;
+    let _lock = PollenLock::acquire(&dirs, c.bool_flag("wait"))?;
     let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+    let verbose = c.bool_flag("verbose") || config.verbose.unwrap_or(false);
     
-    let (config_path, _used_config_file) = match config_file.as_deref() {
-        Some(path) => (path.to_string(), path.to_string()),
-        None => {
-            let track_file = dirs.get_track_file_path(&config);
-            let path_str = track_file.to_str().ok_or(PollenError::InvalidEndpoint("Invalid config file path".into()))?;
-            (path_str.to_string(), track_file.display().to_string())
-        }
-    };
+    let config_paths = track_files(&dirs, &config)?;
 
-    let entries = parser.parse_file(&config_path)?;
+    let entries = parser.parse_files(&config_paths)?;
     
-    // Filter entries based on command line arguments
-    let entries_to_gather: Vec<_> = if target_entries.is_empty() {
-        // If no specific entries specified, gather all
-        entries
-    } else {
-        entries
-            .into_iter()
-            .filter(|entry| {
-                // Match by name or alias
-                target_entries.iter().any(|target| {
-                    entry.name == *target || 
-                    entry.alias_as.as_ref() == Some(target) ||
-                    entry.get_display_name() == target
-                })
-            })
-            .collect()
-    };
+    // Filter entries by profile and command line arguments
+    let entries_to_gather = select_entries(c, &parser, &dirs, &config, &config_paths, entries.clone())?;
+    let (entries_to_gather, pulled_in) = add_related_entries(c, &parser, &config, entries_to_gather, entries)?;
+    
+    // Entries whose `when` condition does not hold on this machine are left alone
+    let facts = HostFacts::detect();
+    let (entries_to_gather, skipped): (Vec<_>, Vec<_>) = entries_to_gather
+        .into_iter()
+        .partition(|entry| entry.is_enabled(&facts));
+    
+    if verbose {
+        for entry in &skipped {
+            if let Some(condition) = &entry.when {
+                println!("• Skipped: {} (when {})", entry.get_display_name(), condition);
+            }
+        }
+    }
     
     if entries_to_gather.is_empty() {
         println!("No matching entries found to gather.");
@@ -48,45 +50,138 @@ pub fn gather_files(c: &Context) -> Result<(), PollenError> {
     println!("Gathering {} entries...", entries_to_gather.len());
     
     let dirs = PollenDirs::new()?;
+    let variables = template_variables(&dirs, &config, &facts)?;
+    let staging_dir = render_staging_dir(&dirs);
+    let overlays = overlay_names(&dirs, &facts, active_profile(c, &dirs)?.as_deref());
+    let scan_mode = ScanMode::from_config(&config)?;
+    let allowlist = Allowlist::from_config(&dirs, &config)?;
     let mut gathered_count = 0;
     let mut backed_up_count = 0;
+    let mut drifted_count = 0;
     let mut failed_count = 0;
     let mut operation_entries = Vec::new();
     
+    let context = GatherContext {
+        dirs: &dirs,
+        facts: &facts,
+        variables: &variables,
+        staging_dir: &staging_dir,
+        scan: scan_mode == ScanMode::Gather,
+        allowlist: &allowlist,
+        verbose,
+    };
+    
     for entry in &entries_to_gather {
-        match gather_single_entry(entry, &dirs) {
-            Ok((gathered, backed_up, backup_path)) => {
-                if gathered {
+        // Write back into the alternate scatter deploys on this machine
+        let stored = select_stored_copy(&dirs, entry, &facts);
+        let layers = entry_layers(&dirs, entry, &overlays);
+        
+        // Hooks run around everything gather does, so before hooks see the system untouched, and
+        // see the file on the system as the source, not the staged copies made from it
+        let outcome = hook_target(entry, &stored, &layers, &dirs, &facts).and_then(|target| {
+            let hook_context = HookContext {
+                entry,
+                operation: HookOperation::Gather,
+                source: &entry.path,
+                target: &target,
+                files_dir: &dirs.files_dir,
+            };
+            run_before_hooks(&hook_context)?;
+            let outcome = gather_entry(entry, &stored, &layers, &context)?;
+            run_after_hooks(&hook_context)?;
+            Ok(outcome)
+        });
+        
+        match outcome {
+            Ok(Outcome::Template(drift)) if drift.is_empty() => {
+                println!("✓ Template up to date: {}{}", entry.get_display_name(), pulled_in_note(entry, &pulled_in));
+            }
+            Ok(Outcome::Template(drift)) => {
+                drifted_count += 1;
+                println!("• Template drift: {} (edit the template, then scatter)", entry.get_display_name());
+                for path in drift {
+                    if path.is_empty() {
+                        println!("  → {} differs from the rendered template", entry.path.display());
+                    } else {
+                        println!("  → {} differs from the rendered template", entry.path.join(path).display());
+                    }
+                }
+            }
+            Ok(Outcome::Fragments(updated)) if updated.is_empty() => {
+                println!("✓ Fragments up to date: {}", entry.get_display_name());
+            }
+            Ok(Outcome::Fragments(updated)) => {
+                gathered_count += 1;
+                println!("🌻 Gathered: {} (into fragments){}", entry.get_display_name(), pulled_in_note(entry, &pulled_in));
+                for fragment in &updated {
+                    let name = fragment.target.strip_prefix(&dirs.files_dir).unwrap_or(&fragment.target);
+                    println!("  → Updated fragment {}", name.display());
+                }
+                if updated.iter().any(|fragment| fragment.backup_path.is_some()) {
+                    backed_up_count += 1;
+                    println!("  → Backed up existing fragments");
+                }
+                
+                // Record the operation, one entry for each fragment written
+                operation_entries.extend(updated.into_iter().map(|fragment| OperationEntry {
+                    entry_name: entry.name.clone(),
+                    source_path: fragment.source.display().to_string(),
+                    target_path: fragment.target.display().to_string(),
+                    backup_path: fragment.backup_path,
+                    managed_block: false,
+                }));
+            }
+            Ok(Outcome::Stored(written)) => {
+                if !written.is_empty() {
                     gathered_count += 1;
-                    println!("🌻 Gathered: {}", entry.get_display_name());
-                    
-                    // Record the operation
-                    let target_filename = entry.name.replace('/', "_").replace('\\', "_");
-                    let target_path = dirs.get_files_path(&target_filename);
-                    operation_entries.push(OperationEntry {
-                        entry_name: entry.name.clone(),
-                        source_path: entry.path.display().to_string(),
-                        target_path: target_path.display().to_string(),
-                        backup_path,
-                    });
+                    let note = pulled_in_note(entry, &pulled_in);
+                    match &stored.alternate {
+                        Some(alternate) => println!("🌻 Gathered: {} (into ##{}){}", entry.get_display_name(), alternate, note),
+                        None => println!("🌻 Gathered: {}{}", entry.get_display_name(), note),
+                    }
                 }
-                if backed_up {
+                if written.iter().any(|gathered| gathered.backup_path.is_some()) {
                     backed_up_count += 1;
-                    println!("  → Backed up existing file to cache");
+                    println!("  → Backed up existing file");
                 }
+                
+                // Record the operation, one entry for each path written in the files directory
+                operation_entries.extend(written.into_iter().map(|gathered| OperationEntry {
+                    entry_name: entry.name.clone(),
+                    source_path: gathered.source.display().to_string(),
+                    target_path: gathered.target.display().to_string(),
+                    backup_path: gathered.backup_path,
+                    managed_block: false,
+                }));
+            }
+            Err(e) if entry.template => {
+                failed_count += 1;
+                eprintln!("✗ Failed to check template {}: {}", entry.get_display_name(), e);
             }
             Err(e) => {
                 failed_count += 1;
-                eprintln!("✗ Failed to gather {}: {}", entry.get_display_name(), e);
+                eprintln!("✗ Failed to gather {}{}: {}", entry.get_display_name(), pulled_in_note(entry, &pulled_in), e);
             }
         }
     }
     
+    let _ = fs::remove_dir_all(&staging_dir);
+    
     println!("\nGather complete:");
     println!("  Gathered: {}", gathered_count);
     if backed_up_count > 0 {
         println!("  Backed up: {}", backed_up_count);
     }
+    if drifted_count > 0 {
+        println!("  Template drift: {}", drifted_count);
+    }
+    let pulled_in_count = entries_to_gather.iter().filter(|entry| pulled_in.contains_key(&entry.name)).count();
+    if pulled_in_count > 0 {
+        println!("  Pulled in: {}", pulled_in_count);
+    }
+    if !skipped.is_empty() {
+        println!("  Skipped: {}", skipped.len());
+    }
     if failed_count > 0 {
         println!("  Failed: {}", failed_count);
     }
@@ -94,6 +189,7 @@ pub fn gather_files(c: &Context) -> Result<(), PollenError> {
     // Save the operation to history if any entries were gathered
     if !operation_entries.is_empty() {
         let operation = Operation {
+            id: String::new(),
             operation_type: OperationType::Gather,
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -122,80 +218,349 @@ pub fn gather_files(c: &Context) -> Result<(), PollenError> {
     Ok(())
 }
 
-fn gather_single_entry(entry: &Entry, dirs: &PollenDirs) -> Result<(bool, bool, Option<String>), PollenError> {
-    let source_path = &entry.path;
-    let target_filename = entry.name.replace('/', "_").replace('\\', "_");
-    let target_path = dirs.get_files_path(&target_filename);
+/// What the gather of every entry shares
+struct GatherContext<'a> {
+    dirs: &'a PollenDirs,
+    facts: &'a HostFacts,
+    variables: &'a Mapping,
+    staging_dir: &'a Path,
+    /// Whether findings of secret scanning keep an entry out of the files directory
+    scan: bool,
+    allowlist: &'a Allowlist,
+    verbose: bool,
+}
+
+/// What gathering an entry came to
+enum Outcome {
+    /// A template, with the paths where the system differs from its rendered output
+    Template(Vec<String>),
+    /// An assembled entry, with the fragments that changed
+    Fragments(Vec<Gathered>),
+    /// Any other entry, with the paths written in the files directory
+    Stored(Vec<Gathered>),
+}
+
+/// Where hooks see an entry gathered to: the fragments of an assembled entry separated like PATH,
+/// the overlay that replaces a file entry, or else the stored copy
+fn hook_target(entry: &Entry, stored: &StoredCopy, layers: &[Layer], dirs: &PollenDirs, facts: &HostFacts) -> Result<PathBuf, PollenError> {
+    if let Some(assembly) = &entry.assemble {
+        let fragments = assembly.active_fragments(facts).into_iter().map(|fragment| dirs.files_dir.join(&fragment.file));
+        return std::env::join_paths(fragments)
+            .map(PathBuf::from)
+            .map_err(|e| PollenError::InvalidEndpoint(format!("Cannot pass fragments to hooks: {}", e)));
+    }
     
-    // Check if source exists
-    if !source_path.exists() {
+    if !layers.is_empty() && entry.path.is_file() {
+        let provider = file_layers(&stored.path, layers).get(Path::new("")).cloned().flatten();
+        if let Some(layer) = layers.iter().find(|layer| Some(&layer.name) == provider.as_ref()) {
+            return Ok(layer.path.clone());
+        }
+    }
+    Ok(stored.path.clone())
+}
+
+/// Stage what is on the system the way it is stored, then write it into the files directory
+fn gather_entry(entry: &Entry, stored: &StoredCopy, layers: &[Layer], context: &GatherContext) -> Result<Outcome, PollenError> {
+    let GatherContext { dirs, facts, variables, staging_dir, .. } = *context;
+    
+    // Only the managed block, or the keys pollen manages, are gathered, never the whole file
+    let system_path = if entry.managed_block {
+        stage_block(entry, staging_dir)?.ok_or_else(|| PollenError::InvalidEndpoint(format!(
+            "no pollen:{} block in {}", entry.name, entry.path.display()
+        )))?
+    } else if let Some(merge) = &entry.merge {
+        stage_captured(entry, merge, &stored.path, staging_dir)?.unwrap_or_else(|| entry.path.clone())
+    } else {
+        entry.path.clone()
+    };
+    
+    // Clean filters turn what is on the system into what is stored
+    let system_path = filter_entry(entry, &system_path, FilterDirection::Clean, dirs, staging_dir)?.unwrap_or(system_path);
+    
+    // Templates are never overwritten by their rendered output, only checked for drift
+    if entry.template {
+        let rendered_path = render_template(entry, stored, layers, dirs, facts, variables, staging_dir)?;
+        let concealed_path = conceal_secrets(entry, &system_path, Some(&rendered_path), dirs, staging_dir)?;
+        return template_drift(concealed_path.as_deref().unwrap_or(&system_path), &rendered_path).map(Outcome::Template);
+    }
+    
+    // Secret values go back to their placeholders before anything reaches the files directory
+    let reference_path = secrets_reference(entry, stored, layers, dirs, facts, staging_dir)?;
+    let system_path = conceal_secrets(entry, &system_path, reference_path.as_deref(), dirs, staging_dir)?.unwrap_or(system_path);
+    
+    // With secret_scan: gather, nothing that looks like a secret reaches the files directory
+    if context.scan && !entry.encrypt {
+        let name = stored.path.strip_prefix(&dirs.files_dir).unwrap_or(&stored.path).display().to_string();
+        let findings = scan_path(&system_path, &name, context.allowlist);
+        if !findings.is_empty() {
+            return Err(PollenError::SecretsFound(report(&findings, context.allowlist)));
+        }
+    }
+    
+    // Assembled entries are split back into their fragments
+    if let Some(assembly) = &entry.assemble {
+        return gather_fragments(entry, assembly, &system_path, dirs, facts, staging_dir, context.verbose).map(Outcome::Fragments);
+    }
+    
+    // Only ciphertext reaches the files directory
+    let system_path = if entry.encrypt && system_path.exists() {
+        encrypt_entry(entry, &system_path, &stored.path, dirs, staging_dir)?
+    } else {
+        system_path
+    };
+    
+    if layers.is_empty() {
+        gather_single_entry(entry, &system_path, stored, dirs, context.verbose).map(Outcome::Stored)
+    } else {
+        gather_layered_entry(entry, &system_path, stored, layers, dirs, staging_dir, context.verbose).map(Outcome::Stored)
+    }
+}
+
+/// Split an assembled file on the system back into its fragments, returning the fragments that changed
+fn gather_fragments(
+    entry: &Entry,
+    assembly: &Assembly,
+    system_path: &Path,
+    dirs: &PollenDirs,
+    facts: &HostFacts,
+    staging_dir: &Path,
+    verbose: bool,
+) -> Result<Vec<Gathered>, PollenError> {
+    let content = fs::read_to_string(system_path).map_err(|e| PollenError::Io(std::io::Error::new(
+        e.kind(),
+        format!("Cannot read {}: {}", entry.path.display(), e)
+    )))?;
+    
+    let parts = assembly.split(&content, facts).map_err(|reason| PollenError::InvalidEndpoint(format!(
+        "Cannot map {} back to its fragments: {}. Edit the fragments in {} instead",
+        entry.path.display(), reason, dirs.files_dir.display()
+    )))?;
+    
+    // Markers always end a fragment with a newline, which the fragment itself may lack
+    let changed: Vec<(PathBuf, String)> = parts
+        .into_iter()
+        .map(|(file, content)| (dirs.files_dir.join(file), content))
+        .filter(|(path, content)| {
+            let current = fs::read_to_string(path).ok();
+            !current.is_some_and(|current| current == *content || format!("{}\n", current) == *content)
+        })
+        .collect();
+    if changed.is_empty() {
+        return Ok(Vec::new());
+    }
+    
+    let mut updated = Vec::new();
+    for (path, content) in changed {
+        let file = path.strip_prefix(&dirs.files_dir).unwrap_or(&path);
+        let staged_path = staging_dir.join("fragments").join(file);
+        if let Some(parent) = staged_path.parent() {
+            fs::create_dir_all(parent).map_err(PollenError::Io)?;
+        }
+        fs::write(&staged_path, content).map_err(PollenError::Io)?;
+        updated.push(store_gathered(&entry.path, Some(&staged_path), &path, dirs, verbose)?);
+    }
+    
+    Ok(updated)
+}
+
+/// A path gather wrote in the files directory, with the backup of what was there before
+struct Gathered {
+    /// Where the content came from on the system
+    source: PathBuf,
+    /// The stored copy, overlay or overlay file that was written or removed
+    target: PathBuf,
+    backup_path: Option<String>,
+}
+
+/// Gather an entry with overlays: files an overlay provides go back into that overlay, everything
+/// else into the stored copy, which keeps its own version of files an overlay replaces
+fn gather_layered_entry(
+    entry: &Entry,
+    system_path: &Path,
+    stored: &StoredCopy,
+    layers: &[Layer],
+    dirs: &PollenDirs,
+    staging_dir: &Path,
+    verbose: bool,
+) -> Result<Vec<Gathered>, PollenError> {
+    if !system_path.exists() {
         return Err(PollenError::Io(std::io::Error::new(
             std::io::ErrorKind::NotFound,
-            format!("Source file does not exist: {}", source_path.display())
+            format!("Source file does not exist: {}", system_path.display())
         )));
     }
     
-    // Execute run_before command if specified
-    if let Some(run_before) = &entry.run_before {
-        println!("  → Running pre-gather command: {}", run_before);
-        if let Err(e) = execute_shell_command(run_before) {
-            return Err(PollenError::InvalidEndpoint(
-                format!("Failed to execute run_before command '{}': {}", run_before, e)
-            ));
+    let sources = file_layers(&stored.path, layers);
+    
+    // A file entry replaced by an overlay is gathered into that overlay as a whole
+    if system_path.is_file() {
+        let provider = sources.get(Path::new("")).cloned().flatten();
+        if let Some(layer) = layers.iter().find(|layer| Some(&layer.name) == provider.as_ref()) {
+            println!("  → Into overlay {}", layer.name);
+            let overlay = StoredCopy { path: layer.path.clone(), alternate: None };
+            return gather_single_entry(entry, system_path, &overlay, dirs, verbose);
         }
+        return gather_single_entry(entry, system_path, stored, dirs, verbose);
     }
     
-    let mut backed_up = false;
+    let split_path = staging_dir.join(OVERLAYS_DIR).join(stored_name(entry));
+    if split_path.exists() {
+        fs::remove_dir_all(&split_path).map_err(PollenError::Io)?;
+    }
+    copy_path(system_path, &split_path)?;
+    
+    let mut written = Vec::new();
+    let system_files = relative_files(system_path);
+    for layer in layers {
+        let mut routed = 0;
+        for (path, _) in sources.iter().filter(|(_, source)| source.as_deref() == Some(layer.name.as_str())) {
+            let overlay_file = layer.path.join(path);
+            let source = entry.path.join(path);
+            if system_files.contains(path) {
+                if !differing_paths(&overlay_file, &system_path.join(path)).is_empty() {
+                    written.push(store_gathered(&source, Some(&system_path.join(path)), &overlay_file, dirs, verbose)?);
+                    routed += 1;
+                }
+            } else if overlay_file.symlink_metadata().is_ok() {
+                // Removed on the system, so it goes from the overlay as well
+                written.push(store_gathered(&source, None, &overlay_file, dirs, verbose)?);
+                routed += 1;
+            }
+            
+            // The stored copy keeps its own version, or does not get the file at all
+            let split_file = split_path.join(path);
+            let base_file = stored.path.join(path);
+            if base_file.exists() {
+                copy_path(&base_file, &split_file)?;
+            } else if split_file.exists() {
+                fs::remove_file(&split_file).map_err(PollenError::Io)?;
+            }
+        }
+        if routed > 0 {
+            println!("  → {} files into overlay {}", routed, layer.name);
+        }
+    }
+    
+    written.push(store_gathered(&entry.path, Some(&split_path), &stored.path, dirs, verbose)?);
+    
+    Ok(written)
+}
+
+fn gather_single_entry(entry: &Entry, source_path: &Path, stored: &StoredCopy, dirs: &PollenDirs, verbose: bool) -> Result<Vec<Gathered>, PollenError> {
+    // Check if source exists
+    if !source_path.exists() {
+        return Err(PollenError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Source file does not exist: {}", source_path.display())
+        )));
+    }
+    
+    Ok(vec![store_gathered(&entry.path, Some(source_path), &stored.path, dirs, verbose)?])
+}
+
+/// Replace a path in the files directory with `source_path`, or remove it when there is no
+/// source, backing up what was there first so undo can put it back
+fn store_gathered(system_path: &Path, source_path: Option<&Path>, target_path: &Path, dirs: &PollenDirs, verbose: bool) -> Result<Gathered, PollenError> {
     let mut backup_path_str = None;
     
-    // If target already exists, back it up to cache
-    if target_path.exists() {
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map_err(|_| PollenError::Io(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to get system time"
-            )))?
-            .as_secs();
-        
-        let backup_filename = format!("{}_{}.backup", target_filename, timestamp);
-        let backup_path = dirs.get_cache_file_path(&backup_filename);
+    // If target already exists, back it up
+    if target_path.symlink_metadata().is_ok() {
+        let target_filename = target_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let backup_path = dirs.new_backup_file_path(&target_filename)?;
         
-        // Ensure cache directory exists
-        if let Some(cache_parent) = backup_path.parent() {
-            fs::create_dir_all(cache_parent)
+        // Ensure backup directory exists
+        if let Some(backup_parent) = backup_path.parent() {
+            fs::create_dir_all(backup_parent)
                 .map_err(PollenError::Io)?;
         }
         
-        fs::copy(&target_path, &backup_path)
-            .map_err(PollenError::Io)?;
-        backed_up = true;
+        copy_path(target_path, &backup_path)?.print_verbose(verbose, "Backed up");
         backup_path_str = Some(backup_path.display().to_string());
     }
     
-    // Ensure target directory exists
-    if let Some(target_parent) = target_path.parent() {
-        fs::create_dir_all(target_parent)
-            .map_err(PollenError::Io)?;
+    match source_path {
+        Some(source_path) => {
+            // Ensure target directory exists
+            if let Some(target_parent) = target_path.parent() {
+                fs::create_dir_all(target_parent)
+                    .map_err(PollenError::Io)?;
+            }
+            
+            // Copy source to target
+            copy_path(source_path, target_path)?.print_verbose(verbose, "Copied");
+        }
+        None => remove_path(target_path)?,
     }
     
-    // Copy source to target
-    if source_path.is_dir() {
-        copy_dir_all(source_path, &target_path)?;
+    Ok(Gathered {
+        source: system_path.to_path_buf(),
+        target: target_path.to_path_buf(),
+        backup_path: backup_path_str,
+    })
+}
+
+/// Render a templated entry the way scatter would, returning the rendered path
+fn render_template(
+    entry: &Entry,
+    stored: &StoredCopy,
+    layers: &[Layer],
+    dirs: &PollenDirs,
+    facts: &HostFacts,
+    variables: &Mapping,
+    staging_dir: &Path,
+) -> Result<PathBuf, PollenError> {
+    let layered_path = if let Some(assembly) = &entry.assemble {
+        assemble_entry(entry, assembly, &dirs.files_dir, facts, staging_dir)?
+    } else if layers.is_empty() {
+        stored.path.clone()
     } else {
-        fs::copy(source_path, &target_path)
-            .map_err(PollenError::Io)?;
-    }
+        layer_entry(entry, &stored.path, layers, staging_dir)?
+    };
+    let layered_path = if entry.encrypt {
+        decrypt_entry(entry, &layered_path, dirs, staging_dir)?
+    } else {
+        layered_path
+    };
+    render_entry(entry, &layered_path, variables, staging_dir)
+}
+
+/// What scatter deploys for an entry before resolving its secrets, whose placeholders gather puts
+/// back in place of their values; None when nothing is stored yet
+fn secrets_reference(
+    entry: &Entry,
+    stored: &StoredCopy,
+    layers: &[Layer],
+    dirs: &PollenDirs,
+    facts: &HostFacts,
+    staging_dir: &Path,
+) -> Result<Option<PathBuf>, PollenError> {
+    let reference_path = if let Some(assembly) = &entry.assemble {
+        assemble_entry(entry, assembly, &dirs.files_dir, facts, staging_dir)?
+    } else if !stored.path.exists() {
+        return Ok(None);
+    } else if layers.is_empty() {
+        stored.path.clone()
+    } else {
+        layer_entry(entry, &stored.path, layers, staging_dir)?
+    };
     
-    // Execute run_after command if specified
-    if let Some(run_after) = &entry.run_after {
-        println!("  → Running post-gather command: {}", run_after);
-        if let Err(e) = execute_shell_command(run_after) {
-            return Err(PollenError::InvalidEndpoint(
-                format!("Failed to execute run_after command '{}': {}", run_after, e)
-            ));
-        }
+    if entry.encrypt {
+        return decrypt_entry(entry, &reference_path, dirs, staging_dir).map(Some);
+    }
+    Ok(Some(reference_path))
+}
+
+/// Compare a rendered template with what is on the system, returning the paths that differ
+fn template_drift(system_path: &Path, rendered_path: &Path) -> Result<Vec<String>, PollenError> {
+    if !system_path.exists() {
+        return Err(PollenError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Source file does not exist: {}", system_path.display())
+        )));
     }
     
-    Ok((true, backed_up, backup_path_str))
+    Ok(differing_paths(rendered_path, system_path))
 }
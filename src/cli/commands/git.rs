@@ -1,10 +1,34 @@
-use crate::{PollenDirs, PollenError};
+use crate::{ConfigParser, PollenDirs, PollenLock, PollenError};
+use crate::alternate::{stored_name, ALTERNATE_SEPARATOR};
+use crate::block;
+use crate::cli::utils::track_files;
+use crate::overlay::OVERLAYS_DIR;
 use seahorse::Context;
+use std::{fs, process::Command};
+
+/// Name of the git diff driver that shows encrypted stored copies in plaintext
+const DIFF_DRIVER: &str = "pollen-encrypted";
+
+/// Name of the block pollen manages in the files directory's .gitattributes
+const ATTRIBUTES_BLOCK: &str = "encrypted";
 
 pub fn handle_git_command(c: &Context) -> Result<(), PollenError> {
     let dirs = PollenDirs::new()?;
     
-    let subcommand = c.args.get(0).map(|s| s.as_str()).unwrap_or("status");
+    let subcommand = c.args.first().map(|s| s.as_str()).unwrap_or("status");
+    
+    // Everything except read-only subcommands may touch the files directory
+    let lock = match subcommand {
+        "status" | "help" | "--help" | "-h" => None,
+        _ => Some(PollenLock::acquire(&dirs, c.bool_flag("wait"))?),
+    };
+    
+    // Keep `git diff` showing encrypted entries in plaintext on this machine
+    if lock.is_some() && subcommand != "init" && dirs.is_files_git_repo() {
+        if let Err(e) = install_diff_driver(&dirs) {
+            eprintln!("Warning: Failed to set up the diff driver for encrypted entries: {}", e);
+        }
+    }
     
     match subcommand {
         "init" => {
@@ -13,6 +37,11 @@ pub fn handle_git_command(c: &Context) -> Result<(), PollenError> {
                 true => {
                     println!("✓ Git repository initialized in {}", dirs.files_dir.display());
                     println!("✓ Created .gitignore file");
+                    match install_diff_driver(&dirs) {
+                        Ok(0) => {}
+                        Ok(_) => println!("✓ Set up plaintext diffs for encrypted entries"),
+                        Err(e) => eprintln!("Warning: Failed to set up the diff driver for encrypted entries: {}", e),
+                    }
                     println!("\nYou can now:");
                     println!("  cd {}", dirs.files_dir.display());
                     println!("  git remote add origin <your-repo-url>");
@@ -35,7 +64,7 @@ pub fn handle_git_command(c: &Context) -> Result<(), PollenError> {
             
             println!("Git status for files directory:");
             let output = std::process::Command::new("git")
-                .args(&["status", "--short"])
+                .args(["status", "--short"])
                 .current_dir(&dirs.files_dir)
                 .output()
                 .map_err(PollenError::Io)?;
@@ -131,3 +160,54 @@ pub fn handle_git_command(c: &Context) -> Result<(), PollenError> {
         }
     }
 }
+
+/// Point git at `pollen decrypt` for the stored copies of encrypted entries, returning how many there are.
+///
+/// The driver lives in the repository's local config, so other clones only ever see ciphertext.
+fn install_diff_driver(dirs: &PollenDirs) -> Result<usize, PollenError> {
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+    let entries = parser.parse_files(&track_files(dirs, &config)?)?;
+
+    // Alternates and overlays of an entry are encrypted just like its stored copy
+    let mut patterns = String::new();
+    let mut count = 0;
+    for entry in entries.iter().filter(|entry| entry.encrypt) {
+        let name = stored_name(entry);
+        for pattern in [
+            format!("/{}", name),
+            format!("/{}{}*", name, ALTERNATE_SEPARATOR),
+            format!("/{}/*/{}", OVERLAYS_DIR, name),
+        ] {
+            patterns.push_str(&format!("{} diff={}\n", pattern, DIFF_DRIVER));
+        }
+        count += 1;
+    }
+
+    let attributes_path = dirs.files_dir.join(".gitattributes");
+    let attributes = fs::read_to_string(&attributes_path).unwrap_or_default();
+    let block_error = |reason: String| PollenError::InvalidEndpoint(format!("{}: {}", attributes_path.display(), reason));
+    let updated = if count == 0 {
+        block::remove(&attributes, ATTRIBUTES_BLOCK).map_err(block_error)?
+    } else {
+        block::replace(&attributes, ATTRIBUTES_BLOCK, &patterns).map_err(block_error)?
+    };
+    if updated != attributes {
+        fs::write(&attributes_path, updated).map_err(PollenError::Io)?;
+    }
+
+    if count > 0 {
+        let executable = std::env::current_exe().map_err(PollenError::Io)?;
+        let status = Command::new("git")
+            .args(["config", &format!("diff.{}.textconv", DIFF_DRIVER)])
+            .arg(format!("'{}' decrypt", executable.display()))
+            .current_dir(&dirs.files_dir)
+            .status()
+            .map_err(PollenError::Io)?;
+        if !status.success() {
+            return Err(PollenError::InvalidEndpoint("git config failed".to_string()));
+        }
+    }
+
+    Ok(count)
+}
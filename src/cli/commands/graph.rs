@@ -0,0 +1,145 @@
+use crate::{ConfigParser, PollenDirs, Entry, PollenError};
+use crate::cli::selection::resolve_selectors;
+use crate::profile::add_dependencies;
+use crate::cli::utils::track_files;
+use seahorse::Context;
+
+pub fn show_graph(c: &Context) -> Result<(), PollenError> {
+    let format = c.string_flag("format").unwrap_or_else(|_| "text".to_string());
+    let target_entries: Vec<String> = c.args.clone();
+
+    let dirs = PollenDirs::new()?;
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+
+    let config_paths = track_files(&dirs, &config)?;
+
+    // Entries come back in dependency order, which keeps the output stable
+    let entries = parser.parse_files(&config_paths)?;
+
+    // Selected entries are shown together with everything they depend on
+    let entries: Vec<Entry> = if target_entries.is_empty() {
+        entries
+    } else {
+        let mut selected = resolve_selectors(&target_entries, &entries, parser.home_dir())?;
+        add_dependencies(&mut selected, &entries);
+        entries.into_iter().filter(|entry| selected.contains(&entry.name)).collect()
+    };
+
+    let edges = dependency_edges(&entries);
+    let output = match format.as_str() {
+        "text" => to_text(&entries, &edges),
+        "dot" => to_dot(&entries, &edges),
+        "mermaid" => to_mermaid(&entries, &edges),
+        other => {
+            return Err(PollenError::InvalidOption(format!(
+                "Unknown graph format '{}' (expected text, dot or mermaid)", other
+            )));
+        }
+    };
+
+    print!("{}", output);
+    Ok(())
+}
+
+/// How one entry relates to an entry it comes after
+#[derive(Clone, Copy, PartialEq)]
+enum Relation {
+    DependsOn,
+    Optional,
+    After,
+}
+
+impl Relation {
+    fn note(self) -> &'static str {
+        match self {
+            Relation::DependsOn => "",
+            Relation::Optional => "optional",
+            Relation::After => "after",
+        }
+    }
+}
+
+/// (entry, dependency, relation) triples by position, with aliases resolved.
+/// Optional dependencies and `after` that name no entry are left out.
+fn dependency_edges(entries: &[Entry]) -> Vec<(usize, usize, Relation)> {
+    let mut edges = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let relations = entry
+            .depends_on
+            .iter()
+            .map(|name| (name, Relation::DependsOn))
+            .chain(entry.optional_depends_on.iter().map(|name| (name, Relation::Optional)))
+            .chain(entry.after.iter().map(|name| (name, Relation::After)));
+
+        for (name, relation) in relations {
+            if let Some(target) = entries.iter().position(|other| other.matches_name(name)) {
+                if !edges.iter().any(|&(from, to, _)| from == index && to == target) {
+                    edges.push((index, target, relation));
+                }
+            }
+        }
+    }
+    edges
+}
+
+fn label(entry: &Entry) -> String {
+    match &entry.alias_as {
+        Some(alias) => format!("{} ({})", entry.name, alias),
+        None => entry.name.clone(),
+    }
+}
+
+fn to_text(entries: &[Entry], edges: &[(usize, usize, Relation)]) -> String {
+    let mut output = String::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let dependencies: Vec<String> = edges
+            .iter()
+            .filter(|(from, _, _)| *from == index)
+            .map(|(_, to, relation)| match relation {
+                Relation::DependsOn => entries[*to].name.clone(),
+                other => format!("{} ({})", entries[*to].name, other.note()),
+            })
+            .collect();
+
+        if dependencies.is_empty() {
+            output.push_str(&format!("{}\n", label(entry)));
+        } else {
+            output.push_str(&format!("{} → {}\n", label(entry), dependencies.join(", ")));
+        }
+    }
+    output
+}
+
+fn to_dot(entries: &[Entry], edges: &[(usize, usize, Relation)]) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+
+    let mut output = String::from("digraph pollen {\n    rankdir=LR;\n");
+    for entry in entries {
+        output.push_str(&format!("    {} [label={}];\n", quote(&entry.name), quote(&label(entry))));
+    }
+    for (from, to, relation) in edges {
+        let style = match relation {
+            Relation::DependsOn => String::new(),
+            other => format!(" [style=dashed, label={}]", quote(other.note())),
+        };
+        output.push_str(&format!("    {} -> {}{};\n", quote(&entries[*from].name), quote(&entries[*to].name), style));
+    }
+    output.push_str("}\n");
+    output
+}
+
+fn to_mermaid(entries: &[Entry], edges: &[(usize, usize, Relation)]) -> String {
+    // Entry names contain dots and slashes, so nodes get generated ids and the name as label
+    let mut output = String::from("graph LR\n");
+    for (index, entry) in entries.iter().enumerate() {
+        output.push_str(&format!("    n{}[\"{}\"]\n", index, label(entry).replace('"', "#quot;")));
+    }
+    for (from, to, relation) in edges {
+        match relation {
+            Relation::DependsOn => output.push_str(&format!("    n{} --> n{}\n", from, to)),
+            other => output.push_str(&format!("    n{} -.->|{}| n{}\n", from, other.note(), to)),
+        }
+    }
+    output
+}
@@ -14,6 +14,8 @@ pub fn init_pollen() -> Result<(), PollenError> {
     }
     
     println!("Configuration directory: {}", dirs.config_dir.display());
+    println!("State directory: {}", dirs.state_dir.display());
+    println!("Data directory: {}", dirs.data_dir.display());
     println!("Cache directory: {}", dirs.cache_dir.display());
     println!("Files directory: {}", dirs.files_dir.display());
     
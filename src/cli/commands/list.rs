@@ -1,41 +1,47 @@
-use crate::{ConfigParser, PollenDirs, PollenError};
+use crate::{ConfigParser, Entry, HostFacts, PollenDirs, PollenError};
+use crate::alternate::select_stored_copy;
+use crate::cli::selection::filter_by_tags;
+use crate::cli::utils::track_files;
+use std::collections::BTreeMap;
 use seahorse::Context;
 
 pub fn list_entries(c: &Context) -> Result<(), PollenError> {
-    let config_file = c.string_flag("config").ok();
     let show_paths = c.bool_flag("paths");
     
-    let parser = ConfigParser::new()?;
     let dirs = PollenDirs::new()?;
     let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
     
-    let (config_path, _used_config_file) = match config_file.as_deref() {
-        Some(path) => (path.to_string(), path.to_string()),
-        None => {
-            let track_file = dirs.get_track_file_path(&config);
-            let path_str = track_file.to_str().ok_or(PollenError::InvalidEndpoint("Invalid config file path".into()))?;
-            (path_str.to_string(), track_file.display().to_string())
-        }
-    };
+    let config_paths = track_files(&dirs, &config)?;
+
+    let entries = filter_by_tags(c, parser.parse_files(&config_paths)?)?;
+    let facts = HostFacts::detect();
 
-    let entries = parser.parse_file(&config_path)?;
+    if c.bool_flag("by-tag") {
+        print_by_tag(&entries, show_paths, &facts);
+        return Ok(());
+    }
 
     if show_paths {
         for entry in &entries {
             let display_name = entry.get_display_name();
+            let alternate = select_stored_copy(&dirs, entry, &facts)
+                .alternate
+                .map(|alternate| format!(" ##{}", alternate))
+                .unwrap_or_default();
             if display_name != entry.name {
-                println!("{} ({})", entry.path.display(), display_name);
+                println!("{}{} ({}){}", entry.path.display(), alternate, display_name, skipped_note(entry, &facts));
             } else {
-                println!("{}", entry.path.display());
+                println!("{}{}{}", entry.path.display(), alternate, skipped_note(entry, &facts));
             }
         }
     } else {
         for entry in &entries {
             let display_name = entry.get_display_name();
             if display_name != entry.name {
-                println!("{} ({})", display_name, entry.name);
+                println!("{} ({}){}", display_name, entry.name, skipped_note(entry, &facts));
             } else {
-                println!("{}", entry.name);
+                println!("{}{}", entry.name, skipped_note(entry, &facts));
             }
         }
     }
@@ -47,7 +53,7 @@ pub fn list_entries(c: &Context) -> Result<(), PollenError> {
             println!("Aliases (alias -> path):");
             for (alias, _path) in &aliases {
                 // Find the entry with this alias to get its path
-                if let Some(entry) = entries.iter().find(|e| e.alias_as.as_ref().map(|a| a.as_str()) == Some(*alias)) {
+                if let Some(entry) = entries.iter().find(|e| e.alias_as.as_deref() == Some(*alias)) {
                     println!("  {} -> {}", alias, entry.path.display());
                 }
             }
@@ -61,3 +67,38 @@ pub fn list_entries(c: &Context) -> Result<(), PollenError> {
     
     Ok(())
 }
+
+/// Note appended to entries whose `when` condition does not hold on this machine
+fn skipped_note(entry: &Entry, facts: &HostFacts) -> String {
+    match &entry.when {
+        Some(condition) if !entry.is_enabled(facts) => format!("  [skipped: {}]", condition),
+        _ => String::new(),
+    }
+}
+
+/// Print entries grouped under each of their tags, untagged entries last
+fn print_by_tag(entries: &[Entry], show_paths: bool, facts: &HostFacts) {
+    let mut groups: BTreeMap<&str, Vec<&Entry>> = BTreeMap::new();
+    let mut untagged = Vec::new();
+
+    for entry in entries {
+        if entry.tags.is_empty() {
+            untagged.push(entry);
+        }
+        for tag in &entry.tags {
+            groups.entry(tag).or_default().push(entry);
+        }
+    }
+
+    let untagged_group = if untagged.is_empty() { None } else { Some(("(untagged)", untagged)) };
+    for (index, (tag, group)) in groups.into_iter().chain(untagged_group).enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("{}:", tag);
+        for entry in group {
+            let label = if show_paths { entry.path.display().to_string() } else { entry.get_display_name().to_string() };
+            println!("  {}{}", label, skipped_note(entry, facts));
+        }
+    }
+}
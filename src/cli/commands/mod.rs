@@ -5,6 +5,14 @@ pub mod validate;
 pub mod gather;
 pub mod scatter;
 pub mod undo;
+pub mod restore;
+pub mod render;
+pub mod decrypt;
+pub mod status;
+pub mod diff;
+pub mod profile;
+pub mod graph;
+pub mod trash;
 pub mod git;
 pub mod cd;
 pub mod config;
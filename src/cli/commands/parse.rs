@@ -1,32 +1,21 @@
-use crate::{ConfigParser, PollenError, PollenDirs};
+use crate::{ConfigParser, HostFacts, PollenError, PollenDirs};
+use crate::block;
+use crate::cli::utils::track_files;
 use seahorse::Context;
 
 pub fn parse_config(c: &Context) -> Result<(), PollenError> {
-    let config_file = c.string_flag("config").ok();
     let verbose = c.bool_flag("verbose");
     
-    let parser = ConfigParser::new()?;
     let dirs = PollenDirs::new()?;
     let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
     
-    let (config_path, _used_config_file) = match config_file.as_deref() {
-        Some(path) => (path.to_string(), path.to_string()),
-        None => {
-            let track_file = dirs.get_track_file_path(&config);
-            let path_str = track_file.to_str().ok_or(PollenError::InvalidEndpoint("Invalid config file path".into()))?;
-            (path_str.to_string(), track_file.display().to_string())
-        }
-    };
+    let config_paths = track_files(&dirs, &config)?;
 
-    let entries = parser.parse_file(&config_path)?;
+    let entries = parser.parse_files(&config_paths)?;
     
     if verbose {
-        let used_config_file = if let Some(ref path) = config_file {
-            path.clone()
-        } else {
-            dirs.get_track_file_path(&config).display().to_string()
-        };
-        println!("Parsed {} entries from {}", entries.len(), used_config_file);
+        println!("Parsed {} entries from {}", entries.len(), config_paths.join(", "));
         
         // Show aliases
         let aliases = parser.list_aliases(&entries);
@@ -39,6 +28,7 @@ pub fn parse_config(c: &Context) -> Result<(), PollenError> {
         println!();
     }
     
+    let facts = HostFacts::detect();
     for entry in &entries {
         println!("Entry: {}", entry.name);
         if let Some(alias) = &entry.alias_as {
@@ -46,10 +36,75 @@ pub fn parse_config(c: &Context) -> Result<(), PollenError> {
         }
         println!("  Path: {}", entry.path.display());
         
+        if let Some(source) = &entry.source {
+            println!("  Defined at: {}", source);
+        }
+        
         if !entry.depends_on.is_empty() {
             println!("  Dependencies: {}", entry.depends_on.join(", "));
         }
         
+        if !entry.optional_depends_on.is_empty() {
+            println!("  Optional dependencies: {}", entry.optional_depends_on.join(", "));
+        }
+        
+        if !entry.after.is_empty() {
+            println!("  After: {}", entry.after.join(", "));
+        }
+        
+        if !entry.conflicts_with.is_empty() {
+            println!("  Conflicts with: {}", entry.conflicts_with.join(", "));
+        }
+        
+        if !entry.tags.is_empty() {
+            println!("  Tags: {}", entry.tags.join(", "));
+        }
+        
+        if entry.template {
+            println!("  Template: yes");
+        }
+        
+        if entry.encrypt {
+            println!("  Encrypted: yes");
+        }
+        
+        if let Some(assembly) = &entry.assemble {
+            let fragments: Vec<String> = assembly
+                .fragments
+                .iter()
+                .map(|fragment| match &fragment.when {
+                    Some(condition) if !condition.evaluate(&facts) => format!("{} (skipped: when {})", fragment.file, condition),
+                    Some(condition) => format!("{} (when {})", fragment.file, condition),
+                    None => fragment.file.clone(),
+                })
+                .collect();
+            println!("  Assembled from: {}", fragments.join(", "));
+        }
+        
+        if entry.managed_block {
+            println!("  Managed block: {}", block::begin_marker(&entry.name));
+        }
+        
+        if let Some(merge) = &entry.merge {
+            if merge.ignore_keys.is_empty() {
+                println!("  Merge: {}", merge.format);
+            } else {
+                println!("  Merge: {} (ignoring {})", merge.format, merge.ignore_keys.join(", "));
+            }
+        }
+        
+        if !entry.filters.is_empty() {
+            println!("  Filters: {}", entry.filters.join(", "));
+        }
+        
+        if let Some(condition) = &entry.when {
+            if entry.is_enabled(&facts) {
+                println!("  When: {}", condition);
+            } else {
+                println!("  When: {} (skipped on this machine)", condition);
+            }
+        }
+        
         if let Some(run_before) = &entry.run_before {
             println!("  Run before: {}", run_before);
         }
@@ -58,6 +113,20 @@ pub fn parse_config(c: &Context) -> Result<(), PollenError> {
             println!("  Run after: {}", run_after);
         }
         
+        let direction_hooks = [
+            ("Before scatter", &entry.before_scatter),
+            ("After scatter", &entry.after_scatter),
+            ("Before gather", &entry.before_gather),
+            ("After gather", &entry.after_gather),
+            ("Before undo", &entry.before_undo),
+            ("After undo", &entry.after_undo),
+        ];
+        for (label, command) in direction_hooks {
+            if let Some(command) = command {
+                println!("  {}: {}", label, command);
+            }
+        }
+        
         println!();
     }
     
@@ -0,0 +1,81 @@
+use crate::{ConfigParser, PollenDirs, PollenError, HostFacts};
+use crate::cli::selection::load_profiles;
+use crate::cli::utils::track_files;
+use seahorse::Context;
+
+pub fn handle_profile_command(c: &Context) -> Result<(), PollenError> {
+    let dirs = PollenDirs::new()?;
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+
+    let config_paths = track_files(&dirs, &config)?;
+
+    let profiles = load_profiles(&parser, &config, &config_paths)?;
+    let mut local = dirs.load_local_config()?;
+
+    let subcommand = c.args.first().map(|s| s.as_str()).unwrap_or("list");
+
+    match subcommand {
+        "list" => {
+            if profiles.names().is_empty() {
+                println!("No profiles defined. Add 'profiles:' to pollen.yaml or track.yaml.");
+                return Ok(());
+            }
+
+            println!("Profiles:");
+            for name in profiles.names() {
+                let marker = if local.profile.as_deref() == Some(name) { "*" } else { " " };
+                println!("  {} {}", marker, name);
+            }
+            if local.profile.is_some() {
+                println!("\n* default on this machine");
+            }
+            Ok(())
+        }
+
+        "show" => {
+            let name = c.args.get(1).or(local.profile.as_ref()).ok_or_else(|| {
+                PollenError::InvalidOption("Usage: pollen profile show <NAME>".to_string())
+            })?;
+
+            let entries = profiles.select(name, parser.parse_files(&config_paths)?)?;
+            let facts = HostFacts::detect();
+
+            println!("Profile '{}' ({} entries):", name, entries.len());
+            for entry in &entries {
+                if entry.is_enabled(&facts) {
+                    println!("  {}", entry.get_display_name());
+                } else {
+                    println!("  {}  [skipped on this machine]", entry.get_display_name());
+                }
+            }
+            Ok(())
+        }
+
+        "use" => {
+            let name = c.args.get(1).ok_or_else(|| {
+                PollenError::InvalidOption("Usage: pollen profile use <NAME>".to_string())
+            })?;
+
+            // Resolve it once so a broken profile is reported now rather than on the next scatter
+            profiles.select(name, parser.parse_files(&config_paths)?)?;
+
+            local.profile = Some(name.clone());
+            dirs.save_local_config(&local)?;
+            println!("✓ Default profile on this machine is now '{}'", name);
+            Ok(())
+        }
+
+        "clear" => {
+            local.profile = None;
+            dirs.save_local_config(&local)?;
+            println!("✓ Cleared the default profile on this machine");
+            Ok(())
+        }
+
+        other => Err(PollenError::InvalidOption(format!(
+            "Unknown profile subcommand '{}'. Expected list, show, use or clear",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{ConfigParser, PollenDirs, HostFacts, PollenError};
+use crate::alternate::select_stored_copy;
+use crate::cli::pipeline::{assemble_entry, decrypt_entry, render_entry, render_staging_dir};
+use crate::cli::selection::resolve_selectors;
+use crate::template::template_variables;
+use crate::cli::utils::track_files;
+use seahorse::Context;
+use std::{fs, path::Path};
+
+pub fn render_templates(c: &Context) -> Result<(), PollenError> {
+    let target_entries: Vec<String> = c.args.clone();
+
+    let dirs = PollenDirs::new()?;
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+
+    let config_paths = track_files(&dirs, &config)?;
+
+    let entries = parser.parse_files(&config_paths)?;
+
+    // Render every template unless specific entries were asked for
+    let entries_to_render: Vec<_> = if target_entries.is_empty() {
+        entries.into_iter().filter(|entry| entry.template).collect()
+    } else {
+        let selected = resolve_selectors(&target_entries, &entries, parser.home_dir())?;
+        entries.into_iter().filter(|entry| selected.contains(&entry.name)).collect()
+    };
+
+    if entries_to_render.is_empty() {
+        println!("No matching template entries found to render.");
+        return Ok(());
+    }
+
+    let facts = HostFacts::detect();
+    let variables = template_variables(&dirs, &config, &facts)?;
+    let staging_dir = render_staging_dir(&dirs);
+
+    let result = entries_to_render.iter().try_for_each(|entry| {
+        if !entry.template {
+            return Err(PollenError::InvalidOption(format!(
+                "'{}' is not a template (add 'template: true' to its options)",
+                entry.get_display_name()
+            )));
+        }
+
+        let stored_path = match &entry.assemble {
+            Some(assembly) => assemble_entry(entry, assembly, &dirs.files_dir, &facts, &staging_dir)?,
+            None => select_stored_copy(&dirs, entry, &facts).path,
+        };
+        if !stored_path.exists() {
+            return Err(PollenError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Source file does not exist in files directory: {}", stored_path.display())
+            )));
+        }
+
+        let stored_path = if entry.encrypt {
+            decrypt_entry(entry, &stored_path, &dirs, &staging_dir)?
+        } else {
+            stored_path
+        };
+        let rendered_path = render_entry(entry, &stored_path, &variables, &staging_dir)?;
+        print_rendered(&rendered_path, &entry.path)
+    });
+
+    let _ = fs::remove_dir_all(&staging_dir);
+    result
+}
+
+/// Print a rendered file, or every file of a rendered directory, under a header naming its target
+fn print_rendered(rendered_path: &Path, target_path: &Path) -> Result<(), PollenError> {
+    if rendered_path.is_dir() {
+        let mut children: Vec<_> = fs::read_dir(rendered_path)
+            .map_err(PollenError::Io)?
+            .flatten()
+            .map(|entry| entry.file_name())
+            .collect();
+        children.sort();
+
+        for child in children {
+            print_rendered(&rendered_path.join(&child), &target_path.join(&child))?;
+        }
+        return Ok(());
+    }
+
+    let content = fs::read(rendered_path).map_err(PollenError::Io)?;
+    println!("==> {} <==", target_path.display());
+    print!("{}", String::from_utf8_lossy(&content));
+    if !content.ends_with(b"\n") {
+        println!();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,730 @@
+use crate::{ConfigParser, PollenDirs, PollenLock, Trash, Entry, HookOperation, PollenError, Operation, OperationType, OperationEntry};
+use crate::{HostFacts, template::template_variables};
+use crate::alternate::{select_stored_copy, stored_name};
+use crate::cli::pipeline::{assemble_entry, backup_target, decrypt_entry, deploy_entry, layer_entry, remove_block, render_entry, resolve_secrets, filter_entry};
+use crate::filter::FilterDirection;
+use crate::cli::selection::{active_profile, resolve_selectors};
+use crate::overlay::{entry_layers, overlay_names, Layer, OVERLAYS_DIR};
+use crate::cli::utils::{format_timestamp, parse_local_datetime, remove_path, set_dry_run, track_files};
+use seahorse::Context;
+use serde_yaml::Mapping;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The point in time the user asked to restore to
+struct RestorePoint {
+    /// Human readable description used in the plan
+    description: String,
+    /// Operations from this position in the history on happened after the restore point
+    since: Option<usize>,
+    /// Commit of the files directory at the restore point
+    commit: Option<String>,
+}
+
+/// What a backup holds, which decides how it is put back
+#[derive(Clone, Copy, PartialEq)]
+enum BackupForm {
+    /// The file on the system, backed up by scatter or restore, deployed as it is
+    System,
+    /// The stored copy, backed up by gather, deployed the way scatter deploys stored copies
+    Stored,
+}
+
+/// Where the content of an entry at the restore point comes from
+enum RestoreSource {
+    /// A backup taken by a later operation, before it overwrote the entry
+    Backup { path: PathBuf, operation: String, form: BackupForm },
+    /// The stored copy in the files directory at a commit
+    Git { commit: String, path: String },
+    /// The target did not exist at the restore point
+    Absent,
+    /// Nothing recorded about this entry after the restore point
+    Unchanged,
+    /// What the entry was built from cannot be put back together, for the given reason
+    Refused(String),
+}
+
+/// What deploying every entry of a restore shares
+struct Deployment<'a> {
+    dirs: &'a PollenDirs,
+    facts: &'a HostFacts,
+    variables: &'a Mapping,
+    /// Overlays active on this machine, by name
+    overlays: &'a [String],
+    staging_dir: &'a Path,
+    verbose: bool,
+}
+
+pub fn restore_files(c: &Context) -> Result<(), PollenError> {
+    let at = c.string_flag("at").ok();
+    let dry_run = c.bool_flag("dry-run");
+    set_dry_run(dry_run);
+    let target_entries: Vec<String> = c.args.clone();
+
+    let dirs = PollenDirs::new()?;
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+    let verbose = c.bool_flag("verbose") || config.verbose.unwrap_or(false);
+    let operations = dirs.get_operations()?;
+
+    let at = match at {
+        Some(at) => at,
+        None => {
+            print_recent_operations(&operations);
+            return Ok(());
+        }
+    };
+
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(PollenLock::acquire(&dirs, c.bool_flag("wait"))?)
+    };
+
+    let config_paths = track_files(&dirs, &config)?;
+
+    let entries = parser.parse_files(&config_paths)?;
+
+    // Filter entries based on command line arguments
+    let entries_to_restore: Vec<_> = if target_entries.is_empty() {
+        entries
+    } else {
+        let selected = resolve_selectors(&target_entries, &entries, parser.home_dir())?;
+        entries.into_iter().filter(|entry| selected.contains(&entry.name)).collect()
+    };
+
+    // Entries that do not apply to this machine are not restored
+    let facts = HostFacts::detect();
+    let entries_to_restore: Vec<_> = entries_to_restore
+        .into_iter()
+        .filter(|entry| entry.is_enabled(&facts))
+        .collect();
+
+    if entries_to_restore.is_empty() {
+        println!("No matching entries found to restore.");
+        return Ok(());
+    }
+
+    let point = resolve_restore_point(&at, &dirs, &operations)?;
+    let overlays = overlay_names(&dirs, &facts, active_profile(c, &dirs)?.as_deref());
+    let plan: Vec<(Entry, RestoreSource)> = entries_to_restore
+        .into_iter()
+        .map(|entry| {
+            let source = plan_entry(&entry, &point, &operations, &overlays, &dirs, &facts);
+            (entry, source)
+        })
+        .collect();
+
+    println!("Restore plan ({}):", point.description);
+    for (entry, source) in &plan {
+        let action = match source {
+            RestoreSource::Backup { path, operation, form: BackupForm::System } => {
+                format!("← backup taken by operation {} ({})", operation, path.display())
+            }
+            RestoreSource::Backup { path, operation, form: BackupForm::Stored } => {
+                format!("← stored copy backed up by operation {} ({})", operation, path.display())
+            }
+            RestoreSource::Git { commit, path } => format!("← {} at {}", path, short_commit(commit)),
+            RestoreSource::Absent if entry.managed_block => "✗ remove block (did not exist)".to_string(),
+            RestoreSource::Absent => "✗ move to trash (did not exist)".to_string(),
+            RestoreSource::Unchanged => "• unchanged".to_string(),
+            RestoreSource::Refused(reason) => format!("✗ cannot restore: {}", reason),
+        };
+        println!("  {:<24} {}", entry.get_display_name(), action);
+    }
+
+    let changes = plan
+        .iter()
+        .filter(|(_, source)| !matches!(source, RestoreSource::Unchanged))
+        .count();
+
+    if dry_run {
+        println!("\nDry run: {} entries would be restored.", changes);
+        return Ok(());
+    }
+
+    if changes == 0 {
+        println!("\nNothing to restore.");
+        return Ok(());
+    }
+
+    println!("\nRestoring {} entries...", changes);
+
+    let staging_dir = dirs.cache_dir.join(format!("restore-{}", std::process::id()));
+    let variables = template_variables(&dirs, &config, &facts)?;
+    let deployment = Deployment {
+        dirs: &dirs,
+        facts: &facts,
+        variables: &variables,
+        overlays: &overlays,
+        staging_dir: &staging_dir,
+        verbose,
+    };
+    let mut restored_count = 0;
+    let mut failed_count = 0;
+    let mut operation_entries = Vec::new();
+
+    for (entry, source) in &plan {
+        let result = match source {
+            RestoreSource::Unchanged => continue,
+            RestoreSource::Backup { path, form: BackupForm::System, .. } => {
+                deploy_entry(entry, path, path, HookOperation::Restore, &dirs, verbose)
+                    .map(|(deployed, _, backup_path)| (deployed, path.display().to_string(), backup_path))
+            }
+            RestoreSource::Backup { path, form: BackupForm::Stored, .. } => {
+                deploy_stored(entry, path, path, &deployment)
+                    .map(|(deployed, _, backup_path)| (deployed, path.display().to_string(), backup_path))
+            }
+            RestoreSource::Git { commit, path } => {
+                deploy_from_git(entry, commit, path, &deployment)
+                    .map(|(deployed, _, backup_path)| (deployed, format!("git:{}:{}", commit, path), backup_path))
+            }
+            RestoreSource::Absent => remove_target(entry, &dirs, verbose).map(|backup_path| (true, String::new(), backup_path)),
+            RestoreSource::Refused(reason) => Err(PollenError::InvalidEndpoint(reason.clone())),
+        };
+
+        match result {
+            Ok((false, _, _)) => println!("✓ Up to date: {}", entry.get_display_name()),
+            Ok((true, origin, backup_path)) => {
+                restored_count += 1;
+                println!("🐝 Restored: {}", entry.get_display_name());
+                operation_entries.push(OperationEntry {
+                    entry_name: entry.name.clone(),
+                    source_path: entry.path.display().to_string(), // Target location, as for scatter
+                    target_path: origin,
+                    backup_path,
+                    managed_block: entry.managed_block,
+                });
+            }
+            Err(e) => {
+                failed_count += 1;
+                eprintln!("✗ Failed to restore {}: {}", entry.get_display_name(), e);
+            }
+        }
+    }
+
+    let _ = fs::remove_dir_all(&staging_dir);
+
+    println!("\nRestore complete:");
+    println!("  Restored: {}", restored_count);
+    if failed_count > 0 {
+        println!("  Failed: {}", failed_count);
+    }
+
+    // Save as a single operation so one undo reverts the whole restore
+    if !operation_entries.is_empty() {
+        let operation = Operation {
+            id: String::new(),
+            operation_type: OperationType::Restore,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            entries: operation_entries,
+        };
+
+        if let Err(e) = dirs.save_operation(&operation) {
+            eprintln!("Warning: Failed to save operation to history: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_recent_operations(operations: &[Operation]) {
+    if operations.is_empty() {
+        println!("No operations recorded yet.");
+        return;
+    }
+
+    println!("Recent operations (restore to just before one with 'pollen restore --at <ID>'):");
+    for operation in operations.iter().rev() {
+        println!(
+            "  {:<14} {}  {:<8} {} entries",
+            operation.id(),
+            format_timestamp(operation.timestamp),
+            operation.operation_type.as_str(),
+            operation.entries.len()
+        );
+    }
+}
+
+/// Interpret `--at` as an operation ID, a local date or a git revision, in that order
+fn resolve_restore_point(at: &str, dirs: &PollenDirs, operations: &[Operation]) -> Result<RestorePoint, PollenError> {
+    if let Some(index) = find_operation(at, operations)? {
+        // Restoring to an operation means the moment right before it ran
+        let operation = &operations[index];
+        return Ok(RestorePoint {
+            description: format!(
+                "before {} operation {} at {}",
+                operation.operation_type.as_str(),
+                operation.id(),
+                format_timestamp(operation.timestamp)
+            ),
+            since: Some(index),
+            commit: git_commit_at(dirs, operation.timestamp.saturating_sub(1)),
+        });
+    }
+
+    if let Some(timestamp) = parse_local_datetime(at) {
+        return Ok(RestorePoint {
+            description: format!("as of {}", format_timestamp(timestamp)),
+            since: Some(operations.partition_point(|op| op.timestamp <= timestamp)),
+            commit: git_commit_at(dirs, timestamp),
+        });
+    }
+
+    if dirs.is_files_git_repo() {
+        let output = Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", at)])
+            .current_dir(&dirs.files_dir)
+            .output()
+            .map_err(PollenError::Io)?;
+
+        if output.status.success() {
+            let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            return Ok(RestorePoint {
+                description: format!("files directory at {}", short_commit(&commit)),
+                since: None,
+                commit: Some(commit),
+            });
+        }
+    }
+
+    Err(PollenError::InvalidOption(format!(
+        "'{}' is not an operation ID, a date (YYYY-MM-DD [HH:MM[:SS]]) or a git revision",
+        at
+    )))
+}
+
+/// The position in the history of the operation with the ID `at`. A bare timestamp, as older
+/// histories used for IDs, is accepted when only one operation has it.
+fn find_operation(at: &str, operations: &[Operation]) -> Result<Option<usize>, PollenError> {
+    if let Some(index) = operations.iter().position(|op| op.id() == at) {
+        return Ok(Some(index));
+    }
+
+    let matching: Vec<usize> = operations
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| op.timestamp.to_string() == at)
+        .map(|(index, _)| index)
+        .collect();
+    match matching.as_slice() {
+        [] => Ok(None),
+        [index] => Ok(Some(*index)),
+        _ => Err(PollenError::InvalidOption(format!(
+            "Several operations ran at {}: {}. Pass one of their IDs",
+            at,
+            matching.iter().map(|&index| operations[index].id()).collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
+
+/// Find the newest commit in the files directory made at or before `timestamp`
+fn git_commit_at(dirs: &PollenDirs, timestamp: u64) -> Option<String> {
+    if !dirs.is_files_git_repo() {
+        return None;
+    }
+
+    let output = Command::new("git")
+        .args(["rev-list", "-1", &format!("--before=@{}", timestamp), "HEAD"])
+        .current_dir(&dirs.files_dir)
+        .output()
+        .ok()?;
+
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !commit.is_empty() {
+        Some(commit)
+    } else {
+        None
+    }
+}
+
+/// Decide where the content of an entry at the restore point comes from
+fn plan_entry(
+    entry: &Entry,
+    point: &RestorePoint,
+    operations: &[Operation],
+    overlays: &[String],
+    dirs: &PollenDirs,
+    facts: &HostFacts,
+) -> RestoreSource {
+    // The alternate scatter would use on this machine is the one to look up in history
+    let stored_path = select_stored_copy(dirs, entry, facts).path;
+    let stored_name = stored_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let later_operations: Vec<&Operation> = match point.since {
+        Some(since) => operations[since..].iter().collect(),
+        None => Vec::new(),
+    };
+
+    // The first deployment after the restore point backed up exactly what was on the machine
+    let first_deployment = later_operations.iter().find_map(|op| match op.operation_type {
+        OperationType::Scatter | OperationType::Restore => op
+            .entries
+            .iter()
+            .find(|op_entry| op_entry.entry_name == entry.name)
+            .map(|op_entry| (op.id(), op_entry)),
+        OperationType::Gather => None,
+    });
+
+    if let Some((operation, op_entry)) = first_deployment {
+        match &op_entry.backup_path {
+            Some(backup_path) if Path::new(backup_path).exists() => {
+                return RestoreSource::Backup { path: PathBuf::from(backup_path), operation, form: BackupForm::System };
+            }
+            // The backup has been cleaned up, fall back to the repository history
+            Some(_) => {}
+            None if entry.path.exists() => return RestoreSource::Absent,
+            None => return RestoreSource::Unchanged,
+        }
+    }
+
+    // Assembled entries have no stored copy of their own, only fragments
+    if let Some(commit) = &point.commit {
+        let stored_at_commit = match &entry.assemble {
+            Some(assembly) => assembly.active_fragments(facts).iter().any(|fragment| git_path_exists(dirs, commit, &fragment.file)),
+            None => git_path_exists(dirs, commit, &stored_name),
+        };
+        if stored_at_commit {
+            return RestoreSource::Git { commit: commit.clone(), path: stored_name };
+        }
+    }
+    
+    // Gather backs up each overlay file and fragment on its own, and those backups cannot be put
+    // back together into what scatter deployed; only git keeps all of them
+    let gathered = later_operations
+        .iter()
+        .filter(|op| matches!(op.operation_type, OperationType::Gather))
+        .any(|op| op.entries.iter().any(|op_entry| op_entry.entry_name == entry.name));
+    if gathered && entry.assemble.is_some() {
+        return RestoreSource::Refused("its fragments were gathered since, and only git history can restore them".to_string());
+    }
+    if gathered && !entry_layers(dirs, entry, overlays).is_empty() {
+        return RestoreSource::Refused("it has overlays and was gathered since, and only git history can restore them".to_string());
+    }
+
+    // Without git history, a later gather's backup holds the stored copy from before it. Gather
+    // also records overlay files and fragments, whose backups are not the stored copy
+    let first_gather_backup = later_operations
+        .iter()
+        .filter(|op| matches!(op.operation_type, OperationType::Gather))
+        .find_map(|op| {
+            op.entries
+                .iter()
+                .find(|op_entry| op_entry.entry_name == entry.name && Path::new(&op_entry.target_path) == stored_path)
+                .and_then(|op_entry| op_entry.backup_path.as_ref())
+                .filter(|backup_path| Path::new(backup_path).exists())
+                .map(|backup_path| (op.id(), PathBuf::from(backup_path)))
+        });
+
+    if let Some((operation, path)) = first_gather_backup {
+        return RestoreSource::Backup { path, operation, form: BackupForm::Stored };
+    }
+
+    RestoreSource::Unchanged
+}
+
+/// Deploy an entry the way scatter would have at `commit`: from its stored copy, or its fragments,
+/// with the overlays of this machine as they were then layered over it
+fn deploy_from_git(entry: &Entry, commit: &str, path: &str, deployment: &Deployment) -> Result<(bool, bool, Option<String>), PollenError> {
+    let Deployment { dirs, facts, staging_dir, .. } = *deployment;
+    let snapshot_dir = staging_dir.join("files");
+    
+    let staged = match &entry.assemble {
+        Some(assembly) => {
+            for fragment in assembly.active_fragments(facts) {
+                materialize_from_git(dirs, commit, &fragment.file, &snapshot_dir)?;
+            }
+            assemble_entry(entry, assembly, &snapshot_dir, facts, staging_dir)?
+        }
+        None => materialize_from_git(dirs, commit, path, &snapshot_dir)?,
+    };
+    
+    let mut layers = Vec::new();
+    for name in deployment.overlays {
+        let layer_path = format!("{}/{}/{}", OVERLAYS_DIR, name, stored_name(entry));
+        if git_path_exists(dirs, commit, &layer_path) {
+            let path = materialize_from_git(dirs, commit, &layer_path, &snapshot_dir)?;
+            layers.push(Layer { name: name.clone(), path });
+        }
+    }
+    let staged = if layers.is_empty() {
+        staged
+    } else {
+        layer_entry(entry, &staged, &layers, staging_dir)?
+    };
+    
+    deploy_stored(entry, &staged, &dirs.files_dir.join(path), deployment)
+}
+
+/// Deploy a stored copy, from git or a gather backup, the way scatter would: decrypted, rendered,
+/// with secrets resolved and smudge filters applied
+fn deploy_stored(entry: &Entry, stored_path: &Path, origin: &Path, deployment: &Deployment) -> Result<(bool, bool, Option<String>), PollenError> {
+    let Deployment { dirs, variables, staging_dir, verbose, .. } = *deployment;
+    let staged = if entry.encrypt {
+        decrypt_entry(entry, stored_path, dirs, staging_dir)?
+    } else {
+        stored_path.to_path_buf()
+    };
+    let staged = if entry.template {
+        render_entry(entry, &staged, variables, &staging_dir.join("rendered"))?
+    } else {
+        staged
+    };
+    let staged = resolve_secrets(entry, &staged, dirs, staging_dir)?.unwrap_or(staged);
+    let staged = filter_entry(entry, &staged, FilterDirection::Smudge, dirs, staging_dir)?.unwrap_or(staged);
+    deploy_entry(entry, &staged, origin, HookOperation::Restore, dirs, verbose)
+}
+
+fn git_path_exists(dirs: &PollenDirs, commit: &str, path: &str) -> bool {
+    Command::new("git")
+        .args(["cat-file", "-e", &format!("{}:{}", commit, path)])
+        .current_dir(&dirs.files_dir)
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Write the stored file or directory at `commit` into the staging directory, with the modes
+/// git recorded for its files
+fn materialize_from_git(dirs: &PollenDirs, commit: &str, path: &str, staging_dir: &Path) -> Result<PathBuf, PollenError> {
+    let output = Command::new("git")
+        .args(["ls-tree", "-r", "-z", commit, "--", path])
+        .current_dir(&dirs.files_dir)
+        .output()
+        .map_err(PollenError::Io)?;
+
+    if !output.status.success() {
+        return Err(PollenError::InvalidEndpoint(format!(
+            "Failed to list '{}' at {}", path, short_commit(commit)
+        )));
+    }
+
+    let listing = String::from_utf8_lossy(&output.stdout);
+    for line in listing.split('\0').filter(|line| !line.is_empty()) {
+        // Each line is "<mode> <type> <object>\t<path>"
+        let Some((mode, file)) = line.split_once('\t').and_then(|(info, file)| Some((info.split(' ').next()?, file))) else {
+            continue;
+        };
+        let content = Command::new("git")
+            .args(["show", &format!("{}:{}", commit, file)])
+            .current_dir(&dirs.files_dir)
+            .output()
+            .map_err(PollenError::Io)?;
+
+        if !content.status.success() {
+            return Err(PollenError::InvalidEndpoint(format!(
+                "Failed to read '{}' at {}", file, short_commit(commit)
+            )));
+        }
+
+        let staged_file = staging_dir.join(file);
+        if let Some(parent) = staged_file.parent() {
+            fs::create_dir_all(parent).map_err(PollenError::Io)?;
+        }
+        write_git_file(&staged_file, mode, content.stdout)?;
+    }
+
+    Ok(staging_dir.join(path))
+}
+
+/// Write a file read from git, as a symlink for mode 120000 and executable for mode 100755
+#[cfg(unix)]
+fn write_git_file(path: &Path, mode: &str, content: Vec<u8>) -> Result<(), PollenError> {
+    use std::os::unix::{ffi::OsStringExt, fs::PermissionsExt};
+
+    if mode == "120000" {
+        let link_target = PathBuf::from(std::ffi::OsString::from_vec(content));
+        return std::os::unix::fs::symlink(link_target, path).map_err(PollenError::Io);
+    }
+
+    fs::write(path, content).map_err(PollenError::Io)?;
+    if mode == "100755" {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).map_err(PollenError::Io)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_git_file(path: &Path, _mode: &str, content: Vec<u8>) -> Result<(), PollenError> {
+    fs::write(path, content).map_err(PollenError::Io)
+}
+
+/// Back up and remove an entry that did not exist at the restore point. Anything the backup
+/// does not hold, such as a dangling symlink, is moved to the trash instead.
+fn remove_target(entry: &Entry, dirs: &PollenDirs, verbose: bool) -> Result<Option<String>, PollenError> {
+    let backup_path = backup_target(entry, dirs, verbose)?;
+
+    if entry.managed_block {
+        remove_block(entry)?;
+    } else if backup_path.is_some() {
+        remove_path(&entry.path)?;
+    } else if entry.path.symlink_metadata().is_ok() {
+        Trash::new(dirs)?.move_to_trash(&entry.path)?;
+    }
+
+    Ok(backup_path)
+}
+
+fn short_commit(commit: &str) -> &str {
+    &commit[..commit.len().min(7)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::Assembly;
+    use crate::entry::EntryArgument;
+
+    fn dirs(root: &Path) -> PollenDirs {
+        PollenDirs::from_base_dirs(root.join("config"), root.join("state"), root.join("data"), root.join("cache")).unwrap()
+    }
+
+    /// Write `files` into the files directory and commit them, returning the commit
+    fn commit(dirs: &PollenDirs, files: &[(&str, &str)]) -> String {
+        for (file, content) in files {
+            let path = dirs.files_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .args(["-c", "user.name=pollen", "-c", "user.email=pollen@localhost"])
+                .args(args)
+                .current_dir(&dirs.files_dir)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        git(&["init", "-q"]);
+        git(&["add", "-A"]);
+        git(&["commit", "-q", "-m", "files"]);
+        git(&["rev-parse", "HEAD"])
+    }
+
+    fn restore(entry: &Entry, commit: &str, overlays: &[String], dirs: &PollenDirs) -> String {
+        let deployment = Deployment {
+            dirs,
+            facts: &HostFacts::detect(),
+            variables: &Mapping::new(),
+            overlays,
+            staging_dir: &dirs.cache_dir.join("restore"),
+            verbose: false,
+        };
+        deploy_from_git(entry, commit, &stored_name(entry), &deployment).unwrap();
+        fs::read_to_string(&entry.path).unwrap()
+    }
+
+    #[test]
+    fn overlays_are_restored_as_they_were_at_the_commit() {
+        let root = std::env::temp_dir().join(format!("pollen-restore-overlay-{}", std::process::id()));
+        let dirs = dirs(&root);
+        let entry = Entry::new(EntryArgument { name: ".vimrc".to_string(), path: root.join("home/.vimrc") });
+        let at = commit(&dirs, &[(".vimrc", "base\n"), ("overlays/work/.vimrc", "work\n")]);
+        commit(&dirs, &[("overlays/work/.vimrc", "work, later\n")]);
+
+        let restored = restore(&entry, &at, &["work".to_string()], &dirs);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(restored, "work\n");
+    }
+
+    #[test]
+    fn assembled_entries_are_restored_from_their_fragments() {
+        let root = std::env::temp_dir().join(format!("pollen-restore-assembled-{}", std::process::id()));
+        let dirs = dirs(&root);
+        let assembly = Assembly::parse(&serde_yaml::from_str("{fragments: [ssh/base, ssh/hosts], markers: false}").unwrap()).unwrap();
+        let entry = Entry::new(EntryArgument { name: "config".to_string(), path: root.join("home/.ssh/config") })
+            .with_assembly(assembly);
+        let at = commit(&dirs, &[("ssh/base", "Host *\n"), ("ssh/hosts", "Host old\n")]);
+        commit(&dirs, &[("ssh/hosts", "Host new\n")]);
+
+        let restored = restore(&entry, &at, &[], &dirs);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(restored, "Host *\n\nHost old\n");
+    }
+
+    fn operation(operation_type: OperationType, timestamp: u64, backup_path: Option<&Path>) -> Operation {
+        Operation {
+            id: String::new(),
+            operation_type,
+            timestamp,
+            entries: vec![OperationEntry {
+                entry_name: ".vimrc".to_string(),
+                source_path: String::new(),
+                target_path: String::new(),
+                backup_path: backup_path.map(|path| path.display().to_string()),
+                managed_block: false,
+            }],
+        }
+    }
+
+    #[test]
+    fn plans_use_the_first_deployment_after_the_point() {
+        let root = std::env::temp_dir().join(format!("pollen-restore-plan-{}", std::process::id()));
+        let dirs = dirs(&root);
+        let entry = Entry::new(EntryArgument { name: ".vimrc".to_string(), path: root.join("home/.vimrc") });
+        let (first, second) = (dirs.backup_dir.join("first.backup"), dirs.backup_dir.join("second.backup"));
+        fs::write(&first, "first").unwrap();
+        fs::write(&second, "second").unwrap();
+        let operations = [
+            operation(OperationType::Scatter, 1, None),
+            operation(OperationType::Scatter, 2, Some(&first)),
+            operation(OperationType::Scatter, 3, Some(&second)),
+        ];
+        let facts = HostFacts::detect();
+        let plan = |since: usize| {
+            let point = RestorePoint { description: String::new(), since: Some(since), commit: None };
+            plan_entry(&entry, &point, &operations, &[], &dirs, &facts)
+        };
+
+        assert!(matches!(plan(1), RestoreSource::Backup { path, form: BackupForm::System, .. } if path == first));
+        assert!(matches!(plan(2), RestoreSource::Backup { path, .. } if path == second));
+        // Nothing was there before the first scatter
+        assert!(matches!(plan(0), RestoreSource::Unchanged));
+        fs::create_dir_all(root.join("home")).unwrap();
+        fs::write(&entry.path, "scattered").unwrap();
+        assert!(matches!(plan(0), RestoreSource::Absent));
+        assert!(matches!(plan(3), RestoreSource::Unchanged));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn gathered_overlays_and_fragments_without_git_are_refused() {
+        let root = std::env::temp_dir().join(format!("pollen-restore-refused-{}", std::process::id()));
+        let dirs = dirs(&root);
+        fs::create_dir_all(dirs.files_dir.join("overlays/work/.vimrc")).unwrap();
+        let assembly = Assembly::parse(&serde_yaml::from_str("[ssh/base]").unwrap()).unwrap();
+        let assembled = Entry::new(EntryArgument { name: "config".to_string(), path: root.join("home/.ssh/config") })
+            .with_assembly(assembly);
+        let layered = Entry::new(EntryArgument { name: ".vimrc".to_string(), path: root.join("home/.vimrc") });
+
+        let gathered = |name: &str| OperationEntry {
+            entry_name: name.to_string(),
+            source_path: String::new(),
+            target_path: String::new(),
+            backup_path: None,
+            managed_block: false,
+        };
+        let operations = [Operation {
+            id: String::new(),
+            operation_type: OperationType::Gather,
+            timestamp: 1,
+            entries: vec![gathered("config"), gathered(".vimrc")],
+        }];
+        let point = RestorePoint { description: String::new(), since: Some(0), commit: None };
+
+        let facts = HostFacts::detect();
+        let overlays = ["work".to_string()];
+        let sources = [&assembled, &layered].map(|entry| plan_entry(entry, &point, &operations, &overlays, &dirs, &facts));
+        fs::remove_dir_all(&root).unwrap();
+        assert!(sources.iter().all(|source| matches!(source, RestoreSource::Refused(_))));
+    }
+}
@@ -1,92 +1,134 @@
-use crate::{ConfigParser, PollenDirs, Entry, PollenError, Operation, OperationType, OperationEntry};
-use crate::cli::utils::{execute_shell_command, copy_dir_all};
+use crate::{ConfigParser, PollenDirs, PollenLock, Entry, HostFacts, PollenError, Operation, OperationType, OperationEntry, HookOperation};
+use crate::alternate::select_stored_copy;
+use crate::filter::FilterDirection;
+use crate::overlay::{entry_layers, overlay_names, Layer};
+use crate::cli::pipeline::{assemble_entry, decrypt_entry, deploy_entry, filter_entry, layer_entry, render_entry, render_staging_dir, resolve_secrets};
+use crate::cli::selection::{active_profile, add_related_entries, check_conflicts, pulled_in_note, select_entries};
+use crate::cli::utils::track_files;
+use crate::template::template_variables;
 use seahorse::Context;
-use std::{fs, time::SystemTime};
+use serde_yaml::Mapping;
+use std::{fs, path::Path};
 
 pub fn scatter_files(c: &Context) -> Result<(), PollenError> {
-    let config_file = c.string_flag("config").ok();
-    let target_entries: Vec<String> = c.args.clone();
-    
-    let parser = ConfigParser::new()?;
     let dirs = PollenDirs::new()?;
+    let _lock = PollenLock::acquire(&dirs, c.bool_flag("wait"))?;
     let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+    let verbose = c.bool_flag("verbose") || config.verbose.unwrap_or(false);
     
-    let (config_path, _used_config_file) = match config_file.as_deref() {
-        Some(path) => (path.to_string(), path.to_string()),
-        None => {
-            let track_file = dirs.get_track_file_path(&config);
-            let path_str = track_file.to_str().ok_or(PollenError::InvalidEndpoint("Invalid config file path".into()))?;
-            (path_str.to_string(), track_file.display().to_string())
-        }
-    };
+    let config_paths = track_files(&dirs, &config)?;
 
-    let entries = parser.parse_file(&config_path)?;
-    
-    // Filter entries based on command line arguments
-    let entries_to_scatter: Vec<_> = if target_entries.is_empty() {
-        // If no specific entries specified, scatter all
-        entries
-    } else {
-        entries
-            .into_iter()
-            .filter(|entry| {
-                // Match by name or alias
-                target_entries.iter().any(|target| {
-                    entry.name == *target || 
-                    entry.alias_as.as_ref() == Some(target) ||
-                    entry.get_display_name() == target
-                })
-            })
-            .collect()
-    };
+    let entries = parser.parse_files(&config_paths)?;
+    
+    // Filter entries by profile and command line arguments
+    let entries_to_scatter = select_entries(c, &parser, &dirs, &config, &config_paths, entries.clone())?;
+    let (entries_to_scatter, pulled_in) = add_related_entries(c, &parser, &config, entries_to_scatter, entries)?;
+    
+    // Entries whose `when` condition does not hold on this machine are left alone
+    let facts = HostFacts::detect();
+    let (entries_to_scatter, skipped): (Vec<_>, Vec<_>) = entries_to_scatter
+        .into_iter()
+        .partition(|entry| entry.is_enabled(&facts));
+    
+    if verbose {
+        for entry in &skipped {
+            if let Some(condition) = &entry.when {
+                println!("• Skipped: {} (when {})", entry.get_display_name(), condition);
+            }
+        }
+    }
     
     if entries_to_scatter.is_empty() {
         println!("No matching entries found to scatter.");
         return Ok(());
     }
     
+    // Entries skipped by a condition do not conflict with anything
+    check_conflicts(&entries_to_scatter)?;
+    
     println!("Scattering {} entries...", entries_to_scatter.len());
     
     let dirs = PollenDirs::new()?;
+    let variables = template_variables(&dirs, &config, &facts)?;
+    let staging_dir = render_staging_dir(&dirs);
+    let overlays = overlay_names(&dirs, &facts, active_profile(c, &dirs)?.as_deref());
     let mut scattered_count = 0;
     let mut backed_up_count = 0;
     let mut failed_count = 0;
     let mut operation_entries = Vec::new();
     
     for entry in &entries_to_scatter {
-        match scatter_single_entry(entry, &dirs) {
+        let stored = select_stored_copy(&dirs, entry, &facts);
+        if verbose {
+            if let Some(alternate) = &stored.alternate {
+                println!("  → Using alternate ##{} for {}", alternate, entry.get_display_name());
+            }
+        }
+        
+        let layers = entry_layers(&dirs, entry, &overlays);
+        
+        // Assembled entries have no stored copy of their own
+        let source_path = match &entry.assemble {
+            Some(assembly) => {
+                if verbose {
+                    println!("  → Assembling {} from {} fragments", entry.get_display_name(), assembly.active_fragments(&facts).len());
+                }
+                assemble_entry(entry, assembly, &dirs.files_dir, &facts, &staging_dir)
+            }
+            None => Ok(stored.path.clone()),
+        };
+        
+        let result = source_path.and_then(|source_path| {
+            scatter_single_entry(entry, &source_path, &layers, &dirs, verbose, &variables, &staging_dir)
+        });
+        
+        match result {
             Ok((scattered, backed_up, backup_path)) => {
                 if scattered {
                     scattered_count += 1;
-                    println!("🐝 Scattered: {}", entry.get_display_name());
+                    println!("🐝 Scattered: {}{}", entry.get_display_name(), pulled_in_note(entry, &pulled_in));
                     
                     // Record the operation
-                    let source_filename = entry.name.replace('/', "_").replace('\\', "_");
-                    let source_path = dirs.get_files_path(&source_filename);
                     operation_entries.push(OperationEntry {
                         entry_name: entry.name.clone(),
                         source_path: entry.path.display().to_string(), // Target location for scatter
-                        target_path: source_path.display().to_string(),  // Source in files directory
+                        target_path: stored.path.display().to_string(),  // Source in files directory
                         backup_path,
+                        managed_block: entry.managed_block,
                     });
+                } else {
+                    println!("✓ Up to date: {}{}", entry.get_display_name(), pulled_in_note(entry, &pulled_in));
+                }
+                for layer in &layers {
+                    println!("  → Layered overlay {}", layer.name);
                 }
                 if backed_up {
                     backed_up_count += 1;
-                    println!("  → Backed up existing file to cache");
+                    println!("  → Backed up existing {}", if entry.managed_block { "block" } else { "file" });
                 }
             }
             Err(e) => {
                 failed_count += 1;
-                eprintln!("✗ Failed to scatter {}: {}", entry.get_display_name(), e);
+                eprintln!("✗ Failed to scatter {}{}: {}", entry.get_display_name(), pulled_in_note(entry, &pulled_in), e);
             }
         }
     }
     
+    let _ = fs::remove_dir_all(&staging_dir);
+    
     println!("\nScatter complete:");
     println!("  Scattered: {}", scattered_count);
     if backed_up_count > 0 {
         println!("  Backed up: {}", backed_up_count);
     }
+    let pulled_in_count = entries_to_scatter.iter().filter(|entry| pulled_in.contains_key(&entry.name)).count();
+    if pulled_in_count > 0 {
+        println!("  Pulled in: {}", pulled_in_count);
+    }
+    if !skipped.is_empty() {
+        println!("  Skipped: {}", skipped.len());
+    }
     if failed_count > 0 {
         println!("  Failed: {}", failed_count);
     }
@@ -94,6 +136,7 @@ pub fn scatter_files(c: &Context) -> Result<(), PollenError> {
     // Save the operation to history if any entries were scattered
     if !operation_entries.is_empty() {
         let operation = Operation {
+            id: String::new(),
             operation_type: OperationType::Scatter,
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -110,10 +153,17 @@ pub fn scatter_files(c: &Context) -> Result<(), PollenError> {
     Ok(())
 }
 
-fn scatter_single_entry(entry: &Entry, dirs: &PollenDirs) -> Result<(bool, bool, Option<String>), PollenError> {
-    let source_filename = entry.name.replace('/', "_").replace('\\', "_");
-    let source_path = dirs.get_files_path(&source_filename);
-    let target_path = &entry.path;
+fn scatter_single_entry(
+    entry: &Entry,
+    source_path: &Path,
+    layers: &[Layer],
+    dirs: &PollenDirs,
+    verbose: bool,
+    variables: &Mapping,
+    staging_dir: &Path,
+) -> Result<(bool, bool, Option<String>), PollenError> {
+    // Hooks see the stored copy as the source, not the staged copies made from it
+    let origin = source_path;
     
     // Check if source exists in files directory
     if !source_path.exists() {
@@ -123,76 +173,48 @@ fn scatter_single_entry(entry: &Entry, dirs: &PollenDirs) -> Result<(bool, bool,
         )));
     }
     
-    // Execute run_before command if specified
-    if let Some(run_before) = &entry.run_before {
-        println!("  → Running pre-scatter command: {}", run_before);
-        if let Err(e) = execute_shell_command(run_before) {
-            return Err(PollenError::InvalidEndpoint(
-                format!("Failed to execute run_before command '{}': {}", run_before, e)
-            ));
-        }
-    }
+    // Overlays are layered before rendering, so they can hold templates too
+    let layered_path;
+    let source_path = if layers.is_empty() {
+        source_path
+    } else {
+        layered_path = layer_entry(entry, source_path, layers, staging_dir)?;
+        &layered_path
+    };
     
-    let mut backed_up = false;
-    let mut backup_path_str = None;
-    
-    // If target already exists, back it up to cache
-    if target_path.exists() {
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map_err(|_| PollenError::Io(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to get system time"
-            )))?
-            .as_secs();
-        
-        let backup_filename = format!("{}_{}.backup", source_filename, timestamp);
-        let backup_path = dirs.get_cache_file_path(&backup_filename);
-        
-        // Ensure cache directory exists
-        if let Some(cache_parent) = backup_path.parent() {
-            fs::create_dir_all(cache_parent)
-                .map_err(PollenError::Io)?;
+    let decrypted_path;
+    let source_path = if entry.encrypt {
+        decrypted_path = decrypt_entry(entry, source_path, dirs, staging_dir)?;
+        if verbose {
+            println!("  → Decrypted {}", source_path.display());
         }
-        
-        if target_path.is_dir() {
-            copy_dir_all(target_path, &backup_path)?;
-        } else {
-            fs::copy(target_path, &backup_path)
-                .map_err(PollenError::Io)?;
-        }
-        backed_up = true;
-        backup_path_str = Some(backup_path.display().to_string());
-    }
-    
-    // Ensure target directory exists
-    if let Some(target_parent) = target_path.parent() {
-        fs::create_dir_all(target_parent)
-            .map_err(PollenError::Io)?;
-    }
+        &decrypted_path
+    } else {
+        source_path
+    };
     
-    // Copy source to target
-    if source_path.is_dir() {
-        // If target exists and is a directory, remove it first
-        if target_path.exists() && target_path.is_dir() {
-            fs::remove_dir_all(target_path)
-                .map_err(PollenError::Io)?;
+    let rendered_path;
+    let source_path = if entry.template {
+        rendered_path = render_entry(entry, source_path, variables, staging_dir)?;
+        if verbose {
+            println!("  → Rendered template {}", source_path.display());
         }
-        copy_dir_all(&source_path, target_path)?;
+        &rendered_path
     } else {
-        fs::copy(&source_path, target_path)
-            .map_err(PollenError::Io)?;
+        source_path
+    };
+    
+    // Secrets are resolved last, so their values never pass through templates or overlays
+    let resolved_path = resolve_secrets(entry, source_path, dirs, staging_dir)?;
+    if verbose && resolved_path.is_some() {
+        println!("  → Resolved secrets in {}", source_path.display());
     }
+    let source_path = resolved_path.as_deref().unwrap_or(source_path);
     
-    // Execute run_after command if specified
-    if let Some(run_after) = &entry.run_after {
-        println!("  → Running post-scatter command: {}", run_after);
-        if let Err(e) = execute_shell_command(run_after) {
-            return Err(PollenError::InvalidEndpoint(
-                format!("Failed to execute run_after command '{}': {}", run_after, e)
-            ));
-        }
+    let smudged_path = filter_entry(entry, source_path, FilterDirection::Smudge, dirs, staging_dir)?;
+    if verbose && smudged_path.is_some() {
+        println!("  → Smudged through {}", entry.filters.join(", "));
     }
     
-    Ok((true, backed_up, backup_path_str))
+    deploy_entry(entry, smudged_path.as_deref().unwrap_or(source_path), origin, HookOperation::Scatter, dirs, verbose)
 }
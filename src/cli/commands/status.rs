@@ -0,0 +1,210 @@
+use crate::{ConfigParser, PollenDirs, Entry, HostFacts, PollenError};
+use crate::alternate::{select_stored_copy, stored_name};
+use crate::block::with_final_newline;
+use crate::cli::pipeline::{assemble_entry, layer_entry, render_entry, decrypt_entry, render_staging_dir, stage_block, stage_captured, stage_normalized, conceal_secrets, filter_entry};
+use crate::filter::FilterDirection;
+use crate::cli::selection::{active_profile, select_entries};
+use crate::overlay::{entry_layers, file_layers, overlay_names, Layer};
+use crate::cli::utils::differing_paths;
+use crate::template::template_variables;
+use crate::cli::utils::track_files;
+use seahorse::Context;
+use serde_yaml::Mapping;
+use std::{fs, path::{Path, PathBuf}};
+
+/// How an entry's stored copy compares with the file on the system
+pub(crate) enum EntryState {
+    /// The system matches the stored copy (rendered, for templates)
+    InSync,
+    /// The system differs; holds the differing relative paths, empty string for a single file
+    Modified(Vec<String>),
+    /// Stored but not present on the system
+    MissingOnSystem,
+    /// Present on the system but never gathered
+    NotGathered,
+    /// Neither stored nor present on the system
+    Missing,
+}
+
+pub fn show_status(c: &Context) -> Result<(), PollenError> {
+    let dirs = PollenDirs::new()?;
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+
+    let config_paths = track_files(&dirs, &config)?;
+
+    let entries = parser.parse_files(&config_paths)?;
+    let entries = select_entries(c, &parser, &dirs, &config, &config_paths, entries)?;
+
+    if entries.is_empty() {
+        println!("No matching entries found.");
+        return Ok(());
+    }
+
+    let facts = HostFacts::detect();
+    let variables = template_variables(&dirs, &config, &facts)?;
+    let staging_dir = render_staging_dir(&dirs);
+    let overlays = overlay_names(&dirs, &facts, active_profile(c, &dirs)?.as_deref());
+    let mut changed = 0;
+
+    for entry in &entries {
+        let name = entry.get_display_name();
+
+        if !entry.is_enabled(&facts) {
+            if let Some(condition) = &entry.when {
+                println!("  - {:<24} skipped (when {})", name, condition);
+            }
+            continue;
+        }
+
+        let stored = select_stored_copy(&dirs, entry, &facts);
+        let alternate = stored
+            .alternate
+            .as_ref()
+            .map(|alternate| format!(" (##{})", alternate))
+            .unwrap_or_default();
+        let layers = entry_layers(&dirs, entry, &overlays);
+
+        // Which layer each file is deployed from, shown for entries with overlays
+        let sources = file_layers(&stored.path, &layers);
+        let source_of = |path: &str| match sources.get(Path::new(path)) {
+            Some(Some(layer)) => format!(" (overlay {})", layer),
+            Some(None) => " (base)".to_string(),
+            None => String::new(),
+        };
+
+        match entry_state(entry, &dirs, &facts, &layers, &variables, &staging_dir) {
+            Ok((EntryState::InSync, ..)) => println!("  ✓ {:<24} in sync{}", name, alternate),
+            Ok((EntryState::Modified(paths), ..)) => {
+                changed += 1;
+                println!("  • {:<24} modified{}", name, alternate);
+                for path in paths.iter().filter(|path| !path.is_empty()) {
+                    if layers.is_empty() {
+                        println!("      {}", path);
+                    } else {
+                        println!("      {}{}", path, source_of(path));
+                    }
+                }
+            }
+            Ok((EntryState::MissingOnSystem, ..)) => {
+                changed += 1;
+                println!("  ✗ {:<24} missing on system{}", name, alternate);
+            }
+            Ok((EntryState::NotGathered, ..)) => {
+                changed += 1;
+                println!("  ✗ {:<24} not gathered", name);
+            }
+            Ok((EntryState::Missing, ..)) => {
+                changed += 1;
+                println!("  ✗ {:<24} missing everywhere", name);
+            }
+            Err(e) => {
+                changed += 1;
+                println!("  ✗ {:<24} {}", name, e);
+            }
+        }
+
+        for (path, layer) in &sources {
+            if let Some(layer) = layer {
+                let path = if path.as_os_str().is_empty() { name.to_string() } else { path.display().to_string() };
+                println!("      ↳ {} from overlay {}", path, layer);
+            }
+        }
+    }
+
+    let _ = fs::remove_dir_all(&staging_dir);
+
+    if changed == 0 {
+        println!("\nEverything is in sync.");
+    } else {
+        println!("\n{} entries differ from the files directory.", changed);
+    }
+
+    Ok(())
+}
+
+/// Compare an entry's stored copy with the system, returning the state, the path holding the
+/// expected content (layered with overlays and rendered for templates) and the path holding what
+/// is on the system (just the block, for managed blocks)
+pub(crate) fn entry_state(
+    entry: &Entry,
+    dirs: &PollenDirs,
+    facts: &HostFacts,
+    layers: &[Layer],
+    variables: &Mapping,
+    staging_dir: &Path,
+) -> Result<(EntryState, PathBuf, PathBuf), PollenError> {
+    let stored_path = match &entry.assemble {
+        Some(assembly) => assemble_entry(entry, assembly, &dirs.files_dir, facts, staging_dir)?,
+        None => select_stored_copy(dirs, entry, facts).path,
+    };
+
+    // A managed block is compared on its own, without the rest of the file it lives in
+    let system_path = if entry.managed_block {
+        stage_block(entry, staging_dir)?
+    } else {
+        Some(entry.path.clone()).filter(|path| path.symlink_metadata().is_ok())
+    };
+    let on_system = system_path.is_some();
+    let system_path = system_path.unwrap_or_else(|| entry.path.clone());
+
+    if !stored_path.exists() {
+        let state = if on_system { EntryState::NotGathered } else { EntryState::Missing };
+        return Ok((state, stored_path, system_path));
+    }
+
+    let expected_path = if layers.is_empty() {
+        stored_path
+    } else {
+        layer_entry(entry, &stored_path, layers, staging_dir)?
+    };
+    let expected_path = if entry.encrypt {
+        decrypt_entry(entry, &expected_path, dirs, staging_dir)?
+    } else {
+        expected_path
+    };
+    let expected_path = if entry.template {
+        render_entry(entry, &expected_path, variables, staging_dir)?
+    } else {
+        expected_path
+    };
+
+    // Scatter always ends a block with a newline, so the stored copy is compared the same way
+    let expected_path = if entry.managed_block && expected_path.is_file() {
+        let content = fs::read_to_string(&expected_path).map_err(PollenError::Io)?;
+        let block_path = staging_dir.join("blocks-expected").join(stored_name(entry));
+        if let Some(parent) = block_path.parent() {
+            fs::create_dir_all(parent).map_err(PollenError::Io)?;
+        }
+        fs::write(&block_path, with_final_newline(&content)).map_err(PollenError::Io)?;
+        block_path
+    } else {
+        expected_path
+    };
+
+    if !on_system {
+        return Ok((EntryState::MissingOnSystem, expected_path, system_path));
+    }
+
+    // Merged entries compare only the keys pollen manages, both laid out the same way
+    let (expected_path, system_path) = match &entry.merge {
+        Some(merge) => {
+            let captured_path = stage_captured(entry, merge, &expected_path, staging_dir)?.unwrap_or(system_path);
+            (stage_normalized(entry, merge, &expected_path, staging_dir)?, captured_path)
+        }
+        None => (expected_path, system_path),
+    };
+
+    // The system is compared as gather would store it
+    let system_path = filter_entry(entry, &system_path, FilterDirection::Clean, dirs, staging_dir)?.unwrap_or(system_path);
+
+    // Secret values on the system are compared as the placeholders they were resolved from
+    let system_path = conceal_secrets(entry, &system_path, Some(&expected_path), dirs, staging_dir)?.unwrap_or(system_path);
+
+    let differences = differing_paths(&expected_path, &system_path);
+    if differences.is_empty() {
+        Ok((EntryState::InSync, expected_path, system_path))
+    } else {
+        Ok((EntryState::Modified(differences), expected_path, system_path))
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{PollenDirs, PollenLock, PollenError, Trash};
+use crate::cli::utils::format_timestamp;
+use seahorse::Context;
+
+pub fn handle_trash_command(c: &Context) -> Result<(), PollenError> {
+    let dirs = PollenDirs::new()?;
+    let trash = Trash::new(&dirs)?;
+
+    let subcommand = c.args.first().map(|s| s.as_str()).unwrap_or("list");
+
+    match subcommand {
+        "list" => {
+            let items = trash.list()?;
+            if items.is_empty() {
+                println!("Trash is empty.");
+                return Ok(());
+            }
+
+            println!("Trash ({}):", dirs.trash_dir.display());
+            for item in &items {
+                println!(
+                    "  {:<14} {}  {:>10}  {}",
+                    item.id,
+                    format_timestamp(item.trashed_at),
+                    format_size(item.size),
+                    item.original_path
+                );
+            }
+
+            let total: u64 = items.iter().map(|item| item.size).sum();
+            println!("\n{} items, {}", items.len(), format_size(total));
+            Ok(())
+        }
+
+        "restore" => {
+            let ids = &c.args[1..];
+            if ids.is_empty() {
+                return Err(PollenError::InvalidOption(
+                    "Usage: pollen trash restore <ID>...".to_string()
+                ));
+            }
+
+            let _lock = PollenLock::acquire(&dirs, c.bool_flag("wait"))?;
+            for id in ids {
+                let item = trash.restore(id)?;
+                println!("✓ Restored {} to {}", item.id, item.original_path);
+            }
+            Ok(())
+        }
+
+        "empty" => {
+            let _lock = PollenLock::acquire(&dirs, c.bool_flag("wait"))?;
+            let removed = trash.empty()?;
+            println!("✓ Permanently deleted {} items from the trash", removed);
+            Ok(())
+        }
+
+        other => Err(PollenError::InvalidOption(format!(
+            "Unknown trash subcommand '{}'. Expected list, restore or empty",
+            other
+        ))),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
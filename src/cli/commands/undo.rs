@@ -1,10 +1,12 @@
-use crate::{PollenDirs, PollenError, OperationType, OperationEntry};
-use crate::cli::utils::copy_dir_all;
+use crate::{block, ConfigParser, Entry, HookOperation, PollenDirs, PollenLock, Trash, PollenError, Operation, OperationType, OperationEntry};
+use crate::cli::utils::{copy_path, run_after_hooks, run_before_hooks, track_files, HookContext};
 use seahorse::Context;
 use std::{fs, path::Path};
 
-pub fn undo_last_operation(_c: &Context) -> Result<(), PollenError> {
+pub fn undo_last_operation(c: &Context) -> Result<(), PollenError> {
     let dirs = PollenDirs::new()?;
+    let _lock = PollenLock::acquire(&dirs, c.bool_flag("wait"))?;
+    let verbose = c.bool_flag("verbose") || dirs.load_config()?.verbose.unwrap_or(false);
     
     // Get the last operation
     let last_operation = match dirs.get_last_operation()? {
@@ -16,19 +18,30 @@ pub fn undo_last_operation(_c: &Context) -> Result<(), PollenError> {
     };
     
     println!("Undoing last {} operation with {} entries...", 
-             match last_operation.operation_type {
-                 OperationType::Gather => "gather",
-                 OperationType::Scatter => "scatter",
-             },
+             last_operation.operation_type.as_str(),
              last_operation.entries.len());
     
     let mut restored_count = 0;
     let mut failed_count = 0;
     let mut removed_count = 0;
+    let mut blocks_removed_count = 0;
+    let mut hook_failed_count = 0;
+    let mut skipped_count = 0;
+    let mut remaining = Vec::new();
+    
+    // Undo hooks come from the entries as they are tracked now
+    let entries = match tracked_entries(&dirs) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Warning: Cannot read track files, skipping undo hooks: {}", e);
+            Vec::new()
+        }
+    };
     
     for op_entry in &last_operation.entries {
-        match undo_single_operation_entry(op_entry, &last_operation.operation_type) {
-            Ok(undo_result) => {
+        let entry = entries.iter().find(|entry| entry.name == op_entry.entry_name);
+        match undo_with_hooks(op_entry, entry, &last_operation.operation_type, &dirs, verbose) {
+            Ok(UndoOutcome::Done(undo_result, after_hooks)) => {
                 match undo_result {
                     UndoResult::Restored => {
                         restored_count += 1;
@@ -36,32 +49,59 @@ pub fn undo_last_operation(_c: &Context) -> Result<(), PollenError> {
                     }
                     UndoResult::Removed => {
                         removed_count += 1;
-                        println!("✓ Removed: {}", op_entry.entry_name);
+                        println!("✓ Moved to trash: {}", op_entry.entry_name);
+                    }
+                    UndoResult::BlockRemoved => {
+                        blocks_removed_count += 1;
+                        println!("✓ Removed block: {}", op_entry.entry_name);
                     }
                     UndoResult::NoBackup => {
                         println!("• No backup to restore for: {}", op_entry.entry_name);
                     }
                 }
+                if let Err(e) = after_hooks {
+                    hook_failed_count += 1;
+                    eprintln!("Warning: Undid {} but its hooks failed: {}", op_entry.entry_name, e);
+                }
+            }
+            Ok(UndoOutcome::Skipped(e)) => {
+                skipped_count += 1;
+                remaining.push(op_entry.clone());
+                eprintln!("✗ Skipped {}: {}", op_entry.entry_name, e);
             }
             Err(e) => {
                 failed_count += 1;
+                remaining.push(op_entry.clone());
                 eprintln!("✗ Failed to undo {}: {}", op_entry.entry_name, e);
             }
         }
     }
     
-    // Remove the operation from history
-    dirs.remove_last_operation()?;
+    // Entries that were skipped or failed to revert stay in history so the next undo can retry them
+    if remaining.is_empty() {
+        dirs.remove_last_operation()?;
+    } else {
+        dirs.replace_last_operation(&Operation { entries: remaining, ..last_operation })?;
+    }
     
     println!("\nUndo complete:");
     if restored_count > 0 {
         println!("  Restored from backup: {}", restored_count);
     }
     if removed_count > 0 {
-        println!("  Removed files: {}", removed_count);
+        println!("  Moved to trash: {}", removed_count);
+    }
+    if blocks_removed_count > 0 {
+        println!("  Removed blocks: {}", blocks_removed_count);
+    }
+    if hook_failed_count > 0 {
+        println!("  Hooks failed after undo: {}", hook_failed_count);
     }
     if failed_count > 0 {
-        println!("  Failed: {}", failed_count);
+        println!("  Failed, kept for the next undo: {}", failed_count);
+    }
+    if skipped_count > 0 {
+        println!("  Skipped, kept for the next undo: {}", skipped_count);
     }
     
     Ok(())
@@ -70,11 +110,56 @@ pub fn undo_last_operation(_c: &Context) -> Result<(), PollenError> {
 #[derive(Debug)]
 enum UndoResult {
     Restored,    // File was restored from backup
-    Removed,     // File was removed (no backup existed)
+    Removed,     // File was moved to the trash (no backup existed)
+    BlockRemoved, // Managed block was taken out of the file (no backup existed)
     NoBackup,    // No action taken, no backup found
 }
 
-fn undo_single_operation_entry(op_entry: &OperationEntry, operation_type: &OperationType) -> Result<UndoResult, PollenError> {
+/// How undoing an operation entry with its hooks went
+enum UndoOutcome {
+    /// The entry was reverted, along with the result of its after hooks
+    Done(UndoResult, Result<(), PollenError>),
+    /// A before hook failed, so the entry was left as it is
+    Skipped(PollenError),
+}
+
+fn tracked_entries(dirs: &PollenDirs) -> Result<Vec<Entry>, PollenError> {
+    let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
+    parser.parse_files(&track_files(dirs, &config)?)
+}
+
+/// Undo an operation entry, running the entry's `before_undo` and `after_undo` hooks around it.
+///
+/// Errors are failures to revert the entry; hook failures are part of the outcome.
+fn undo_with_hooks(op_entry: &OperationEntry, entry: Option<&Entry>, operation_type: &OperationType, dirs: &PollenDirs, verbose: bool) -> Result<UndoOutcome, PollenError> {
+    let Some(entry) = entry else {
+        let result = undo_single_operation_entry(op_entry, operation_type, dirs, verbose)?;
+        return Ok(UndoOutcome::Done(result, Ok(())));
+    };
+    
+    // Undo copies the backup back over what the operation wrote
+    let target = match operation_type {
+        OperationType::Gather => &op_entry.target_path,
+        OperationType::Scatter | OperationType::Restore => &op_entry.source_path,
+    };
+    let hook_context = HookContext {
+        entry,
+        operation: HookOperation::Undo,
+        source: Path::new(op_entry.backup_path.as_deref().unwrap_or("")),
+        target: Path::new(target),
+        files_dir: &dirs.files_dir,
+    };
+    
+    if let Err(e) = run_before_hooks(&hook_context) {
+        return Ok(UndoOutcome::Skipped(e));
+    }
+    let result = undo_single_operation_entry(op_entry, operation_type, dirs, verbose)?;
+    
+    Ok(UndoOutcome::Done(result, run_after_hooks(&hook_context)))
+}
+
+fn undo_single_operation_entry(op_entry: &OperationEntry, operation_type: &OperationType, dirs: &PollenDirs, verbose: bool) -> Result<UndoResult, PollenError> {
     match operation_type {
         OperationType::Gather => {
             // For gather operations, we need to:
@@ -83,13 +168,9 @@ fn undo_single_operation_entry(op_entry: &OperationEntry, operation_type: &Opera
             
             let target_path = Path::new(&op_entry.target_path);
             
-            // Remove the gathered file
-            if target_path.exists() {
-                if target_path.is_dir() {
-                    fs::remove_dir_all(target_path).map_err(PollenError::Io)?;
-                } else {
-                    fs::remove_file(target_path).map_err(PollenError::Io)?;
-                }
+            // Move the gathered file to the trash
+            if target_path.symlink_metadata().is_ok() {
+                Trash::new(dirs)?.move_to_trash(target_path)?;
             }
             
             // Restore backup if it exists
@@ -101,11 +182,7 @@ fn undo_single_operation_entry(op_entry: &OperationEntry, operation_type: &Opera
                         fs::create_dir_all(target_parent).map_err(PollenError::Io)?;
                     }
                     
-                    if backup_path.is_dir() {
-                        copy_dir_all(backup_path, target_path)?;
-                    } else {
-                        fs::copy(backup_path, target_path).map_err(PollenError::Io)?;
-                    }
+                    copy_path(backup_path, target_path)?.print_verbose(verbose, "Copied");
                     
                     // Remove the backup file
                     if backup_path.is_dir() {
@@ -123,20 +200,21 @@ fn undo_single_operation_entry(op_entry: &OperationEntry, operation_type: &Opera
             Ok(UndoResult::Removed)
         }
         
-        OperationType::Scatter => {
-            // For scatter operations, we need to:
+        OperationType::Scatter | OperationType::Restore => {
+            // For scatter and restore operations, we need to:
             // 1. Remove the scattered file from the target location (source_path in the op_entry represents the original target)
             // 2. Restore the backup if it exists
             
             let target_path = Path::new(&op_entry.source_path); // This is actually the target location for scatter
             
-            // Remove the scattered file
-            if target_path.exists() {
-                if target_path.is_dir() {
-                    fs::remove_dir_all(target_path).map_err(PollenError::Io)?;
-                } else {
-                    fs::remove_file(target_path).map_err(PollenError::Io)?;
-                }
+            // Only the block was replaced, so only the block is put back
+            if op_entry.managed_block {
+                return undo_block(op_entry, target_path, dirs);
+            }
+            
+            // Move the scattered file to the trash
+            if target_path.symlink_metadata().is_ok() {
+                Trash::new(dirs)?.move_to_trash(target_path)?;
             }
             
             // Restore backup if it exists
@@ -148,11 +226,7 @@ fn undo_single_operation_entry(op_entry: &OperationEntry, operation_type: &Opera
                         fs::create_dir_all(target_parent).map_err(PollenError::Io)?;
                     }
                     
-                    if backup_path.is_dir() {
-                        copy_dir_all(backup_path, target_path)?;
-                    } else {
-                        fs::copy(backup_path, target_path).map_err(PollenError::Io)?;
-                    }
+                    copy_path(backup_path, target_path)?.print_verbose(verbose, "Copied");
                     
                     // Remove the backup file
                     if backup_path.is_dir() {
@@ -171,3 +245,42 @@ fn undo_single_operation_entry(op_entry: &OperationEntry, operation_type: &Opera
         }
     }
 }
+
+/// Put back the managed block an operation replaced, or take out the block it added
+fn undo_block(op_entry: &OperationEntry, target_path: &Path, dirs: &PollenDirs) -> Result<UndoResult, PollenError> {
+    let block_error = |reason: String| PollenError::InvalidEndpoint(format!(
+        "Managed block in {}: {}", target_path.display(), reason
+    ));
+    let text = match fs::read_to_string(target_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(PollenError::Io(e)),
+    };
+    
+    match &op_entry.backup_path {
+        Some(backup_path_str) => {
+            let backup_path = Path::new(backup_path_str);
+            if !backup_path.exists() {
+                return Ok(UndoResult::NoBackup);
+            }
+            
+            let previous = fs::read_to_string(backup_path).map_err(PollenError::Io)?;
+            let text = block::replace(&text, &op_entry.entry_name, &previous).map_err(block_error)?;
+            fs::write(target_path, text).map_err(PollenError::Io)?;
+            fs::remove_file(backup_path).map_err(PollenError::Io)?;
+            Ok(UndoResult::Restored)
+        }
+        None => {
+            let text = block::remove(&text, &op_entry.entry_name).map_err(block_error)?;
+            if text.is_empty() {
+                // The file held nothing but the block, so it was created for it
+                if target_path.symlink_metadata().is_ok() {
+                    Trash::new(dirs)?.move_to_trash(target_path)?;
+                }
+            } else {
+                fs::write(target_path, text).map_err(PollenError::Io)?;
+            }
+            Ok(UndoResult::BlockRemoved)
+        }
+    }
+}
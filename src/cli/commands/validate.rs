@@ -1,28 +1,27 @@
-use crate::{ConfigParser, PollenError, PollenDirs};
+use crate::{ConfigParser, HostFacts, PollenError, PollenDirs};
+use crate::cli::utils::track_files;
 use seahorse::Context;
 
-pub fn validate_config(c: &Context) -> Result<(), PollenError> {
-    let config_file = c.string_flag("config").ok();
-    
-    let parser = ConfigParser::new()?;
+pub fn validate_config(_c: &Context) -> Result<(), PollenError> {
     let dirs = PollenDirs::new()?;
     let config = dirs.load_config()?;
+    let parser = ConfigParser::new()?.with_filters(&config);
     
-    let (config_path, used_config_file) = match config_file.as_deref() {
-        Some(path) => (path.to_string(), path.to_string()),
-        None => {
-            let track_file = dirs.get_track_file_path(&config);
-            let path_str = track_file.to_str().ok_or(PollenError::InvalidEndpoint("Invalid config file path".into()))?;
-            (path_str.to_string(), track_file.display().to_string())
-        }
-    };
+    let config_paths = track_files(&dirs, &config)?;
     
-    let entries = parser.parse_file(&config_path)?;
+    let entries = parser.parse_files(&config_paths)?;
     
     // Validation is done during parsing, so if we get here, it's valid
-    println!("Configuration file '{}' is valid!", used_config_file);
+    println!("Configuration file '{}' is valid!", config_paths.join("', '"));
     println!("Found {} entries", entries.len());
     
+    // Conditions are checked for every entry, including the ones skipped here
+    let facts = HostFacts::detect();
+    let skipped = entries.iter().filter(|entry| !entry.is_enabled(&facts)).count();
+    if skipped > 0 {
+        println!("Skipped on this machine: {}", skipped);
+    }
+    
     let aliases = parser.list_aliases(&entries);
     if !aliases.is_empty() {
         println!("Found {} aliases", aliases.len());
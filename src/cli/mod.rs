@@ -2,5 +2,7 @@ pub mod app;
 pub mod actions;
 pub mod commands;
 pub mod utils;
+pub mod selection;
+pub mod pipeline;
 
 pub use app::run;
@@ -0,0 +1,462 @@
+//! Staging shared by the commands that deploy, capture or compare entries: each step copies an
+//! entry into the staging directory transformed, and `deploy_entry` puts the result on the system.
+
+use crate::{Entry, HostFacts, PollenDirs, PollenError, HookOperation, Trash};
+use crate::alternate::stored_name;
+use crate::assemble::Assembly;
+use crate::block;
+use crate::crypt::{is_encrypted, write_private, Cipher};
+use crate::filter::{filter_commands, filter_path, FilterDirection};
+use crate::merge::Merge;
+use crate::secret::{conceal_path, path_placeholders, resolve_path, Secrets};
+use crate::overlay::{apply_layers, Layer, OVERLAYS_DIR};
+use crate::cli::utils::{run_after_hooks, run_before_hooks, copy_path, remove_path, HookContext};
+use crate::template::render_path;
+use serde_yaml::Mapping;
+use std::{fs, os::unix::fs::PermissionsExt, path::{Path, PathBuf}};
+
+/// Directory that templates are rendered into before being deployed or compared
+pub fn render_staging_dir(dirs: &PollenDirs) -> PathBuf {
+    dirs.cache_dir.join(format!("render-{}", std::process::id()))
+}
+
+/// Render a templated entry from `source_path` into the staging directory, returning the rendered path
+pub fn render_entry(entry: &Entry, source_path: &Path, variables: &Mapping, staging_dir: &Path) -> Result<PathBuf, PollenError> {
+    let rendered_path = staging_dir.join(stored_name(entry));
+    
+    if rendered_path.is_dir() {
+        fs::remove_dir_all(&rendered_path).map_err(PollenError::Io)?;
+    } else if rendered_path.exists() {
+        fs::remove_file(&rendered_path).map_err(PollenError::Io)?;
+    }
+    
+    render_path(source_path, &rendered_path, variables)?;
+    Ok(rendered_path)
+}
+
+/// Assemble an entry from its fragments in `files_dir` into the staging directory, returning the
+/// assembled path
+pub fn assemble_entry(
+    entry: &Entry,
+    assembly: &Assembly,
+    files_dir: &Path,
+    facts: &HostFacts,
+    staging_dir: &Path,
+) -> Result<PathBuf, PollenError> {
+    let assembled_path = staging_dir.join("assembled").join(stored_name(entry));
+    if let Some(parent) = assembled_path.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    
+    fs::write(&assembled_path, assembly.assemble(files_dir, facts)?).map_err(PollenError::Io)?;
+    Ok(assembled_path)
+}
+
+/// Decrypt an encrypted entry's stored copy into the staging directory, returning the decrypted path.
+///
+/// A stored copy that is not encrypted yet is used as it is; the next gather encrypts it. The
+/// plaintext is only readable by its owner; `deploy_entry` gives the target the stored copy's mode.
+pub fn decrypt_entry(entry: &Entry, source_path: &Path, dirs: &PollenDirs, staging_dir: &Path) -> Result<PathBuf, PollenError> {
+    if !source_path.is_file() {
+        return Err(PollenError::Encryption(format!(
+            "{} uses encrypt, so its stored copy must be a single file", entry.name
+        )));
+    }
+    
+    let content = fs::read(source_path).map_err(PollenError::Io)?;
+    let plaintext = if is_encrypted(&content) {
+        Cipher::from_config(dirs, &dirs.load_config()?)?.decrypt(&content)?
+    } else {
+        content
+    };
+    
+    let decrypted_path = staging_dir.join("decrypted").join(stored_name(entry));
+    if let Some(parent) = decrypted_path.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    write_private(&decrypted_path, &plaintext)?;
+    Ok(decrypted_path)
+}
+
+/// Encrypt what gather takes from `system_path` into the staging directory, returning the encrypted path.
+///
+/// Encryption is salted, so the stored ciphertext is kept when it still decrypts to the same
+/// content; otherwise every gather would change the repository. The ciphertext gets the system
+/// file's mode, so scatter can give it back.
+pub fn encrypt_entry(
+    entry: &Entry,
+    system_path: &Path,
+    stored_path: &Path,
+    dirs: &PollenDirs,
+    staging_dir: &Path,
+) -> Result<PathBuf, PollenError> {
+    if !system_path.is_file() {
+        return Err(PollenError::Encryption(format!(
+            "{} uses encrypt, so it must be a single file", entry.name
+        )));
+    }
+    
+    let cipher = Cipher::from_config(dirs, &dirs.load_config()?)?;
+    let plaintext = fs::read(system_path).map_err(PollenError::Io)?;
+    let stored = fs::read(stored_path).ok().filter(|stored| is_encrypted(stored));
+    let unchanged = match &stored {
+        Some(stored) => cipher.decrypt(stored).is_ok_and(|previous| previous == plaintext),
+        None => false,
+    };
+    let ciphertext = match stored {
+        Some(stored) if unchanged => stored,
+        _ => cipher.encrypt(&plaintext)?,
+    };
+    
+    let encrypted_path = staging_dir.join("encrypted").join(stored_name(entry));
+    if let Some(parent) = encrypted_path.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    fs::write(&encrypted_path, ciphertext).map_err(PollenError::Io)?;
+    
+    let permissions = fs::metadata(system_path).map_err(PollenError::Io)?.permissions();
+    fs::set_permissions(&encrypted_path, permissions).map_err(PollenError::Io)?;
+    Ok(encrypted_path)
+}
+
+/// Resolve the secret placeholders of an entry into the staging directory, returning the resolved
+/// path, or None when the entry has no placeholders
+pub fn resolve_secrets(entry: &Entry, source_path: &Path, dirs: &PollenDirs, staging_dir: &Path) -> Result<Option<PathBuf>, PollenError> {
+    if path_placeholders(source_path).is_empty() {
+        return Ok(None);
+    }
+    
+    let secrets = Secrets::from_config(dirs, &dirs.load_config()?)?;
+    let resolved_path = staging_dir.join("resolved").join(stored_name(entry));
+    clear_staged(&resolved_path)?;
+    resolve_path(source_path, &resolved_path, &secrets)?;
+    Ok(Some(resolved_path))
+}
+
+/// Stage what is on the system at `system_path` with the secret values behind the placeholders of
+/// `reference_path` turned back into placeholders, returning the concealed path, or None when
+/// there is nothing to conceal
+pub fn conceal_secrets(
+    entry: &Entry,
+    system_path: &Path,
+    reference_path: Option<&Path>,
+    dirs: &PollenDirs,
+    staging_dir: &Path,
+) -> Result<Option<PathBuf>, PollenError> {
+    if !system_path.exists() {
+        return Ok(None);
+    }
+    
+    let placeholders = reference_path.map(path_placeholders).unwrap_or_default();
+    if placeholders.is_empty() {
+        return Ok(None);
+    }
+    
+    let secrets = Secrets::from_config(dirs, &dirs.load_config()?)?;
+    let concealed_path = staging_dir.join("concealed").join(stored_name(entry));
+    clear_staged(&concealed_path)?;
+    conceal_path(system_path, &concealed_path, &secrets, &placeholders)?;
+    Ok(Some(concealed_path))
+}
+
+/// Pass an entry's content at `path` through its filters into the staging directory, returning the
+/// filtered path, or None when none of its filters has a command for `direction`
+pub fn filter_entry(
+    entry: &Entry,
+    path: &Path,
+    direction: FilterDirection,
+    dirs: &PollenDirs,
+    staging_dir: &Path,
+) -> Result<Option<PathBuf>, PollenError> {
+    if entry.filters.is_empty() || !path.exists() {
+        return Ok(None);
+    }
+    
+    let commands = filter_commands(&entry.filters, direction, &dirs.load_config()?)?;
+    if commands.is_empty() {
+        return Ok(None);
+    }
+    
+    let stage = match direction {
+        FilterDirection::Clean => "cleaned",
+        FilterDirection::Smudge => "smudged",
+    };
+    let filtered_path = staging_dir.join(stage).join(stored_name(entry));
+    clear_staged(&filtered_path)?;
+    filter_path(path, &filtered_path, &commands)?;
+    Ok(Some(filtered_path))
+}
+
+fn clear_staged(path: &Path) -> Result<(), PollenError> {
+    if path.is_dir() {
+        fs::remove_dir_all(path).map_err(PollenError::Io)?;
+    } else if path.exists() {
+        fs::remove_file(path).map_err(PollenError::Io)?;
+    }
+    Ok(())
+}
+
+/// Layer an entry's overlays over `source_path` in the staging directory, returning the layered path
+pub fn layer_entry(entry: &Entry, source_path: &Path, layers: &[Layer], staging_dir: &Path) -> Result<PathBuf, PollenError> {
+    let layered_path = staging_dir.join(OVERLAYS_DIR).join(stored_name(entry));
+    apply_layers(source_path, layers, &layered_path)?;
+    Ok(layered_path)
+}
+
+/// Copy `source_path` to the entry's target location, backing up whatever is there and running hooks.
+///
+/// `origin` is where the content came from before any staging, such as the stored copy, and is
+/// what hooks see as their source. `operation` is the command deploying it, scatter or restore.
+pub fn deploy_entry(
+    entry: &Entry,
+    source_path: &Path,
+    origin: &Path,
+    operation: HookOperation,
+    dirs: &PollenDirs,
+    verbose: bool,
+) -> Result<(bool, bool, Option<String>), PollenError> {
+    let target_path = &entry.path;
+    let hook_context = HookContext {
+        entry,
+        operation,
+        source: origin,
+        target: target_path,
+        files_dir: &dirs.files_dir,
+    };
+    
+    run_before_hooks(&hook_context)?;
+    
+    // A merge that changes no key leaves the file alone, without a backup
+    let merged = match &entry.merge {
+        Some(merge) => match merged_text(entry, merge, source_path)? {
+            Some(text) => Some(text),
+            None => {
+                run_after_hooks(&hook_context)?;
+                return Ok((false, false, None));
+            }
+        },
+        None => None,
+    };
+    
+    let previous_mode = fs::metadata(target_path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.permissions().mode());
+    
+    // If target already exists, back it up
+    let backup_path_str = backup_target(entry, dirs, verbose)?;
+    let backed_up = backup_path_str.is_some();
+    
+    // Ensure target directory exists
+    if let Some(target_parent) = target_path.parent() {
+        fs::create_dir_all(target_parent)
+            .map_err(PollenError::Io)?;
+    }
+    
+    if entry.managed_block {
+        // The rest of the file belongs to other tools, so only the block is rewritten in place
+        write_block(entry, source_path)?;
+        if verbose {
+            println!("  → Updated block pollen:{} in {}", entry.name, target_path.display());
+        }
+    } else if let (Some(merge), Some(text)) = (&entry.merge, merged) {
+        // Keys the stored document does not set stay as the application left them
+        fs::write(target_path, text).map_err(PollenError::Io)?;
+        if verbose {
+            println!("  → Merged {} keys into {}", merge.format, target_path.display());
+        }
+    } else {
+        // The backup keeps what was at the target; anything not backed up, such as a dangling
+        // symlink, goes to the trash instead
+        if backed_up {
+            remove_path(target_path)?;
+        } else if target_path.symlink_metadata().is_ok() {
+            Trash::new(dirs)?.move_to_trash(target_path)?;
+        }
+        
+        // Copy source to target
+        copy_path(source_path, target_path)?.print_verbose(verbose, "Copied");
+        
+        // Decrypted plaintext and resolved secrets are staged private, so the target takes the
+        // stored copy's mode, but never more than it gave others before
+        if entry.encrypt || !path_placeholders(origin).is_empty() {
+            give_stored_modes(origin, target_path, previous_mode)?;
+        }
+    }
+    
+    run_after_hooks(&hook_context)?;
+    
+    Ok((true, backed_up, backup_path_str))
+}
+
+/// Give the files deployed at `target` the modes of their stored copies under `origin`. Group and
+/// other bits are limited to those of `previous`, the mode the target file had before.
+fn give_stored_modes(origin: &Path, target: &Path, previous: Option<u32>) -> Result<(), PollenError> {
+    if origin.is_dir() && target.is_dir() {
+        for child in fs::read_dir(target).map_err(PollenError::Io)? {
+            let child = child.map_err(PollenError::Io)?;
+            give_stored_modes(&origin.join(child.file_name()), &child.path(), None)?;
+        }
+        return Ok(());
+    }
+    
+    // Files with no stored copy to take a mode from stay private
+    if !origin.is_file() || !target.is_file() {
+        return Ok(());
+    }
+    
+    let mode = fs::metadata(origin).map_err(PollenError::Io)?.permissions().mode();
+    let mode = match previous {
+        Some(previous) => mode & (previous | 0o700),
+        None => mode,
+    };
+    fs::set_permissions(target, fs::Permissions::from_mode(mode)).map_err(PollenError::Io)
+}
+
+/// Back up the entry's current target location, returning the backup path if anything was there.
+///
+/// For managed blocks only the block is backed up, since that is all a deployment replaces.
+pub fn backup_target(entry: &Entry, dirs: &PollenDirs, verbose: bool) -> Result<Option<String>, PollenError> {
+    let target_path = &entry.path;
+    
+    let block = if entry.managed_block { read_block(entry)? } else { None };
+    if !target_path.exists() || (entry.managed_block && block.is_none()) {
+        return Ok(None);
+    }
+    
+    let backup_path = dirs.new_backup_file_path(&stored_name(entry))?;
+    
+    // Ensure backup directory exists
+    if let Some(backup_parent) = backup_path.parent() {
+        fs::create_dir_all(backup_parent)
+            .map_err(PollenError::Io)?;
+    }
+    
+    match block {
+        Some(block) => {
+            fs::write(&backup_path, block).map_err(PollenError::Io)?;
+            if verbose {
+                println!("  → Backed up block pollen:{} to {}", entry.name, backup_path.display());
+            }
+        }
+        None => copy_path(target_path, &backup_path)?.print_verbose(verbose, "Backed up"),
+    }
+    
+    Ok(Some(backup_path.display().to_string()))
+}
+
+/// The entry's managed block on the system, or None when the target file or the block is missing
+pub fn read_block(entry: &Entry) -> Result<Option<String>, PollenError> {
+    match read_target_text(entry)? {
+        Some(text) => block::extract(&text, &entry.name).map_err(|reason| block_error(entry, reason)),
+        None => Ok(None),
+    }
+}
+
+/// Set the entry's managed block to the content of `source_path`, leaving the rest of the target file alone
+pub fn write_block(entry: &Entry, source_path: &Path) -> Result<(), PollenError> {
+    if !source_path.is_file() {
+        return Err(PollenError::InvalidEndpoint(format!(
+            "{} uses managed_block, so its stored copy must be a single file", entry.name
+        )));
+    }
+    
+    let content = fs::read_to_string(source_path).map_err(PollenError::Io)?;
+    let text = read_target_text(entry)?.unwrap_or_default();
+    let text = block::replace(&text, &entry.name, &content).map_err(|reason| block_error(entry, reason))?;
+    fs::write(&entry.path, text).map_err(PollenError::Io)
+}
+
+/// Take the entry's managed block out of its target file, leaving the rest of the file alone
+pub fn remove_block(entry: &Entry) -> Result<(), PollenError> {
+    if let Some(text) = read_target_text(entry)? {
+        let text = block::remove(&text, &entry.name).map_err(|reason| block_error(entry, reason))?;
+        fs::write(&entry.path, text).map_err(PollenError::Io)?;
+    }
+    Ok(())
+}
+
+/// Copy the entry's managed block on the system into the staging directory, returning its path,
+/// or None when the target file or the block is missing
+pub fn stage_block(entry: &Entry, staging_dir: &Path) -> Result<Option<PathBuf>, PollenError> {
+    let Some(block) = read_block(entry)? else {
+        return Ok(None);
+    };
+    
+    let block_path = staging_dir.join("blocks").join(stored_name(entry));
+    if let Some(parent) = block_path.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    fs::write(&block_path, block).map_err(PollenError::Io)?;
+    Ok(Some(block_path))
+}
+
+/// The entry's target file with the stored document at `source_path` merged in, or None when
+/// merging would not change it
+fn merged_text(entry: &Entry, merge: &Merge, source_path: &Path) -> Result<Option<String>, PollenError> {
+    if !source_path.is_file() {
+        return Err(PollenError::InvalidEndpoint(format!(
+            "{} uses merge, so its stored copy must be a single file", entry.name
+        )));
+    }
+    
+    let stored = fs::read_to_string(source_path).map_err(PollenError::Io)?;
+    let target = read_target_text(entry)?;
+    merge.merge(target.as_deref(), &stored).map_err(|reason| merge_error(entry, merge, reason))
+}
+
+/// Write the keys pollen manages from the entry's target file to the staging directory, returning
+/// its path, or None when the target is missing. Without a stored copy every key is managed.
+pub fn stage_captured(entry: &Entry, merge: &Merge, stored_path: &Path, staging_dir: &Path) -> Result<Option<PathBuf>, PollenError> {
+    let Some(system) = read_target_text(entry)? else {
+        return Ok(None);
+    };
+    
+    let stored = if stored_path.is_file() {
+        Some(fs::read_to_string(stored_path).map_err(PollenError::Io)?)
+    } else {
+        None
+    };
+    let captured = merge.capture(&system, stored.as_deref()).map_err(|reason| merge_error(entry, merge, reason))?;
+    
+    let captured_path = staging_dir.join("merged").join(stored_name(entry));
+    if let Some(parent) = captured_path.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    fs::write(&captured_path, captured).map_err(PollenError::Io)?;
+    Ok(Some(captured_path))
+}
+
+/// Write the stored document laid out the way `stage_captured` lays out the system, for comparing them
+pub fn stage_normalized(entry: &Entry, merge: &Merge, stored_path: &Path, staging_dir: &Path) -> Result<PathBuf, PollenError> {
+    let stored = fs::read_to_string(stored_path).map_err(PollenError::Io)?;
+    let normalized = merge.normalize(&stored).map_err(|reason| merge_error(entry, merge, reason))?;
+    
+    let normalized_path = staging_dir.join("merged-expected").join(stored_name(entry));
+    if let Some(parent) = normalized_path.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    fs::write(&normalized_path, normalized).map_err(PollenError::Io)?;
+    Ok(normalized_path)
+}
+
+fn read_target_text(entry: &Entry) -> Result<Option<String>, PollenError> {
+    if entry.path.symlink_metadata().is_err() {
+        return Ok(None);
+    }
+    if entry.path.is_dir() {
+        let option = if entry.managed_block { "managed_block" } else { "merge" };
+        return Err(PollenError::InvalidEndpoint(format!(
+            "{} uses {}, but {} is a directory", entry.name, option, entry.path.display()
+        )));
+    }
+    fs::read_to_string(&entry.path).map(Some).map_err(PollenError::Io)
+}
+
+fn merge_error(entry: &Entry, merge: &Merge, reason: String) -> PollenError {
+    PollenError::InvalidEndpoint(format!("Cannot merge {} as {} {}", entry.path.display(), merge.format, reason))
+}
+
+fn block_error(entry: &Entry, reason: String) -> PollenError {
+    PollenError::InvalidEndpoint(format!("Managed block in {}: {}", entry.path.display(), reason))
+}
@@ -0,0 +1,485 @@
+use crate::{ConfigParser, Entry, PollenConfig, PollenDirs, PollenError, Profiles, TagExpression};
+use crate::profile::add_dependencies;
+use seahorse::Context;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Component, Path, PathBuf},
+    sync::Once,
+};
+
+/// Guards the default profile notice, which is printed at most once per run
+static DEFAULT_PROFILE_NOTICE: Once = Once::new();
+
+/// Why an entry that was not selected is operated on anyway
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PulledIn {
+    /// A selected entry depends on it (`--with-deps`)
+    Dependency,
+    /// It depends on a selected entry (`--with-dependents`)
+    Dependent,
+}
+
+impl fmt::Display for PulledIn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PulledIn::Dependency => write!(f, "dependency"),
+            PulledIn::Dependent => write!(f, "dependent"),
+        }
+    }
+}
+
+/// Load profiles from pollen.yaml and the track files
+pub fn load_profiles(parser: &ConfigParser, config: &PollenConfig, config_paths: &[String]) -> Result<Profiles, PollenError> {
+    Profiles::from_sources(vec![
+        config.profiles.clone().unwrap_or_default(),
+        parser.parse_profiles_files(config_paths)?,
+    ])
+}
+
+/// The profile in effect: `--profile`, or the default profile from local.yaml
+pub fn active_profile(c: &Context, dirs: &PollenDirs) -> Result<Option<String>, PollenError> {
+    match c.string_flag("profile").ok() {
+        Some(profile) => Ok(Some(profile)),
+        None => Ok(dirs.load_local_config()?.profile),
+    }
+}
+
+/// Narrow parsed entries down to what the command line asked for.
+///
+/// `--profile` selects a profile; without it, selectors or tag filters, the default profile
+/// from local.yaml is used. `--tag`/`--exclude-tag` and the selectors given as arguments
+/// (see `resolve_selectors`) narrow the result further.
+pub fn select_entries(
+    c: &Context,
+    parser: &ConfigParser,
+    dirs: &PollenDirs,
+    config: &PollenConfig,
+    config_paths: &[String],
+    entries: Vec<Entry>,
+) -> Result<Vec<Entry>, PollenError> {
+    let target_entries: Vec<String> = c.args.clone();
+    let all_entries = entries.clone();
+    let tag_filtered = c.string_flag("tag").is_ok() || c.string_flag("exclude-tag").is_ok();
+
+    let profile = match c.string_flag("profile").ok() {
+        Some(profile) => Some(profile),
+        None if target_entries.is_empty() && !tag_filtered => {
+            let default = dirs.load_local_config()?.profile;
+            if let Some(profile) = &default {
+                DEFAULT_PROFILE_NOTICE.call_once(|| {
+                    println!("Using default profile '{}' from {}", profile, dirs.local_config_file.display());
+                });
+            }
+            default
+        }
+        None => None,
+    };
+
+    // Tags are checked against every entry, so a tag outside the profile is not reported as unknown
+    let tagged: Option<HashSet<String>> = if tag_filtered {
+        Some(filter_by_tags(c, entries.clone())?.into_iter().map(|entry| entry.name).collect())
+    } else {
+        None
+    };
+
+    let entries = match profile {
+        Some(profile) => load_profiles(parser, config, config_paths)?.select(&profile, entries)?,
+        None => entries,
+    };
+
+    let entries: Vec<Entry> = match tagged {
+        Some(tagged) => entries.into_iter().filter(|entry| tagged.contains(&entry.name)).collect(),
+        None => entries,
+    };
+
+    if target_entries.is_empty() {
+        return Ok(entries);
+    }
+
+    let selected = resolve_selectors(&target_entries, &all_entries, parser.home_dir())?;
+    Ok(entries.into_iter().filter(|entry| selected.contains(&entry.name)).collect())
+}
+
+/// Extend a selection with `--with-deps` (or `with_deps` in pollen.yaml) and `--with-dependents`.
+///
+/// Returns the entries in the order of `all_entries` together with the ones that were added.
+/// Dependents are added first, so their own dependencies are pulled in as well. Entries excluded
+/// with `!selector` or `--exclude-tag` stay out even when something selected depends on them.
+pub fn add_related_entries(
+    c: &Context,
+    parser: &ConfigParser,
+    config: &PollenConfig,
+    selected: Vec<Entry>,
+    all_entries: Vec<Entry>,
+) -> Result<(Vec<Entry>, HashMap<String, PulledIn>), PollenError> {
+    let with_deps = c.bool_flag("with-deps") || config.with_deps.unwrap_or(false);
+    let with_dependents = c.bool_flag("with-dependents");
+    if !with_deps && !with_dependents {
+        return Ok((selected, HashMap::new()));
+    }
+
+    let explicit: HashSet<String> = selected.iter().map(|entry| entry.name.clone()).collect();
+    let mut names = explicit.clone();
+    let mut pulled_in = HashMap::new();
+
+    if with_dependents {
+        add_dependents(&mut names, &all_entries);
+        for name in names.difference(&explicit) {
+            pulled_in.insert(name.clone(), PulledIn::Dependent);
+        }
+    }
+    if with_deps {
+        add_dependencies(&mut names, &all_entries);
+        for name in names.difference(&explicit) {
+            pulled_in.entry(name.clone()).or_insert(PulledIn::Dependency);
+        }
+    }
+
+    for name in excluded_entries(c, &all_entries, parser.home_dir())? {
+        if pulled_in.remove(&name).is_some() {
+            names.remove(&name);
+        }
+    }
+
+    let entries = all_entries.into_iter().filter(|entry| names.contains(&entry.name)).collect();
+    Ok((entries, pulled_in))
+}
+
+/// Add every entry that transitively depends on the selected entries, optionally or not
+pub fn add_dependents(selected: &mut HashSet<String>, entries: &[Entry]) {
+    let mut pending: Vec<String> = selected.iter().cloned().collect();
+
+    while let Some(name) = pending.pop() {
+        let Some(entry) = entries.iter().find(|entry| entry.name == name) else {
+            continue;
+        };
+
+        let dependents = entries.iter().filter(|other| {
+            other.depends_on.iter().chain(&other.optional_depends_on).any(|d| entry.matches_name(d))
+        });
+        for dependent in dependents {
+            if selected.insert(dependent.name.clone()) {
+                pending.push(dependent.name.clone());
+            }
+        }
+    }
+}
+
+/// Entries the command line excludes explicitly, with `!selector` or `--exclude-tag`
+fn excluded_entries(c: &Context, entries: &[Entry], home_dir: &Path) -> Result<HashSet<String>, PollenError> {
+    let mut excluded: HashSet<String> = c
+        .args
+        .iter()
+        .filter_map(|selector| selector.strip_prefix('!'))
+        .flat_map(|pattern| match_selector(pattern, entries, home_dir))
+        .collect();
+
+    if let Ok(expression) = c.string_flag("exclude-tag") {
+        let expression = TagExpression::parse(&expression)?;
+        excluded.extend(
+            entries
+                .iter()
+                .filter(|entry| expression.matches(&entry.tags))
+                .map(|entry| entry.name.clone()),
+        );
+    }
+
+    Ok(excluded)
+}
+
+/// Suffix marking an entry that was pulled in rather than selected, empty otherwise
+pub fn pulled_in_note(entry: &Entry, pulled_in: &HashMap<String, PulledIn>) -> String {
+    pulled_in
+        .get(&entry.name)
+        .map(|reason| format!(" (pulled in as {})", reason))
+        .unwrap_or_default()
+}
+
+/// Refuse a set of entries containing two that conflict with each other
+pub fn check_conflicts(entries: &[Entry]) -> Result<(), PollenError> {
+    for entry in entries {
+        for name in &entry.conflicts_with {
+            if let Some(other) = entries.iter().find(|other| other.matches_name(name)) {
+                return Err(PollenError::Conflict(format!(
+                    "'{}' and '{}' cannot be scattered together; select only one of them",
+                    entry.get_display_name(),
+                    other.get_display_name()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolve positional selectors to entry names.
+///
+/// A selector is an entry name or alias, a glob over names and aliases (`nvim*`), a path
+/// relative to home (`.config/`) or absolute (`~/.zshrc`) selecting the entries at, below or
+/// containing it, or any of these prefixed with `!` to exclude. Only exclusions select everything
+/// else. A selector matching nothing is an error that lists close matches.
+pub fn resolve_selectors(selectors: &[String], entries: &[Entry], home_dir: &Path) -> Result<HashSet<String>, PollenError> {
+    let mut included: Option<HashSet<String>> = None;
+    let mut excluded = HashSet::new();
+
+    for selector in selectors {
+        let (negated, pattern) = match selector.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, selector.as_str()),
+        };
+
+        let matched = match_selector(pattern, entries, home_dir);
+        if matched.is_empty() {
+            return Err(no_match_error(pattern, entries));
+        }
+
+        if negated {
+            excluded.extend(matched);
+        } else {
+            included.get_or_insert_with(HashSet::new).extend(matched);
+        }
+    }
+
+    let mut selected = included.unwrap_or_else(|| entries.iter().map(|entry| entry.name.clone()).collect());
+    selected.retain(|name| !excluded.contains(name));
+    Ok(selected)
+}
+
+fn match_selector(pattern: &str, entries: &[Entry], home_dir: &Path) -> HashSet<String> {
+    // Exact names and aliases win, so an entry called `.config` is not read as a path
+    let exact: HashSet<String> = entries
+        .iter()
+        .filter(|entry| entry.matches_name(pattern) || entry.get_display_name() == pattern)
+        .map(|entry| entry.name.clone())
+        .collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+
+    if pattern.contains(['*', '?']) {
+        return entries
+            .iter()
+            .filter(|entry| {
+                glob_matches(pattern, &entry.name) ||
+                entry.alias_as.as_deref().is_some_and(|alias| glob_matches(pattern, alias))
+            })
+            .map(|entry| entry.name.clone())
+            .collect();
+    }
+
+    let path = normalize(&resolve_path(pattern, home_dir));
+    entries
+        .iter()
+        .filter(|entry| {
+            let entry_path = normalize(&entry.path);
+            entry_path.starts_with(&path) || path.starts_with(&entry_path)
+        })
+        .map(|entry| entry.name.clone())
+        .collect()
+}
+
+/// Expand `~` and resolve relative selectors against the home directory
+fn resolve_path(pattern: &str, home_dir: &Path) -> PathBuf {
+    if pattern == "~" {
+        home_dir.to_path_buf()
+    } else if let Some(rest) = pattern.strip_prefix("~/") {
+        home_dir.join(rest)
+    } else if Path::new(pattern).is_absolute() {
+        PathBuf::from(pattern)
+    } else {
+        home_dir.join(pattern)
+    }
+}
+
+/// Drop `.` components and fold `..` so paths from the track file compare reliably
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Match a glob with `*` (any run of characters) and `?` (one character)
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn no_match_error(pattern: &str, entries: &[Entry]) -> PollenError {
+    let mut candidates: Vec<(usize, &str)> = entries
+        .iter()
+        .flat_map(|entry| std::iter::once(entry.name.as_str()).chain(entry.alias_as.as_deref()))
+        .filter_map(|name| {
+            let distance = edit_distance(&pattern.to_lowercase(), &name.to_lowercase());
+            let close = distance <= (pattern.chars().count() / 3).max(2) ||
+                name.to_lowercase().contains(&pattern.to_lowercase());
+            close.then_some((distance, name))
+        })
+        .collect();
+    candidates.sort();
+    candidates.dedup_by(|a, b| a.1 == b.1);
+
+    if candidates.is_empty() {
+        PollenError::InvalidOption(format!("No entry matches '{}'", pattern))
+    } else {
+        let suggestions: Vec<&str> = candidates.iter().take(5).map(|(_, name)| *name).collect();
+        PollenError::InvalidOption(format!(
+            "No entry matches '{}'. Did you mean: {}?", pattern, suggestions.join(", ")
+        ))
+    }
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Apply the `--tag` and `--exclude-tag` expressions, e.g. `--tag "shell or editor"`
+pub fn filter_by_tags(c: &Context, entries: Vec<Entry>) -> Result<Vec<Entry>, PollenError> {
+    let include = c.string_flag("tag").ok().map(|expression| TagExpression::parse(&expression)).transpose()?;
+    let exclude = c.string_flag("exclude-tag").ok().map(|expression| TagExpression::parse(&expression)).transpose()?;
+
+    // A tag no entry carries is almost certainly a typo
+    for expression in include.iter().chain(exclude.iter()) {
+        for tag in expression.tag_names() {
+            if !entries.iter().any(|entry| entry.tags.iter().any(|t| t == tag)) {
+                let mut known: Vec<&str> = entries.iter().flat_map(|entry| entry.tags.iter().map(String::as_str)).collect();
+                known.sort();
+                known.dedup();
+                return Err(PollenError::InvalidOption(if known.is_empty() {
+                    format!("Unknown tag '{}' (no entries are tagged)", tag)
+                } else {
+                    format!("Unknown tag '{}' (known tags: {})", tag, known.join(", "))
+                }));
+            }
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .filter(|entry| include.as_ref().is_none_or(|expression| expression.matches(&entry.tags)))
+        .filter(|entry| exclude.as_ref().is_none_or(|expression| !expression.matches(&entry.tags)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::EntryArgument;
+
+    fn entries() -> Vec<Entry> {
+        let home = Path::new("/home/user");
+        vec![
+            Entry::new(EntryArgument { name: ".zshrc".to_string(), path: home.join(".zshrc") }),
+            Entry::new(EntryArgument { name: "nvim".to_string(), path: home.join(".config/nvim") }).with_alias("editor".to_string()),
+            Entry::new(EntryArgument { name: "nvim-lsp".to_string(), path: home.join(".config/nvim/lsp") }),
+            Entry::new(EntryArgument { name: "i3".to_string(), path: home.join("./.config/i3") }),
+        ]
+    }
+
+    fn select(selectors: &[&str]) -> Result<Vec<String>, PollenError> {
+        let selectors: Vec<String> = selectors.iter().map(|selector| selector.to_string()).collect();
+        let selected = resolve_selectors(&selectors, &entries(), Path::new("/home/user"))?;
+        let mut names: Vec<String> = selected.into_iter().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn names_aliases_and_globs_select_entries() {
+        assert_eq!(select(&["editor"]).unwrap(), ["nvim"]);
+        assert_eq!(select(&["nvim*"]).unwrap(), ["nvim", "nvim-lsp"]);
+        assert_eq!(select(&["ed?tor", "i3"]).unwrap(), ["i3", "nvim"]);
+    }
+
+    #[test]
+    fn paths_select_entries_at_below_or_containing_them() {
+        assert_eq!(select(&[".config/"]).unwrap(), ["i3", "nvim", "nvim-lsp"]);
+        assert_eq!(select(&["~/.config/nvim/lsp/servers.lua"]).unwrap(), ["nvim", "nvim-lsp"]);
+        assert_eq!(select(&["/home/user/.config/i3"]).unwrap(), ["i3"]);
+    }
+
+    #[test]
+    fn exclusions_alone_select_everything_else() {
+        assert_eq!(select(&["!.config"]).unwrap(), [".zshrc"]);
+        assert_eq!(select(&["nvim*", "!nvim-lsp"]).unwrap(), ["nvim"]);
+    }
+
+    #[test]
+    fn selectors_matching_nothing_suggest_close_names() {
+        let error = select(&["nvm"]).unwrap_err();
+        assert!(error.to_string().contains("Did you mean: nvim"), "{}", error);
+        assert!(select(&["tmux*"]).unwrap_err().to_string().contains("No entry matches 'tmux*'"));
+    }
+
+    #[test]
+    fn globs_match_whole_names() {
+        assert!(glob_matches("*rc", ".zshrc"));
+        assert!(glob_matches("n*m*", "nvim-lsp"));
+        assert!(!glob_matches("nvim", "nvim-lsp"));
+        assert!(!glob_matches("?", ""));
+    }
+
+    #[test]
+    fn dependents_include_optional_ones_transitively() {
+        let mut all = entries();
+        all[2].optional_depends_on = vec!["editor".to_string()];
+        all[0].depends_on = vec!["nvim-lsp".to_string()];
+
+        let mut selected: HashSet<String> = HashSet::from(["nvim".to_string()]);
+        add_dependents(&mut selected, &all);
+        let mut names: Vec<String> = selected.into_iter().collect();
+        names.sort();
+        assert_eq!(names, [".zshrc", "nvim", "nvim-lsp"]);
+    }
+
+    #[test]
+    fn conflicting_entries_can_not_be_selected_together() {
+        let mut all = entries();
+        all[3].conflicts_with = vec!["editor".to_string()];
+
+        let error = check_conflicts(&all).unwrap_err();
+        assert!(error.to_string().contains("'i3' and 'editor' cannot be scattered together"), "{}", error);
+        all.remove(1);
+        assert!(check_conflicts(&all).is_ok());
+    }
+}
@@ -1,35 +1,216 @@
-use crate::PollenError;
-use std::path::Path;
+use crate::{Entry, HookOperation, PollenConfig, PollenDirs, PollenError};
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    process::{Command, Stdio},
+    sync::OnceLock,
+    thread,
+};
 
-pub fn execute_shell_command(command: &str) -> Result<(), std::io::Error> {
-    let output = std::process::Command::new("sh")
+/// Track files given with `--config`, in command line order
+static CONFIG_FILES: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Remember the track files given on the command line
+pub fn set_config_files(files: Vec<String>) {
+    let _ = CONFIG_FILES.set(files);
+}
+
+/// Whether the command only shows what it would do, handed to hooks as `POLLEN_DRY_RUN`
+static DRY_RUN: OnceLock<bool> = OnceLock::new();
+
+/// Remember whether the command is a dry run
+pub fn set_dry_run(dry_run: bool) {
+    let _ = DRY_RUN.set(dry_run);
+}
+
+/// Track files to read: every `--config`, or the default track file
+pub fn track_files(dirs: &PollenDirs, config: &PollenConfig) -> Result<Vec<String>, PollenError> {
+    match CONFIG_FILES.get() {
+        Some(files) if !files.is_empty() => Ok(files.clone()),
+        _ => {
+            let track_file = dirs.get_track_file_path(config);
+            let path = track_file.to_str().ok_or(PollenError::InvalidEndpoint("Invalid config file path".into()))?;
+            Ok(vec![path.to_string()])
+        }
+    }
+}
+
+/// What a hook runs for, handed to it as `POLLEN_*` environment variables
+pub struct HookContext<'a> {
+    pub entry: &'a Entry,
+    pub operation: HookOperation,
+    /// Where the content is copied from
+    pub source: &'a Path,
+    /// Where the content is copied to
+    pub target: &'a Path,
+    pub files_dir: &'a Path,
+}
+
+/// Run a hook with `sh -c`, streaming its output as it comes, each line prefixed with the entry name
+pub fn execute_shell_command(command: &str, context: &HookContext) -> Result<(), std::io::Error> {
+    run_streamed(command, context, |line| println!("{}", line), |line| eprintln!("{}", line))
+}
+
+/// Run a hook, handing each prefixed line of its stdout and stderr to the matching callback
+fn run_streamed(
+    command: &str,
+    context: &HookContext,
+    on_stdout: impl FnMut(String),
+    on_stderr: impl FnMut(String) + Send,
+) -> Result<(), std::io::Error> {
+    let mut child = Command::new("sh")
         .arg("-c")
         .arg(command)
-        .output()?;
-        
-    if !output.status.success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Command failed with exit code: {:?}", output.status.code())
+        .env("POLLEN_ENTRY", &context.entry.name)
+        .env("POLLEN_ALIAS", context.entry.alias_as.as_deref().unwrap_or(""))
+        .env("POLLEN_OPERATION", context.operation.as_str())
+        .env("POLLEN_SOURCE", context.source)
+        .env("POLLEN_TARGET", context.target)
+        .env("POLLEN_FILES_DIR", context.files_dir)
+        .env("POLLEN_DRY_RUN", if DRY_RUN.get().copied().unwrap_or(false) { "1" } else { "0" })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    
+    // Both streams are read at once so neither fills up and stalls the hook
+    let prefix = context.entry.get_display_name();
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    thread::scope(|scope| {
+        if let Some(stderr) = stderr {
+            scope.spawn(|| stream_lines(stderr, prefix, on_stderr));
+        }
+        if let Some(stdout) = stdout {
+            stream_lines(stdout, prefix, on_stdout);
+        }
+    });
+    
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(std::io::Error::other(
+            format!("Command failed with exit code: {:?}", status.code())
         ));
     }
     
-    // Print stdout if there's any
-    if !output.stdout.is_empty() {
-        print!("{}", String::from_utf8_lossy(&output.stdout));
+    Ok(())
+}
+
+/// Run the entry's hooks that come before its operation: `run_before`, then the operation's own
+pub fn run_before_hooks(context: &HookContext) -> Result<(), PollenError> {
+    run_hooks(&context.entry.before_hooks(context.operation), "pre", context)
+}
+
+/// Run the entry's hooks that come after its operation: the operation's own, then `run_after`
+pub fn run_after_hooks(context: &HookContext) -> Result<(), PollenError> {
+    run_hooks(&context.entry.after_hooks(context.operation), "post", context)
+}
+
+fn run_hooks(hooks: &[(&str, &str)], stage: &str, context: &HookContext) -> Result<(), PollenError> {
+    for (option, command) in hooks {
+        println!("  → Running {}-{} command: {}", stage, context.operation.as_str(), command);
+        if let Err(e) = execute_shell_command(command, context) {
+            return Err(PollenError::InvalidEndpoint(
+                format!("Failed to execute {} command '{}': {}", option, command, e)
+            ));
+        }
     }
-    
-    // Print stderr if there's any
-    if !output.stderr.is_empty() {
-        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    Ok(())
+}
+
+fn stream_lines(reader: impl Read, prefix: &str, mut emit: impl FnMut(String)) {
+    for line in BufReader::new(reader).split(b'\n').map_while(Result::ok) {
+        let line = String::from_utf8_lossy(&line);
+        emit(format!("    [{}] {}", prefix, line.trim_end_matches('\r')));
     }
+}
+
+/// How a single file was copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyStrategy {
+    /// Copy-on-write clone sharing the source's blocks (FICLONE)
+    Reflink,
+    /// In-kernel copy without passing data through userspace
+    CopyFileRange,
+    /// Plain read/write loop
+    ByteCopy,
+}
+
+/// Number of files copied with each strategy
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CopyReport {
+    pub reflink: usize,
+    pub copy_file_range: usize,
+    pub byte_copy: usize,
+}
+
+impl CopyReport {
+    fn record(&mut self, strategy: CopyStrategy) {
+        match strategy {
+            CopyStrategy::Reflink => self.reflink += 1,
+            CopyStrategy::CopyFileRange => self.copy_file_range += 1,
+            CopyStrategy::ByteCopy => self.byte_copy += 1,
+        }
+    }
+
+    fn merge(&mut self, other: CopyReport) {
+        self.reflink += other.reflink;
+        self.copy_file_range += other.copy_file_range;
+        self.byte_copy += other.byte_copy;
+    }
+
+    /// Describe the strategies used, e.g. "reflink (12 files), byte copy (1 file)"
+    pub fn summary(&self) -> String {
+        let parts: Vec<String> = [
+            ("reflink", self.reflink),
+            ("copy_file_range", self.copy_file_range),
+            ("byte copy", self.byte_copy),
+        ]
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| format!("{} ({} file{})", name, count, if *count == 1 { "" } else { "s" }))
+        .collect();
+
+        if parts.is_empty() {
+            "no files".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+
+    /// Print the summary when verbose output is enabled
+    pub fn print_verbose(&self, verbose: bool, what: &str) {
+        if verbose {
+            println!("  → {} using {}", what, self.summary());
+        }
+    }
+}
+
+/// Delete a file, symlink or directory tree for good
+pub fn remove_path(path: &Path) -> Result<(), PollenError> {
+    use std::fs;
     
-    Ok(())
+    let metadata = path.symlink_metadata().map_err(PollenError::Io)?;
+    if metadata.is_dir() {
+        fs::remove_dir_all(path).map_err(PollenError::Io)
+    } else {
+        fs::remove_file(path).map_err(PollenError::Io)
+    }
+}
+
+/// Copy a file or directory tree
+pub fn copy_path(src: &Path, dst: &Path) -> Result<CopyReport, PollenError> {
+    if src.is_dir() {
+        copy_dir_all(src, dst)
+    } else {
+        let mut report = CopyReport::default();
+        report.record(copy_file(src, dst)?);
+        Ok(report)
+    }
 }
 
-pub fn copy_dir_all(src: &Path, dst: &Path) -> Result<(), PollenError> {
+pub fn copy_dir_all(src: &Path, dst: &Path) -> Result<CopyReport, PollenError> {
     use std::fs;
     
+    let mut report = CopyReport::default();
     fs::create_dir_all(dst).map_err(PollenError::Io)?;
     
     for entry in fs::read_dir(src).map_err(PollenError::Io)? {
@@ -38,11 +219,322 @@ pub fn copy_dir_all(src: &Path, dst: &Path) -> Result<(), PollenError> {
         let dst_path = dst.join(entry.file_name());
         
         if src_path.is_dir() {
-            copy_dir_all(&src_path, &dst_path)?;
+            report.merge(copy_dir_all(&src_path, &dst_path)?);
         } else {
-            fs::copy(&src_path, &dst_path).map_err(PollenError::Io)?;
+            report.record(copy_file(&src_path, &dst_path)?);
         }
     }
     
-    Ok(())
+    Ok(report)
+}
+
+/// Copy a single file, preferring a reflink clone, then copy_file_range, then a byte copy
+pub fn copy_file(src: &Path, dst: &Path) -> Result<CopyStrategy, PollenError> {
+    use std::fs::{self, File, OpenOptions};
+    
+    let mut source = File::open(src).map_err(PollenError::Io)?;
+    let permissions = source.metadata().map_err(PollenError::Io)?.permissions();
+    let mut destination = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dst)
+        .map_err(PollenError::Io)?;
+    
+    let strategy = match accelerated_copy(&source, &destination).map_err(PollenError::Io)? {
+        Some(strategy) => strategy,
+        None => {
+            byte_copy(&mut source, &mut destination).map_err(PollenError::Io)?;
+            CopyStrategy::ByteCopy
+        }
+    };
+    
+    fs::set_permissions(dst, permissions).map_err(PollenError::Io)?;
+    Ok(strategy)
+}
+
+/// Copy through a userspace buffer. `std::io::copy` is avoided because it may use
+/// copy_file_range itself, which would make the reported strategy wrong.
+fn byte_copy(source: &mut std::fs::File, destination: &mut std::fs::File) -> Result<(), std::io::Error> {
+    use std::io::{Read, Write};
+    
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = match source.read(&mut buffer) {
+            Ok(0) => return destination.flush(),
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        destination.write_all(&buffer[..read])?;
+    }
+}
+
+/// Try the Linux fast paths, returning `None` when neither is supported for these files
+#[cfg(target_os = "linux")]
+fn accelerated_copy(source: &std::fs::File, destination: &std::fs::File) -> Result<Option<CopyStrategy>, std::io::Error> {
+    use std::os::unix::io::AsRawFd;
+    
+    let source_fd = source.as_raw_fd();
+    let destination_fd = destination.as_raw_fd();
+    
+    // SAFETY: both descriptors are open for the duration of these calls
+    if unsafe { libc::ioctl(destination_fd, libc::FICLONE, source_fd) } == 0 {
+        return Ok(Some(CopyStrategy::Reflink));
+    }
+    
+    let mut copied = 0usize;
+    loop {
+        let result = unsafe {
+            libc::copy_file_range(
+                source_fd,
+                std::ptr::null_mut(),
+                destination_fd,
+                std::ptr::null_mut(),
+                1 << 30,
+                0,
+            )
+        };
+        
+        if result == 0 {
+            return Ok(Some(CopyStrategy::CopyFileRange));
+        }
+        
+        if result < 0 {
+            let error = std::io::Error::last_os_error();
+            let unsupported = matches!(
+                error.raw_os_error(),
+                Some(libc::EXDEV | libc::ENOSYS | libc::EOPNOTSUPP | libc::EINVAL | libc::EPERM)
+            );
+            
+            // Only fall back if nothing was written yet, otherwise the copy would be corrupted
+            if unsupported && copied == 0 {
+                return Ok(None);
+            }
+            return Err(error);
+        }
+        
+        copied += result as usize;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn accelerated_copy(_source: &std::fs::File, _destination: &std::fs::File) -> Result<Option<CopyStrategy>, std::io::Error> {
+    Ok(None)
+}
+
+/// Compare two files or directory trees, returning the relative paths that differ.
+/// A single file that differs is reported as an empty path.
+pub fn differing_paths(expected: &Path, actual: &Path) -> Vec<String> {
+    let mut differences = Vec::new();
+    collect_differences(expected, actual, Path::new(""), &mut differences);
+    differences
+}
+
+fn collect_differences(expected: &Path, actual: &Path, relative: &Path, differences: &mut Vec<String>) {
+    match (expected.is_dir(), actual.is_dir()) {
+        (true, true) => {
+            let mut names = std::collections::BTreeSet::new();
+            for dir in [expected, actual] {
+                if let Ok(entries) = std::fs::read_dir(dir) {
+                    names.extend(entries.flatten().map(|entry| entry.file_name()));
+                }
+            }
+            for name in names {
+                collect_differences(&expected.join(&name), &actual.join(&name), &relative.join(&name), differences);
+            }
+        }
+        (false, false) => {
+            let same = match (std::fs::read(expected), std::fs::read(actual)) {
+                (Ok(a), Ok(b)) => a == b,
+                (Err(_), Err(_)) => true,
+                _ => false,
+            };
+            if !same {
+                differences.push(relative.display().to_string());
+            }
+        }
+        _ => differences.push(relative.display().to_string()),
+    }
+}
+
+/// Parse a local date such as `2024-05-14`, `2024-05-14 18:30` or `2024-05-14T18:30:15`
+/// into a unix timestamp
+pub fn parse_local_datetime(input: &str) -> Option<u64> {
+    let input = input.trim();
+    let (date, time) = match input.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (input, None),
+    };
+
+    let date_parts: Vec<&str> = date.split('-').collect();
+    if date_parts.len() != 3 || date_parts[0].len() != 4 {
+        return None;
+    }
+    let year: i64 = date_parts[0].parse().ok()?;
+    let month: u32 = date_parts[1].parse().ok()?;
+    let day: u32 = date_parts[2].parse().ok()?;
+
+    let (hour, minute, second) = match time {
+        Some(time) => {
+            let time_parts: Vec<&str> = time.split(':').collect();
+            if time_parts.len() < 2 || time_parts.len() > 3 {
+                return None;
+            }
+            let hour: u32 = time_parts[0].parse().ok()?;
+            let minute: u32 = time_parts[1].parse().ok()?;
+            let second: u32 = match time_parts.get(2) {
+                Some(second) => second.parse().ok()?,
+                None => 0,
+            };
+            (hour, minute, second)
+        }
+        None => (0, 0, 0),
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    local_to_timestamp(year, month, day, hour, minute, second)
+}
+
+/// Format a unix timestamp as a local `YYYY-MM-DD HH:MM:SS` string
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (year, month, day, hour, minute, second) = timestamp_to_local(timestamp);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+#[cfg(unix)]
+fn local_to_timestamp(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> Option<u64> {
+    // SAFETY: `tm` is plain data and mktime only reads and normalizes the struct we own
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = (year - 1900) as libc::c_int;
+    tm.tm_mon = month as libc::c_int - 1;
+    tm.tm_mday = day as libc::c_int;
+    tm.tm_hour = hour as libc::c_int;
+    tm.tm_min = minute as libc::c_int;
+    tm.tm_sec = second as libc::c_int;
+    tm.tm_isdst = -1;
+
+    let timestamp = unsafe { libc::mktime(&mut tm) };
+    u64::try_from(timestamp).ok()
+}
+
+#[cfg(not(unix))]
+fn local_to_timestamp(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> Option<u64> {
+    // Without a timezone database, treat the input as UTC
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second);
+    u64::try_from(seconds).ok()
+}
+
+#[cfg(unix)]
+fn timestamp_to_local(timestamp: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let time = timestamp as libc::time_t;
+    // SAFETY: localtime_r writes into the `tm` we own and does not retain either pointer
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+
+    (
+        i64::from(tm.tm_year) + 1900,
+        (tm.tm_mon + 1) as u32,
+        tm.tm_mday as u32,
+        tm.tm_hour as u32,
+        tm.tm_min as u32,
+        tm.tm_sec as u32,
+    )
+}
+
+#[cfg(not(unix))]
+fn timestamp_to_local(timestamp: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (timestamp / 86400) as i64;
+    let seconds = (timestamp % 86400) as u32;
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+#[cfg(not(unix))]
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::EntryArgument;
+    use std::{fs, path::PathBuf};
+
+    fn entry() -> Entry {
+        Entry::new(EntryArgument { name: "zshrc".to_string(), path: PathBuf::from("/home/user/.zshrc") })
+    }
+
+    fn context<'a>(entry: &'a Entry, target: &'a Path) -> HookContext<'a> {
+        HookContext {
+            entry,
+            operation: HookOperation::Restore,
+            source: &entry.path,
+            target,
+            files_dir: Path::new("/home/user/.config/pollen/files"),
+        }
+    }
+
+    #[test]
+    fn lines_are_prefixed_with_the_entry() {
+        let mut lines = Vec::new();
+        stream_lines("one\r\ntwo\nlast".as_bytes(), "zshrc", |line| lines.push(line));
+        assert_eq!(lines, ["    [zshrc] one", "    [zshrc] two", "    [zshrc] last"]);
+    }
+
+    #[test]
+    fn hooks_see_the_operation_and_paths() {
+        let log = std::env::temp_dir().join(format!("pollen-hook-test-{}", std::process::id()));
+        let entry = entry();
+        let command = format!(
+            "echo \"$POLLEN_ENTRY $POLLEN_OPERATION $POLLEN_SOURCE $POLLEN_TARGET $POLLEN_FILES_DIR $POLLEN_DRY_RUN\" > '{}'",
+            log.display()
+        );
+
+        execute_shell_command(&command, &context(&entry, Path::new("/a:/b"))).unwrap();
+        let seen = fs::read_to_string(&log).unwrap();
+        let _ = fs::remove_file(&log);
+        assert_eq!(seen, "zshrc restore /home/user/.zshrc /a:/b /home/user/.config/pollen/files 0\n");
+    }
+
+    #[test]
+    fn chatty_hooks_do_not_stall() {
+        // More than a pipe buffer on each stream, which stalls a hook whose streams are read in turn
+        let command = "i=0; while [ $i -lt 5000 ]; do echo out $i; echo err $i >&2; i=$((i+1)); done";
+        let entry = entry();
+        let mut lines = 0;
+        run_streamed(command, &context(&entry, Path::new("/target")), |_| lines += 1, |_| {}).unwrap();
+        assert_eq!(lines, 5000);
+    }
+
+    #[test]
+    fn failing_hook_is_an_error() {
+        let entry = entry();
+        let error = execute_shell_command("exit 3", &context(&entry, Path::new("/target"))).unwrap_err();
+        assert_eq!(error.to_string(), "Command failed with exit code: Some(3)");
+    }
 }
@@ -0,0 +1,277 @@
+use crate::{error::PollenError, host::HostFacts, yaml_ext::CanForceIntoString};
+use serde_yaml::Value;
+use std::{fmt, path::Path};
+
+/// A `when:` condition deciding whether an entry applies to the current machine
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Short hostname is one of the given names
+    Hostname(Vec<String>),
+    /// Current user is one of the given names
+    User(Vec<String>),
+    /// Operating system is one of the given names ("linux", "macos", ...)
+    Os(Vec<String>),
+    /// Linux distribution ID (or one it is like) is one of the given names
+    Distro(Vec<String>),
+    /// Environment variable is set and non-empty, or equals a value
+    Env { name: String, value: Option<String> },
+    /// Every given command can be found on PATH
+    CommandExists(Vec<String>),
+    /// Every condition holds
+    All(Vec<Condition>),
+    /// At least one condition holds
+    Any(Vec<Condition>),
+    /// The condition does not hold
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    /// Parse the value of a `when:` option. Several keys in one mapping must all hold.
+    pub fn parse(value: &Value) -> Result<Condition, PollenError> {
+        let mapping = value.as_mapping().ok_or_else(|| {
+            PollenError::InvalidOption(format!("'when' must be a mapping of conditions, got {:?}", value))
+        })?;
+
+        if mapping.is_empty() {
+            return Err(PollenError::InvalidOption("'when' has no conditions".to_string()));
+        }
+
+        let mut conditions = mapping
+            .iter()
+            .map(|(key, value)| Self::parse_single(&key.force_into_string()?, value))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if conditions.len() == 1 {
+            Ok(conditions.remove(0))
+        } else {
+            Ok(Condition::All(conditions))
+        }
+    }
+
+    fn parse_single(key: &str, value: &Value) -> Result<Condition, PollenError> {
+        match key {
+            "hostname" => Ok(Condition::Hostname(string_list(key, value)?)),
+            "user" => Ok(Condition::User(string_list(key, value)?)),
+            "os" => Ok(Condition::Os(string_list(key, value)?)),
+            "distro" => Ok(Condition::Distro(string_list(key, value)?)),
+            "command_exists" => Ok(Condition::CommandExists(string_list(key, value)?)),
+            "env" => match value {
+                Value::String(name) => Ok(Condition::Env { name: name.clone(), value: None }),
+                Value::Mapping(mapping) => {
+                    let conditions = mapping
+                        .iter()
+                        .map(|(name, expected)| {
+                            Ok(Condition::Env {
+                                name: name.force_into_string()?,
+                                value: Some(scalar_string(expected).ok_or_else(|| {
+                                    PollenError::InvalidOption(format!(
+                                        "'env' values must be strings, got {:?}", expected
+                                    ))
+                                })?),
+                            })
+                        })
+                        .collect::<Result<Vec<_>, PollenError>>()?;
+                    Ok(Self::all_of(conditions))
+                }
+                _ => Err(PollenError::InvalidOption(format!(
+                    "'env' expects a variable name or a mapping of names to values, got {:?}", value
+                ))),
+            },
+            "all" | "any" => {
+                let sequence = value.as_sequence().ok_or_else(|| {
+                    PollenError::InvalidOption(format!("'{}' expects a list of conditions", key))
+                })?;
+                let conditions = sequence.iter().map(Self::parse).collect::<Result<Vec<_>, _>>()?;
+                Ok(if key == "all" { Condition::All(conditions) } else { Condition::Any(conditions) })
+            }
+            "not" => Ok(Condition::Not(Box::new(Self::parse(value)?))),
+            _ => Err(PollenError::InvalidOption(format!(
+                "Unknown condition '{}' (expected hostname, user, os, distro, env, command_exists, all, any or not)",
+                key
+            ))),
+        }
+    }
+
+    fn all_of(mut conditions: Vec<Condition>) -> Condition {
+        if conditions.len() == 1 {
+            conditions.remove(0)
+        } else {
+            Condition::All(conditions)
+        }
+    }
+
+    /// Combine an inherited directory condition with an entry's own condition
+    pub fn and(inherited: Option<&Condition>, own: Option<Condition>) -> Option<Condition> {
+        match (inherited, own) {
+            (Some(inherited), Some(own)) => Some(Condition::All(vec![inherited.clone(), own])),
+            (Some(inherited), None) => Some(inherited.clone()),
+            (None, own) => own,
+        }
+    }
+
+    /// Check whether the condition holds on the machine described by `facts`
+    pub fn evaluate(&self, facts: &HostFacts) -> bool {
+        match self {
+            Condition::Hostname(names) => names.iter().any(|name| name.eq_ignore_ascii_case(&facts.hostname)),
+            Condition::User(names) => names.contains(&facts.username),
+            Condition::Os(names) => names.contains(&facts.os),
+            Condition::Distro(names) => names.iter().any(|name| {
+                facts.distro.as_deref() == Some(name.as_str()) || facts.distro_like.contains(name)
+            }),
+            Condition::Env { name, value } => match (std::env::var(name), value) {
+                (Ok(actual), Some(expected)) => actual == *expected,
+                (Ok(actual), None) => !actual.is_empty(),
+                (Err(_), _) => false,
+            },
+            Condition::CommandExists(commands) => commands.iter().all(|command| command_exists(command)),
+            Condition::All(conditions) => conditions.iter().all(|condition| condition.evaluate(facts)),
+            Condition::Any(conditions) => conditions.iter().any(|condition| condition.evaluate(facts)),
+            Condition::Not(condition) => !condition.evaluate(facts),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Hostname(names) => write!(f, "hostname is {}", names.join(" or ")),
+            Condition::User(names) => write!(f, "user is {}", names.join(" or ")),
+            Condition::Os(names) => write!(f, "os is {}", names.join(" or ")),
+            Condition::Distro(names) => write!(f, "distro is {}", names.join(" or ")),
+            Condition::Env { name, value: Some(value) } => write!(f, "${} is \"{}\"", name, value),
+            Condition::Env { name, value: None } => write!(f, "${} is set", name),
+            Condition::CommandExists(commands) => write!(f, "{} installed", commands.join(" and ")),
+            Condition::All(conditions) => write_joined(f, conditions, " and "),
+            Condition::Any(conditions) => write_joined(f, conditions, " or "),
+            Condition::Not(condition) => write!(f, "not ({})", condition),
+        }
+    }
+}
+
+fn write_joined(f: &mut fmt::Formatter<'_>, conditions: &[Condition], separator: &str) -> fmt::Result {
+    for (index, condition) in conditions.iter().enumerate() {
+        if index > 0 {
+            f.write_str(separator)?;
+        }
+        match condition {
+            Condition::All(_) | Condition::Any(_) => write!(f, "({})", condition)?,
+            _ => write!(f, "{}", condition)?,
+        }
+    }
+    Ok(())
+}
+
+/// Accept a single string or a list of strings
+fn string_list(key: &str, value: &Value) -> Result<Vec<String>, PollenError> {
+    let values = match value {
+        Value::Sequence(sequence) => sequence.iter().map(scalar_string).collect::<Option<Vec<_>>>(),
+        other => scalar_string(other).map(|value| vec![value]),
+    };
+
+    values.ok_or_else(|| {
+        PollenError::InvalidOption(format!("'{}' expects a string or a list of strings, got {:?}", key, value))
+    })
+}
+
+fn scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+/// Whether `command` is an executable path or can be found in PATH
+fn command_exists(command: &str) -> bool {
+    if command.contains('/') {
+        return is_executable(Path::new(command));
+    }
+
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| is_executable(&dir.join(command))))
+        .unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> HostFacts {
+        HostFacts {
+            hostname: "laptop".to_string(),
+            username: "ana".to_string(),
+            os: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            distro: Some("pop".to_string()),
+            distro_like: vec!["ubuntu".to_string(), "debian".to_string()],
+        }
+    }
+
+    fn condition(yaml: &str) -> Result<Condition, PollenError> {
+        Condition::parse(&serde_yaml::from_str(yaml).unwrap())
+    }
+
+    #[test]
+    fn keys_of_one_mapping_must_all_hold() {
+        let laptop = condition("{hostname: [Laptop, desk], os: linux}").unwrap();
+        assert_eq!(laptop.to_string(), "hostname is Laptop or desk and os is linux");
+        assert!(laptop.evaluate(&facts()));
+        assert!(!condition("{hostname: laptop, user: root}").unwrap().evaluate(&facts()));
+    }
+
+    #[test]
+    fn distros_match_the_ones_they_derive_from() {
+        assert!(condition("{distro: debian}").unwrap().evaluate(&facts()));
+        assert!(!condition("{distro: [arch, fedora]}").unwrap().evaluate(&facts()));
+    }
+
+    #[test]
+    fn conditions_combine_with_any_and_not() {
+        let work = condition("{any: [{os: macos}, {not: {user: root}}]}").unwrap();
+        assert_eq!(work.to_string(), "os is macos or not (user is root)");
+        assert!(work.evaluate(&facts()));
+        assert!(!condition("{not: {any: [{os: linux}, {os: macos}]}}").unwrap().evaluate(&facts()));
+    }
+
+    #[test]
+    fn env_conditions_check_set_and_equal() {
+        let name = "POLLEN_TEST_CONDITION_ENV";
+        std::env::set_var(name, "work");
+        let set = condition(&format!("{{env: {}}}", name)).unwrap();
+        let equal = condition(&format!("{{env: {{{}: work}}}}", name)).unwrap();
+        let other = condition(&format!("{{env: {{{}: home}}}}", name)).unwrap();
+        assert!(set.evaluate(&facts()) && equal.evaluate(&facts()) && !other.evaluate(&facts()));
+
+        std::env::set_var(name, "");
+        assert!(!set.evaluate(&facts()));
+        std::env::remove_var(name);
+    }
+
+    #[test]
+    fn commands_are_looked_up_on_path() {
+        assert!(condition("{command_exists: sh}").unwrap().evaluate(&facts()));
+        assert!(!condition("{command_exists: [sh, pollen-no-such-command]}").unwrap().evaluate(&facts()));
+    }
+
+    #[test]
+    fn malformed_conditions_are_errors() {
+        assert!(condition("{}").is_err());
+        assert!(condition("{hostnames: laptop}").unwrap_err().to_string().contains("Unknown condition 'hostnames'"));
+        assert!(condition("{any: {os: linux}}").is_err());
+        assert!(condition("[linux]").is_err());
+    }
+}
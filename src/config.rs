@@ -1,46 +1,196 @@
 use crate::{
+    assemble::Assembly,
+    condition::Condition,
+    dirs::PollenConfig,
     error::PollenError,
-    entry::{Entry, EntryArgument},
-    yaml_ext::{CanForceIntoString, Endpoint},
+    entry::{Entry, EntryArgument, EntrySource},
+    merge::{parse_ignore_keys, Merge, MergeFormat},
+    profile::ProfileDefinition,
+    yaml_ext::{option_reading, CanForceIntoBool, CanForceIntoString, Endpoint, LineTree, OptionReading},
 };
 use serde_yaml::{Mapping, Value};
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    fs::File,
-    io::Read,
-    path::{Path, PathBuf},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Component, Path, PathBuf},
 };
 
+/// Top-level track file key holding profile definitions
+const PROFILES_KEY: &str = "profiles";
+
+/// Top-level track file key naming other track files to read
+const INCLUDE_KEY: &str = "include";
+
 /// Configuration parser for Pollen YAML files
 pub struct ConfigParser {
     home_dir: PathBuf,
+    /// Filters defined in pollen.yaml, when entries naming other filters should be refused
+    filters: Option<BTreeSet<String>>,
+}
+
+/// Everything read from a set of track files
+#[derive(Default)]
+struct Collected {
+    entries: Vec<Entry>,
+    /// Profiles with the file defining them
+    profiles: BTreeMap<String, (ProfileDefinition, PathBuf)>,
+    /// Files being read, innermost last, to catch include cycles
+    stack: Vec<PathBuf>,
+    /// Every file read so far
+    seen: HashSet<PathBuf>,
+}
+
+/// What a directory passes to every entry below it: its options and the track file it is in
+#[derive(Debug, Clone, Default)]
+struct Inherited {
+    /// Track file being read, when the entries come from a file
+    file: Option<PathBuf>,
+    /// Condition every entry below the directory must also satisfy
+    when: Option<Condition>,
+    /// Tags every entry below the directory carries
+    tags: Vec<String>,
 }
 
 impl ConfigParser {
     /// Create a new ConfigParser
     pub fn new() -> Result<Self, PollenError> {
         let home_dir = get_home_dir()?;
-        Ok(ConfigParser { home_dir })
+        Ok(ConfigParser { home_dir, filters: None })
+    }
+
+    /// Refuse entries naming a filter that pollen.yaml does not define
+    pub fn with_filters(mut self, config: &PollenConfig) -> Self {
+        self.filters = Some(config.filters.iter().flatten().map(|(name, _)| name.clone()).collect());
+        self
+    }
+
+    /// Home directory entries are resolved against
+    pub fn home_dir(&self) -> &Path {
+        &self.home_dir
     }
 
     /// Parse a configuration file and return a list of entries
     pub fn parse_file(&self, file_path: &str) -> Result<Vec<Entry>, PollenError> {
-        let mut content = String::new();
-        File::open(file_path)
-            .and_then(|mut file| file.read_to_string(&mut content))
-            .map_err(PollenError::Io)?;
+        self.parse_files(&[file_path.to_string()])
+    }
+
+    /// Parse several track files, and the files they include, into one list of entries
+    pub fn parse_files(&self, file_paths: &[String]) -> Result<Vec<Entry>, PollenError> {
+        let collected = self.collect_files(file_paths)?;
+        check_duplicate_entries(&collected.entries)?;
 
-        self.parse_content(&content)
+        // Sort entries based on dependencies to ensure proper ordering
+        self.topological_sort(collected.entries)
     }
 
-    /// Parse YAML content and return a list of entries
+    /// Parse YAML content and return a list of entries.
+    /// Included files are resolved against the current directory.
     pub fn parse_content(&self, content: &str) -> Result<Vec<Entry>, PollenError> {
-        let values: Mapping = serde_yaml::from_str(content)?;
-        let mut entries = Vec::new();
-        self.recurse_over_mapping(&mut entries, &values, &self.home_dir)?;
-        
+        let mut collected = Collected::default();
+        self.collect_content(content, None, &std::env::current_dir()?, &mut collected)?;
+        check_duplicate_entries(&collected.entries)?;
+
         // Sort entries based on dependencies to ensure proper ordering
-        self.topological_sort(entries)
+        self.topological_sort(collected.entries)
+    }
+
+    /// Read the profiles defined at the top of a track file and the files it includes
+    pub fn parse_profiles_file(&self, file_path: &str) -> Result<BTreeMap<String, ProfileDefinition>, PollenError> {
+        self.parse_profiles_files(&[file_path.to_string()])
+    }
+
+    /// Read the profiles defined in several track files and the files they include
+    pub fn parse_profiles_files(&self, file_paths: &[String]) -> Result<BTreeMap<String, ProfileDefinition>, PollenError> {
+        let collected = self.collect_files(file_paths)?;
+        Ok(collected.profiles.into_iter().map(|(name, (definition, _))| (name, definition)).collect())
+    }
+
+    fn collect_files(&self, file_paths: &[String]) -> Result<Collected, PollenError> {
+        let mut collected = Collected::default();
+        for path in file_paths {
+            self.collect_file(Path::new(path), &mut collected)?;
+        }
+        Ok(collected)
+    }
+
+    /// Read one track file, following its includes
+    fn collect_file(&self, path: &Path, collected: &mut Collected) -> Result<(), PollenError> {
+        let read_error = |e: std::io::Error| {
+            PollenError::Io(std::io::Error::new(e.kind(), format!("Cannot read track file {}: {}", path.display(), e)))
+        };
+        let canonical = path.canonicalize().map_err(read_error)?;
+
+        if let Some(position) = collected.stack.iter().position(|file| *file == canonical) {
+            let cycle: Vec<String> = collected.stack[position..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(PollenError::CircularDependency(format!(
+                "Track files include each other: {}", cycle.join(" -> ")
+            )));
+        }
+
+        // A file reached through several includes is only read the first time
+        if !collected.seen.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let content = std::fs::read_to_string(path).map_err(read_error)?;
+        let base_dir = canonical.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        collected.stack.push(canonical);
+        self.collect_content(&content, Some(path), &base_dir, collected)?;
+        collected.stack.pop();
+        Ok(())
+    }
+
+    /// Parse the content of a track file, expanding `include` where it appears
+    fn collect_content(
+        &self,
+        content: &str,
+        file: Option<&Path>,
+        base_dir: &Path,
+        collected: &mut Collected,
+    ) -> Result<(), PollenError> {
+        let values: Mapping = if content.trim().is_empty() {
+            Mapping::new()
+        } else {
+            serde_yaml::from_str(content)?
+        };
+        let lines = LineTree::read(content);
+
+        for (index, (key, value)) in values.iter().enumerate() {
+            match key.as_str() {
+                // A top-level `profiles` mapping defines profiles rather than tracked files
+                Some(PROFILES_KEY) if value.is_mapping() => {
+                    let profiles: BTreeMap<String, ProfileDefinition> = serde_yaml::from_value(value.clone())?;
+                    let file = file.map(Path::to_path_buf).unwrap_or_default();
+                    for (name, definition) in profiles {
+                        if let Some((_, first)) = collected.profiles.get(&name) {
+                            return Err(PollenError::InvalidOption(format!(
+                                "Profile '{}' is defined in both {} and {}", name, first.display(), file.display()
+                            )));
+                        }
+                        collected.profiles.insert(name, (definition, file.clone()));
+                    }
+                }
+                Some(INCLUDE_KEY) => {
+                    for include in parse_includes(value)? {
+                        let path = resolve_include(&include, base_dir, &self.home_dir);
+                        self.collect_file(&path, collected)?;
+                    }
+                }
+                _ => {
+                    let mut section = Mapping::new();
+                    section.insert(key.clone(), value.clone());
+                    let section_lines = LineTree { line: 0, children: vec![lines.child(index).clone()] };
+                    let inherited = Inherited { file: file.map(Path::to_path_buf), ..Inherited::default() };
+                    self.recurse_over_mapping(&mut collected.entries, &section, &section_lines, &self.home_dir, &inherited)?;
+                }
+            }
+        }
+
+        Ok(())
     }
 
     /// Recursively process a YAML mapping
@@ -48,16 +198,56 @@ impl ConfigParser {
         &self,
         entries: &mut Vec<Entry>,
         mapping: &Mapping,
+        lines: &LineTree,
         parent_path: &Path,
+        inherited: &Inherited,
     ) -> Result<(), PollenError> {
-        for (key, value) in mapping.iter() {
+        for (index, (key, value)) in mapping.iter().enumerate() {
+            let lines = lines.child(index);
             match value {
                 Value::String(string) => {
-                    self.handle_string(entries, string.to_string(), parent_path);
+                    self.handle_string(entries, string.to_string(), lines.known_line(), parent_path, inherited);
                 }
                 Value::Sequence(sequence) => {
                     let key_str = key.force_into_string()?;
-                    self.handle_sequence(entries, sequence, &parent_path.join(key_str))?;
+                    self.handle_sequence(entries, sequence, lines, &parent_path.join(key_str), inherited)?;
+                }
+                // `when` next to other keys would otherwise be read as a directory called "when"
+                Value::Mapping(_) if key.as_str() == Some("when") => {
+                    return Err(PollenError::InvalidMapping(
+                        "'when' must be a list item of its own or a key of a directory mapping, \
+                         not a key next to an entry".to_string()
+                    ));
+                }
+                Value::Mapping(children) => {
+                    // A directory written as a mapping reads like the list form, one item per key
+                    let sequence: Vec<Value> = children
+                        .iter()
+                        .map(|(child, value)| match value {
+                            Value::Null => child.clone(),
+                            _ => {
+                                let mut item = Mapping::new();
+                                item.insert(child.clone(), value.clone());
+                                Value::Mapping(item)
+                            }
+                        })
+                        .collect();
+                    let sequence_lines = LineTree {
+                        line: lines.line,
+                        children: children
+                            .iter()
+                            .enumerate()
+                            .map(|(index, (_, value))| {
+                                let child = lines.child(index);
+                                match value {
+                                    Value::Null => LineTree { line: child.line, children: Vec::new() },
+                                    _ => LineTree { line: child.line, children: vec![child.clone()] },
+                                }
+                            })
+                            .collect(),
+                    };
+                    let key_str = key.force_into_string()?;
+                    self.handle_sequence(entries, &sequence, &sequence_lines, &parent_path.join(key_str), inherited)?;
                 }
                 _ => {
                     let key_str = key.force_into_string()
@@ -77,18 +267,43 @@ impl ConfigParser {
         &self,
         entries: &mut Vec<Entry>,
         sequence: &[Value],
+        lines: &LineTree,
         parent_path: &Path,
+        inherited: &Inherited,
     ) -> Result<(), PollenError> {
+        // Directory options apply to every sibling, wherever they appear in the list
+        let mut inherited = inherited.clone();
+        let mut options = Vec::new();
         for value in sequence.iter() {
+            let option = match value.as_mapping() {
+                Some(mapping) => directory_option(mapping, parent_path)?,
+                None => None,
+            };
+            options.push(option.is_some());
+            if let Some((key, option)) = option {
+                match key {
+                    "when" => {
+                        let condition = Condition::parse(option)?;
+                        inherited.when = Condition::and(inherited.when.as_ref(), Some(condition));
+                    }
+                    "tags" => merge_tags(&mut inherited.tags, parse_tags(option)?),
+                    _ => unreachable!("directory_option only returns known options"),
+                }
+            }
+        }
+
+        for (index, (value, is_option)) in sequence.iter().zip(options).enumerate() {
+            let lines = lines.child(index);
             match value {
                 Value::String(string) => {
-                    self.handle_string(entries, string.to_string(), parent_path);
+                    self.handle_string(entries, string.to_string(), lines.known_line(), parent_path, &inherited);
                 }
+                Value::Mapping(_) if is_option => {}
                 Value::Mapping(mapping) => {
                     if mapping.is_an_endpoint()? {
-                        self.handle_endpoint(entries, mapping, parent_path)?;
+                        self.handle_endpoint(entries, mapping, lines.child(0).known_line(), parent_path, &inherited)?;
                     } else {
-                        self.recurse_over_mapping(entries, mapping, parent_path)?;
+                        self.recurse_over_mapping(entries, mapping, lines, parent_path, &inherited)?;
                     }
                 }
                 _ => {
@@ -106,13 +321,18 @@ impl ConfigParser {
         &self,
         entries: &mut Vec<Entry>,
         endpoint: &Mapping,
+        line: Option<usize>,
         parent_path: &Path,
+        inherited: &Inherited,
     ) -> Result<(), PollenError> {
         let key = endpoint.get_key()?;
         let mut entry = Entry::new(EntryArgument {
             name: key.clone(),
             path: parent_path.join(&key),
         });
+        let mut condition = None;
+        let mut merge_format = None;
+        let mut ignore_keys = None;
 
         let sequence = endpoint
             .get_value()?
@@ -129,20 +349,34 @@ impl ConfigParser {
                 ))?;
             
             let key = mapping.get_key()?;
-            let value = mapping.get_value()?.force_into_string()?;
+            let value = mapping.get_value()?;
 
             match key.as_str() {
-                "run_before" => entry.run_before = Some(value),
-                "run_after" => entry.run_after = Some(value),
+                "run_before" => entry.run_before = Some(value.force_into_string()?),
+                "run_after" => entry.run_after = Some(value.force_into_string()?),
+                "before_scatter" => entry.before_scatter = Some(value.force_into_string()?),
+                "after_scatter" => entry.after_scatter = Some(value.force_into_string()?),
+                "before_gather" => entry.before_gather = Some(value.force_into_string()?),
+                "after_gather" => entry.after_gather = Some(value.force_into_string()?),
+                "before_undo" => entry.before_undo = Some(value.force_into_string()?),
+                "after_undo" => entry.after_undo = Some(value.force_into_string()?),
                 "depends_on" => {
                     // depends_on can be a single string or a list of strings
-                    let dependencies = self.parse_dependencies(mapping.get_value()?)?;
-                    entry.depends_on = dependencies;
-                }
-                "alias_as" => {
-                    let alias = value;
-                    entry.alias_as = Some(alias);
+                    entry.depends_on = self.parse_dependencies(value)?;
                 }
+                "optional_depends_on" => entry.optional_depends_on.extend(self.parse_dependencies(value)?),
+                "after" => entry.after.extend(self.parse_dependencies(value)?),
+                "conflicts_with" => entry.conflicts_with.extend(self.parse_dependencies(value)?),
+                "alias_as" => entry.alias_as = Some(value.force_into_string()?),
+                "template" => entry.template = value.force_into_bool()?,
+                "assemble_from" => entry.assemble = Some(Assembly::parse(value)?),
+                "managed_block" => entry.managed_block = value.force_into_bool()?,
+                "merge" => merge_format = Some(MergeFormat::parse(value)?),
+                "ignore_keys" => ignore_keys = Some(parse_ignore_keys(value)?),
+                "encrypt" => entry.encrypt = value.force_into_bool()?,
+                "filters" => entry.filters.extend(self.parse_filters(value)?),
+                "when" => condition = Some(Condition::parse(value)?),
+                "tags" => merge_tags(&mut entry.tags, parse_tags(value)?),
                 _ => {
                     return Err(PollenError::InvalidOption(format!(
                         "Unknown option: {}", key
@@ -151,16 +385,63 @@ impl ConfigParser {
             }
         }
 
+        // An assembled file is owned as a whole, since gather maps it back through its markers
+        if entry.managed_block && entry.assemble.is_some() {
+            return Err(PollenError::InvalidOption(format!(
+                "{} cannot use both managed_block and assemble_from", entry.name
+            )));
+        }
+
+        // Fragments are plain files shared between entries, so there is no single stored copy to encrypt
+        if entry.encrypt && entry.assemble.is_some() {
+            return Err(PollenError::InvalidOption(format!(
+                "{} cannot use both encrypt and assemble_from", entry.name
+            )));
+        }
+
+        match (merge_format, ignore_keys) {
+            (Some(format), ignore_keys) => {
+                // Merging needs the stored copy to parse as it is, and owns keys rather than text
+                let clash = [
+                    (entry.encrypt, "encrypt"),
+                    (entry.template, "template"),
+                    (entry.assemble.is_some(), "assemble_from"),
+                    (entry.managed_block, "managed_block"),
+                ]
+                .into_iter()
+                .find_map(|(used, option)| used.then_some(option));
+                if let Some(option) = clash {
+                    return Err(PollenError::InvalidOption(format!(
+                        "{} cannot use both merge and {}", entry.name, option
+                    )));
+                }
+                entry.merge = Some(Merge { format, ignore_keys: ignore_keys.unwrap_or_default() });
+            }
+            (None, Some(_)) => {
+                return Err(PollenError::InvalidOption(format!(
+                    "{} sets ignore_keys without merge", entry.name
+                )));
+            }
+            (None, None) => {}
+        }
+
+        entry.when = Condition::and(inherited.when.as_ref(), condition);
+        merge_tags(&mut entry.tags, inherited.tags.clone());
+        entry.source = inherited.file.clone().map(|file| EntrySource { file, line });
         entries.push(entry);
         Ok(())
     }
 
     /// Handle a simple string entry
-    fn handle_string(&self, entries: &mut Vec<Entry>, string: String, parent_path: &Path) {
-        entries.push(Entry::new(EntryArgument {
+    fn handle_string(&self, entries: &mut Vec<Entry>, string: String, line: Option<usize>, parent_path: &Path, inherited: &Inherited) {
+        let mut entry = Entry::new(EntryArgument {
             name: string.clone(),
             path: parent_path.join(string),
-        }));
+        });
+        entry.when = inherited.when.clone();
+        entry.tags = inherited.tags.clone();
+        entry.source = inherited.file.clone().map(|file| EntrySource { file, line });
+        entries.push(entry);
     }
 
     /// Parse dependencies from YAML value (can be string or array)
@@ -185,6 +466,26 @@ impl ConfigParser {
         }
     }
 
+    /// Parse the value of a `filters` option, a filter name or a list of them
+    fn parse_filters(&self, value: &Value) -> Result<Vec<String>, PollenError> {
+        let names = match value {
+            Value::Sequence(sequence) => sequence
+                .iter()
+                .map(|name| name.force_into_string())
+                .collect::<Result<Vec<_>, _>>()?,
+            other => vec![other.force_into_string()?],
+        };
+
+        if let Some(known) = &self.filters {
+            if let Some(name) = names.iter().find(|name| !known.contains(*name)) {
+                return Err(PollenError::InvalidOption(format!(
+                    "Unknown filter '{}' (define it under filters in pollen.yaml)", name
+                )));
+            }
+        }
+        Ok(names)
+    }
+
     /// Find an entry by name or alias
     pub fn find_entry_by_name<'a>(&self, entries: &'a [Entry], name: &str) -> Option<&'a Entry> {
         entries.iter().find(|entry| entry.matches_name(name))
@@ -232,85 +533,145 @@ impl ConfigParser {
         Ok(())
     }
 
-    /// Resolve a dependency name (could be an alias) to the actual entry name
-    fn resolve_dependency_name(&self, entries: &[Entry], dep_name: &str) -> Option<String> {
-        entries
-            .iter()
-            .find(|entry| entry.matches_name(dep_name))
-            .map(|entry| entry.name.clone())
+    /// Validate `optional_depends_on`, `after` and `conflicts_with`.
+    ///
+    /// Optional dependencies and `after` may name entries that do not exist, but an entry can
+    /// not refer to itself, and a conflict must name an existing entry it does not depend on.
+    pub fn validate_relations(&self, entries: &[Entry]) -> Result<(), PollenError> {
+        for entry in entries {
+            let relations = [
+                ("depends on", &entry.depends_on),
+                ("optionally depends on", &entry.optional_depends_on),
+                ("is ordered after", &entry.after),
+                ("conflicts with", &entry.conflicts_with),
+            ];
+            for (relation, names) in relations {
+                if let Some(name) = names.iter().find(|name| entry.matches_name(name)) {
+                    return Err(PollenError::InvalidOption(format!(
+                        "Entry '{}' {} itself ('{}')", entry.name, relation, name
+                    )));
+                }
+            }
+
+            for name in &entry.conflicts_with {
+                let Some(other) = entries.iter().find(|other| other.matches_name(name)) else {
+                    return Err(PollenError::MissingDependency(format!(
+                        "Entry '{}' conflicts with '{}' which does not exist", entry.name, name
+                    )));
+                };
+
+                let depends = entry.depends_on.iter().chain(&entry.optional_depends_on).any(|dep| other.matches_name(dep)) ||
+                    other.depends_on.iter().chain(&other.optional_depends_on).any(|dep| entry.matches_name(dep));
+                if depends {
+                    return Err(PollenError::InvalidOption(format!(
+                        "Entries '{}' and '{}' conflict but one depends on the other", entry.name, other.name
+                    )));
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    /// Perform topological sort to order entries based on dependencies
+    /// Order entries so every entry comes after its dependencies.
+    ///
+    /// Among the entries whose dependencies are satisfied, the one written first in the track
+    /// file goes next, so the order is stable and unrelated entries keep their file order.
     fn topological_sort(&self, entries: Vec<Entry>) -> Result<Vec<Entry>, PollenError> {
         // First validate aliases
         self.validate_aliases(&entries)?;
+        self.validate_relations(&entries)?;
 
-        let mut entry_map: HashMap<String, Entry> = HashMap::new();
-        let mut in_degree: HashMap<String, usize> = HashMap::new();
-        let mut adjacency_list: HashMap<String, Vec<String>> = HashMap::new();
-
-        // Build the dependency graph
-        for entry in &entries {
-            entry_map.insert(entry.name.clone(), entry.clone());
-            in_degree.insert(entry.name.clone(), 0);
-            adjacency_list.insert(entry.name.clone(), Vec::new());
-        }
-
-        // Calculate in-degrees and build adjacency list
+        // For each entry, the positions of the entries it comes after: hard and optional
+        // dependencies and `after`, the last two only when they exist
+        let mut dependencies: Vec<Vec<usize>> = Vec::with_capacity(entries.len());
         for entry in &entries {
+            let mut resolved = Vec::new();
             for dep_name in &entry.depends_on {
-                // Resolve dependency name (could be an alias)
-                if let Some(resolved_dep) = self.resolve_dependency_name(&entries, dep_name) {
-                    *in_degree.get_mut(&entry.name).unwrap() += 1;
-                    adjacency_list.get_mut(&resolved_dep).unwrap().push(entry.name.clone());
-                } else {
-                    return Err(PollenError::MissingDependency(format!(
-                        "Entry '{}' depends on '{}' which does not exist",
-                        entry.name, dep_name
-                    )));
+                match entries.iter().position(|other| other.matches_name(dep_name)) {
+                    Some(index) => resolved.push(index),
+                    None => {
+                        return Err(PollenError::MissingDependency(format!(
+                            "Entry '{}' depends on '{}' which does not exist",
+                            entry.name, dep_name
+                        )));
+                    }
                 }
             }
+            for name in entry.optional_depends_on.iter().chain(&entry.after) {
+                resolved.extend(entries.iter().position(|other| other.matches_name(name)));
+            }
+            resolved.sort_unstable();
+            resolved.dedup();
+            dependencies.push(resolved);
         }
 
-        // Kahn's algorithm for topological sorting
-        let mut queue: VecDeque<String> = VecDeque::new();
-        let mut result: Vec<Entry> = Vec::new();
-
-        // Add all entries with no dependencies to the queue
-        for (name, degree) in &in_degree {
-            if *degree == 0 {
-                queue.push_back(name.clone());
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); entries.len()];
+        for (index, deps) in dependencies.iter().enumerate() {
+            for &dep in deps {
+                dependents[dep].push(index);
             }
         }
 
-        while let Some(current) = queue.pop_front() {
-            result.push(entry_map[&current].clone());
+        // Kahn's algorithm, always taking the earliest ready entry
+        let mut in_degree: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+        let mut ready: BTreeSet<usize> = (0..entries.len()).filter(|&index| in_degree[index] == 0).collect();
+        let mut order = Vec::with_capacity(entries.len());
 
-            // Reduce in-degree of dependent entries
-            for dependent in &adjacency_list[&current] {
-                let degree = in_degree.get_mut(dependent).unwrap();
-                *degree -= 1;
-                if *degree == 0 {
-                    queue.push_back(dependent.clone());
+        while let Some(current) = ready.pop_first() {
+            order.push(current);
+            for &dependent in &dependents[current] {
+                in_degree[dependent] -= 1;
+                if in_degree[dependent] == 0 {
+                    ready.insert(dependent);
                 }
             }
         }
 
-        // Check for circular dependencies
-        if result.len() != entries.len() {
-            let remaining: Vec<String> = entries
-                .iter()
-                .filter(|e| !result.iter().any(|r| r.name == e.name))
-                .map(|e| e.name.clone())
-                .collect();
-            
+        if order.len() != entries.len() {
+            let cycle = find_cycle(&dependencies, &in_degree);
+            let path: Vec<String> = cycle.iter().map(|&index| describe_entry(&entries[index])).collect();
             return Err(PollenError::CircularDependency(format!(
-                "Circular dependency detected involving entries: {:?}",
-                remaining
+                "{} (each entry comes after the next)",
+                path.join(" -> ")
             )));
         }
 
-        Ok(result)
+        let mut slots: Vec<Option<Entry>> = entries.into_iter().map(Some).collect();
+        Ok(order.into_iter().filter_map(|index| slots[index].take()).collect())
+    }
+}
+
+/// Find one dependency cycle among the entries left over by the topological sort.
+///
+/// Every leftover entry still waits on another leftover entry, so following those edges from
+/// the first one must loop. Returns the loop with its first entry repeated at the end.
+fn find_cycle(dependencies: &[Vec<usize>], in_degree: &[usize]) -> Vec<usize> {
+    let Some(start) = in_degree.iter().position(|&degree| degree > 0) else {
+        return Vec::new();
+    };
+
+    let mut path = vec![start];
+    let mut current = start;
+    loop {
+        let Some(&next) = dependencies[current].iter().find(|&&dep| in_degree[dep] > 0) else {
+            return path;
+        };
+        if let Some(position) = path.iter().position(|&index| index == next) {
+            let mut cycle = path.split_off(position);
+            cycle.push(next);
+            return cycle;
+        }
+        path.push(next);
+        current = next;
+    }
+}
+
+/// Entry name for messages, with its alias when it has one
+fn describe_entry(entry: &Entry) -> String {
+    match &entry.alias_as {
+        Some(alias) => format!("{} (alias {})", entry.name, alias),
+        None => entry.name.clone(),
     }
 }
 
@@ -320,9 +681,276 @@ impl Default for ConfigParser {
     }
 }
 
+/// Parse an `include` value, either a single file or a list of files
+fn parse_includes(value: &Value) -> Result<Vec<String>, PollenError> {
+    match value {
+        Value::String(file) => Ok(vec![file.clone()]),
+        Value::Sequence(files) => files
+            .iter()
+            .map(|file| {
+                file.as_str().map(str::to_string).ok_or_else(|| {
+                    PollenError::InvalidOption("include must list file names".to_string())
+                })
+            })
+            .collect(),
+        _ => Err(PollenError::InvalidOption(
+            "include must be a file name or a list of file names".to_string()
+        )),
+    }
+}
+
+/// Resolve an included file against the directory of the including file, expanding `~/`
+fn resolve_include(include: &str, base_dir: &Path, home_dir: &Path) -> PathBuf {
+    match include.strip_prefix("~/") {
+        Some(rest) => home_dir.join(rest),
+        None => base_dir.join(include),
+    }
+}
+
+/// Refuse the same path being tracked twice, naming where each is defined
+fn check_duplicate_entries(entries: &[Entry]) -> Result<(), PollenError> {
+    let mut seen: HashMap<PathBuf, &Entry> = HashMap::new();
+    for entry in entries {
+        let path: PathBuf = entry.path.components().filter(|c| !matches!(c, Component::CurDir)).collect();
+        if let Some(first) = seen.insert(path.clone(), entry) {
+            let describe = |entry: &Entry| {
+                entry.source.as_ref().map(ToString::to_string).unwrap_or_else(|| "the track file".to_string())
+            };
+            return Err(PollenError::InvalidEndpoint(format!(
+                "{} is tracked twice, in {} and in {}", path.display(), describe(first), describe(entry)
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Recognise a directory option such as `- when: {...}` or `- tags: [...]` inside a directory's list
+fn directory_option<'a>(mapping: &'a Mapping, parent_path: &Path) -> Result<Option<(&'static str, &'a Value)>, PollenError> {
+    if mapping.len() != 1 {
+        return Ok(None);
+    }
+
+    let Some((Some(key), value)) = mapping.iter().next().map(|(key, value)| (key.as_str(), value)) else {
+        return Ok(None);
+    };
+    let key = match key {
+        "when" => "when",
+        "tags" => "tags",
+        _ => return Ok(None),
+    };
+    match option_reading(key, value) {
+        OptionReading::Option => Ok(Some((key, value))),
+        OptionReading::Content => Ok(None),
+        OptionReading::Ambiguous => Err(PollenError::InvalidMapping(format!(
+            "'{key}' in {} could be {key} for the directory or a directory named '{key}'. \
+             Give one value per item (- {key}: value), or write the directory as a mapping ({key}: {{name: }})",
+            parent_path.display()
+        ))),
+    }
+}
+
+/// Parse a `tags` value, either a single tag or a list of tags
+fn parse_tags(value: &Value) -> Result<Vec<String>, PollenError> {
+    let tags = match value {
+        Value::String(tag) => vec![tag.clone()],
+        Value::Sequence(sequence) => sequence
+            .iter()
+            .map(|tag| tag.force_into_string())
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(PollenError::InvalidOption(format!(
+            "'tags' expects a tag or a list of tags, got {:?}", value
+        ))),
+    };
+
+    // Tag names must survive being used in `--tag` expressions
+    for tag in &tags {
+        let reserved = matches!(tag.as_str(), "and" | "or" | "not");
+        if tag.is_empty() || reserved || tag.contains(|c: char| c.is_whitespace() || "(),!".contains(c)) {
+            return Err(PollenError::InvalidOption(format!("Invalid tag name '{}'", tag)));
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Add tags that are not already present, keeping their order
+fn merge_tags(tags: &mut Vec<String>, additional: Vec<String>) {
+    for tag in additional {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+}
+
 /// Get the user's home directory
 fn get_home_dir() -> Result<PathBuf, PollenError> {
     std::env::var_os("HOME")
         .map(PathBuf::from)
         .ok_or(PollenError::HomeDirectoryNotSet)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<Vec<Entry>, PollenError> {
+        ConfigParser { home_dir: PathBuf::from("/home/user"), filters: None }.parse_content(yaml)
+    }
+
+    fn paths(entries: &[Entry]) -> Vec<String> {
+        entries.iter().map(|entry| entry.path.display().to_string()).collect()
+    }
+
+    #[test]
+    fn option_names_with_directory_contents_are_directories() {
+        let entries = parse("\".config\":\n  - nvim:\n      - init.lua\n      - after: [ftplugin]\n").unwrap();
+        assert_eq!(paths(&entries), ["/home/user/.config/nvim/init.lua", "/home/user/.config/nvim/after/ftplugin"]);
+
+        let entries = parse("\".config\":\n  - nvim:\n      - template: [colors.vim]\n").unwrap();
+        assert_eq!(paths(&entries), ["/home/user/.config/nvim/template/colors.vim"]);
+
+        let entries = parse("\".config\":\n  - nvim:\n      after: [ftplugin]\n").unwrap();
+        assert_eq!(paths(&entries), ["/home/user/.config/nvim/after/ftplugin"]);
+    }
+
+    #[test]
+    fn values_shaped_like_options_are_options() {
+        let entries = parse(
+            "\".config\":\n  - nvim:\n      - after: zsh\n      - after: git\n      - template: true\n  - zsh:\n      - tags: [shell, cli]\n      - when: { os: linux }\n"
+        ).unwrap();
+        let nvim = entries.iter().find(|entry| entry.name == "nvim").unwrap();
+        assert_eq!(nvim.after, ["zsh", "git"]);
+        assert!(nvim.template);
+        let zsh = entries.iter().find(|entry| entry.name == "zsh").unwrap();
+        assert_eq!(zsh.tags, ["shell", "cli"]);
+    }
+
+    #[test]
+    fn lists_that_could_be_either_are_refused() {
+        let error = parse("\".config\":\n  - nvim:\n      - after: [ftplugin]\n").unwrap_err();
+        assert!(error.to_string().contains("'nvim' could be an entry with the after option"), "{}", error);
+
+        let error = parse("\".config\":\n  - tags: [gui]\n  - nvim\n").unwrap_err();
+        assert!(error.to_string().contains("could be tags for the directory"), "{}", error);
+    }
+
+    #[test]
+    fn entries_inherit_the_when_of_their_directory() {
+        let entries = parse("\".config\":\n  when: { os: linux }\n  i3:\n  nvim:\n    - when: { user: ana }\n\".\":\n  - .zshrc\n").unwrap();
+        let when = |name: &str| entries.iter().find(|entry| entry.name == name).unwrap().when.as_ref().map(ToString::to_string);
+        assert_eq!(when("i3").as_deref(), Some("os is linux"));
+        assert_eq!(when("nvim").as_deref(), Some("os is linux and user is ana"));
+        assert_eq!(when(".zshrc"), None);
+    }
+
+    #[test]
+    fn entries_know_the_line_they_are_defined_at() {
+        let content = "\
+# dotfiles
+\".\":
+  - .zshrc
+  - .gitconfig:
+      - depends_on: .zshrc
+\".config\":
+  when: { os: linux }
+  i3:
+  nvim:
+    - alias_as: editor
+\".ssh\": [config, {known_hosts: [{template: true}]}]
+";
+        let parser = ConfigParser { home_dir: PathBuf::from("/home/user"), filters: None };
+        let mut collected = Collected::default();
+        parser.collect_content(content, Some(Path::new("track.yaml")), Path::new("/"), &mut collected).unwrap();
+
+        let lines: Vec<(&str, String)> = collected
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.source.as_ref().unwrap().to_string()))
+            .collect();
+        assert_eq!(lines, [
+            (".zshrc", "track.yaml:3".to_string()),
+            (".gitconfig", "track.yaml:4".to_string()),
+            ("i3", "track.yaml:8".to_string()),
+            ("nvim", "track.yaml:9".to_string()),
+            ("config", "track.yaml:11".to_string()),
+            ("known_hosts", "track.yaml:11".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn entries_follow_their_dependencies_and_keep_file_order_otherwise() {
+        let entries = parse("\".\":\n  - .zshrc:\n      - depends_on: [.profile, .zshenv]\n  - .vimrc\n  - .zshenv:\n      - depends_on: .profile\n  - .profile\n").unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, [".vimrc", ".profile", ".zshenv", ".zshrc"]);
+
+        let error = parse("\".\":\n  - .zshrc:\n      - depends_on: .nope\n").unwrap_err();
+        assert!(matches!(error, PollenError::MissingDependency(_)), "{}", error);
+    }
+
+    #[test]
+    fn cycles_name_every_entry_in_them() {
+        let error = parse(
+            "\".\":\n  - .vimrc\n  - .zshrc:\n      - depends_on: .zshenv\n      - alias_as: zrc\n  - .zshenv:\n      - depends_on: .profile\n  - .profile:\n      - after: zrc\n"
+        ).unwrap_err();
+        assert!(matches!(error, PollenError::CircularDependency(_)), "{}", error);
+        assert!(error.to_string().contains(".zshrc (alias zrc) -> .zshenv -> .profile -> .zshrc (alias zrc)"), "{}", error);
+    }
+
+    #[test]
+    fn optional_relations_order_entries_only_when_present() {
+        let entries = parse(
+            "\".\":\n  - .zshrc:\n      - optional_depends_on: .zshenv\n      - optional_depends_on: .nope\n  - .vimrc:\n      - after: .gitconfig\n  - .zshenv\n  - .gitconfig\n"
+        ).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, [".zshenv", ".zshrc", ".gitconfig", ".vimrc"]);
+    }
+
+    #[test]
+    fn relations_are_validated() {
+        let error = parse("\".\":\n  - .zshrc:\n      - alias_as: zsh\n      - after: zsh\n").unwrap_err();
+        assert!(error.to_string().contains("Entry '.zshrc' is ordered after itself ('zsh')"), "{}", error);
+
+        let error = parse("\".\":\n  - .zshrc:\n      - conflicts_with: .bashrc\n").unwrap_err();
+        assert!(matches!(error, PollenError::MissingDependency(_)), "{}", error);
+
+        let error = parse("\".\":\n  - .zshrc:\n      - conflicts_with: .bashrc\n  - .bashrc:\n      - optional_depends_on: .zshrc\n").unwrap_err();
+        assert!(error.to_string().contains("conflict but one depends on the other"), "{}", error);
+
+        assert!(parse("\".\":\n  - .zshrc:\n      - conflicts_with: .bashrc\n  - .bashrc\n").is_ok());
+    }
+
+    #[test]
+    fn includes_are_read_once_relative_to_the_including_file() {
+        let root = std::env::temp_dir().join(format!("pollen-include-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("hosts")).unwrap();
+        std::fs::write(root.join("track.yaml"), "include: [hosts/work.yaml, shared.yaml]\n\".\":\n  - .zshrc\n").unwrap();
+        std::fs::write(root.join("hosts/work.yaml"), "include: ../shared.yaml\n\".\":\n  - .work\n").unwrap();
+        std::fs::write(root.join("shared.yaml"), "\".\":\n  - .vimrc\n").unwrap();
+
+        let parser = ConfigParser { home_dir: PathBuf::from("/home/user"), filters: None };
+        let entries = parser.parse_file(root.join("track.yaml").to_str().unwrap()).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, [".vimrc", ".work", ".zshrc"]);
+        assert!(entries[0].source.as_ref().unwrap().to_string().ends_with("shared.yaml:2"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_name_the_files_in_them() {
+        let root = std::env::temp_dir().join(format!("pollen-include-cycle-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("track.yaml"), "include: a.yaml\n").unwrap();
+        std::fs::write(root.join("a.yaml"), "include: b.yaml\n").unwrap();
+        std::fs::write(root.join("b.yaml"), "include: a.yaml\n").unwrap();
+
+        let parser = ConfigParser { home_dir: PathBuf::from("/home/user"), filters: None };
+        let error = parser.parse_file(root.join("track.yaml").to_str().unwrap()).unwrap_err();
+        assert!(matches!(error, PollenError::CircularDependency(_)), "{}", error);
+        let canonical = root.canonicalize().unwrap();
+        let cycle = format!("{0}/a.yaml -> {0}/b.yaml -> {0}/a.yaml", canonical.display());
+        assert!(error.to_string().contains(&cycle), "{}", error);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{dirs::{PollenConfig, PollenDirs}, error::PollenError};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+/// Arguments shared by encryption and decryption, so both always agree
+const CIPHER_ARGS: [&str; 6] = ["-aes-256-cbc", "-pbkdf2", "-iter", "200000", "-md", "sha256"];
+
+/// Environment variable the passphrase is handed to openssl in, keeping it off the command line
+const PASSPHRASE_VAR: &str = "POLLEN_ENCRYPTION_PASSPHRASE";
+
+/// Base64 of "Salted__", the start of everything `openssl enc -a` writes
+const ARMOR_PREFIX: &str = "U2FsdGVkX1";
+
+/// Encrypts and decrypts stored copies of entries with `encrypt: true` using the openssl command
+pub struct Cipher {
+    secret: Secret,
+}
+
+enum Secret {
+    KeyFile(PathBuf),
+    Passphrase(String),
+}
+
+impl Cipher {
+    /// The cipher configured in pollen.yaml, preferring the key file over a passphrase
+    pub fn from_config(dirs: &PollenDirs, config: &PollenConfig) -> Result<Cipher, PollenError> {
+        if let Some(key_file) = &config.encryption_key_file {
+            let key_file = expand_home(key_file);
+
+            if !key_file.is_file() {
+                return Err(PollenError::Encryption(format!("Key file {} does not exist", key_file.display())));
+            }
+
+            // A key inside the files directory would end up in the repository next to what it protects.
+            // Both sides are resolved, so neither `..` nor a symlink hides where the key really is
+            let resolved_key = key_file.canonicalize().map_err(PollenError::Io)?;
+            let files_dir = dirs.files_dir.canonicalize().unwrap_or_else(|_| dirs.files_dir.clone());
+            if resolved_key.starts_with(&files_dir) {
+                return Err(PollenError::Encryption(format!(
+                    "The key file {} is inside the files directory; move it elsewhere", key_file.display()
+                )));
+            }
+            return Ok(Cipher { secret: Secret::KeyFile(key_file) });
+        }
+
+        match &config.encryption_passphrase {
+            Some(passphrase) if !passphrase.is_empty() => Ok(Cipher { secret: Secret::Passphrase(passphrase.clone()) }),
+            _ => Err(PollenError::Encryption(
+                "Set encryption_key_file or encryption_passphrase in pollen.yaml to use encrypted entries".to_string()
+            )),
+        }
+    }
+
+    /// Encrypt `plaintext` into base64 text that is safe to commit
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, PollenError> {
+        self.run(&["enc", "-e", "-a", "-salt"], plaintext)
+    }
+
+    /// Decrypt what `encrypt` produced
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, PollenError> {
+        self.run(&["enc", "-d", "-a"], ciphertext).map_err(|e| match e {
+            PollenError::Encryption(message) => PollenError::Encryption(format!("{} (wrong key or passphrase?)", message)),
+            other => other,
+        })
+    }
+
+    fn run(&self, args: &[&str], input: &[u8]) -> Result<Vec<u8>, PollenError> {
+        let mut command = Command::new("openssl");
+        command.args(args).args(CIPHER_ARGS);
+        match &self.secret {
+            Secret::KeyFile(path) => command.arg("-pass").arg(format!("file:{}", path.display())),
+            Secret::Passphrase(passphrase) => command.env(PASSPHRASE_VAR, passphrase).arg("-pass").arg(format!("env:{}", PASSPHRASE_VAR)),
+        };
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| PollenError::Encryption(format!("Cannot run openssl: {}", e)))?;
+
+        // openssl writes while it reads, so stdin is fed from another thread to keep both pipes moving
+        let writer = child.stdin.take().map(|mut stdin| {
+            let input = input.to_vec();
+            thread::spawn(move || stdin.write_all(&input))
+        });
+        let output = child.wait_with_output().map_err(PollenError::Io)?;
+        let written = writer.map(|writer| writer.join());
+
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            let message = message.lines().next().unwrap_or("").trim();
+            return Err(PollenError::Encryption(format!("openssl failed: {}", message)));
+        }
+        match written {
+            Some(Ok(Err(e))) => Err(PollenError::Io(e)),
+            Some(Err(_)) => Err(PollenError::Encryption("Writing to openssl failed".to_string())),
+            _ => Ok(output.stdout),
+        }
+    }
+}
+
+/// Whether `content` looks like something `Cipher::encrypt` wrote
+pub fn is_encrypted(content: &[u8]) -> bool {
+    content.starts_with(ARMOR_PREFIX.as_bytes())
+}
+
+/// Write plaintext that only the owner can read, whatever mode the file had before
+pub fn write_private(path: &Path, content: &[u8]) -> Result<(), PollenError> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(PollenError::Io)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600)).map_err(PollenError::Io)?;
+    file.write_all(content).map_err(PollenError::Io)
+}
+
+/// `path` with a leading `~/` replaced by the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_writes_narrow_existing_files() {
+        let path = std::env::temp_dir().join(format!("pollen-crypt-private-{}", std::process::id()));
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, b"plaintext").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"plaintext");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ciphertext_round_trips_and_needs_the_same_secret() {
+        let root = std::env::temp_dir().join(format!("pollen-crypt-cipher-{}", std::process::id()));
+        let dirs = PollenDirs::from_base_dirs(root.join("config"), root.join("state"), root.join("data"), root.join("cache")).unwrap();
+        let mut config = PollenConfig { encryption_passphrase: Some("hunter2".to_string()), ..PollenConfig::default() };
+        let cipher = Cipher::from_config(&dirs, &config).unwrap();
+
+        let ciphertext = cipher.encrypt(b"machine example.com password s3cret\n").unwrap();
+        assert!(is_encrypted(&ciphertext));
+        assert!(!is_encrypted(b"machine example.com"));
+        assert_eq!(cipher.decrypt(&ciphertext).unwrap(), b"machine example.com password s3cret\n");
+
+        config.encryption_passphrase = Some("hunter3".to_string());
+        let error = Cipher::from_config(&dirs, &config).unwrap().decrypt(&ciphertext).unwrap_err();
+        assert!(error.to_string().contains("wrong key or passphrase?"), "{}", error);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn key_files_must_exist_outside_the_files_directory() {
+        let root = std::env::temp_dir().join(format!("pollen-crypt-key-{}", std::process::id()));
+        let dirs = PollenDirs::from_base_dirs(root.join("config"), root.join("state"), root.join("data"), root.join("cache")).unwrap();
+        fs::create_dir_all(dirs.files_dir.join("sub")).unwrap();
+        let key_file = |path: &Path| PollenConfig {
+            encryption_key_file: Some(path.display().to_string()),
+            encryption_passphrase: Some("unused".to_string()),
+            ..PollenConfig::default()
+        };
+
+        let inside = dirs.files_dir.join("key");
+        fs::write(&inside, "secret").unwrap();
+        let error = Cipher::from_config(&dirs, &key_file(&dirs.files_dir.join("sub/../key"))).err().unwrap();
+        assert!(error.to_string().contains("is inside the files directory"), "{}", error);
+        assert!(Cipher::from_config(&dirs, &key_file(&root.join("missing"))).is_err());
+
+        let outside = root.join("key");
+        fs::write(&outside, "secret").unwrap();
+        let cipher = Cipher::from_config(&dirs, &key_file(&outside)).unwrap();
+        assert_eq!(cipher.decrypt(&cipher.encrypt(b"token").unwrap()).unwrap(), b"token");
+
+        assert!(Cipher::from_config(&dirs, &PollenConfig::default()).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
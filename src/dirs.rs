@@ -1,23 +1,39 @@
-use crate::error::PollenError;
+use crate::{error::PollenError, filter::FilterDefinition, lock::PollenLock, profile::ProfileDefinition, scan::{report, scan_path, Allowlist, ScanMode}, trash::move_path};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs::{self, File},
     io::Read,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 /// Pollen directory structure and configuration management
 pub struct PollenDirs {
-    /// The main pollen config directory (~/.config/pollen)
+    /// The main pollen config directory ($XDG_CONFIG_HOME/pollen)
     pub config_dir: PathBuf,
-    /// The cache directory (~/.config/pollen/cache)
+    /// The state directory for history and other persistent state ($XDG_STATE_HOME/pollen)
+    pub state_dir: PathBuf,
+    /// The data directory ($XDG_DATA_HOME/pollen)
+    pub data_dir: PathBuf,
+    /// The disposable cache directory ($XDG_CACHE_HOME/pollen)
     pub cache_dir: PathBuf,
-    /// The files directory (~/.config/pollen/files)
+    /// The directory backups are written to (data or cache directory, see `backup_location`)
+    pub backup_dir: PathBuf,
+    /// Where removed and replaced files are kept ($XDG_DATA_HOME/pollen/trash)
+    pub trash_dir: PathBuf,
+    /// The files directory ($XDG_CONFIG_HOME/pollen/files)
     pub files_dir: PathBuf,
     /// Path to track.yaml file
     pub track_file: PathBuf,
     /// Path to pollen.yaml config file
     pub pollen_config_file: PathBuf,
+    /// Path to the machine-local local.yaml settings, never committed
+    pub local_config_file: PathBuf,
+    /// Path to the operation history used by undo
+    pub operations_file: PathBuf,
+    /// Path to the lock file held by mutating commands
+    pub lock_file: PathBuf,
 }
 
 /// Configuration settings for Pollen
@@ -37,6 +53,32 @@ pub struct PollenConfig {
     pub auto_commit: Option<bool>,
     /// Default commit message for auto-commits
     pub auto_commit_message: Option<String>,
+    /// Where backups are stored: "data" (kept until undone) or "cache" (expire with the cache)
+    pub backup_location: Option<String>,
+    /// Trash items older than this many seconds are deleted automatically
+    pub trash_max_age: Option<u64>,
+    /// Oldest trash items are deleted once the trash grows beyond this many bytes
+    pub trash_max_size: Option<u64>,
+    /// Also operate on the dependencies of selected entries, as with `--with-deps`
+    pub with_deps: Option<bool>,
+    /// Variables available to templates, overridden by local.yaml
+    pub variables: Option<serde_yaml::Mapping>,
+    /// Named sets of entries selectable with `--profile`
+    pub profiles: Option<BTreeMap<String, ProfileDefinition>>,
+    /// File holding the passphrase for entries with `encrypt: true`, kept outside the files directory
+    pub encryption_key_file: Option<String>,
+    /// Passphrase for entries with `encrypt: true`, used when no key file is set
+    pub encryption_passphrase: Option<String>,
+    /// YAML file of values for `{{ secret "name" }}` placeholders (default: secrets.yaml next to pollen.yaml)
+    pub secrets_file: Option<String>,
+    /// Command printing the value of a secret not found elsewhere, with `{name}` replaced by its name
+    pub secret_command: Option<String>,
+    /// When secret scanning blocks: "commit" (default), "gather" or "off"
+    pub secret_scan: Option<String>,
+    /// File listing false positives of secret scanning (default: .secret-allowlist in the files directory)
+    pub secret_allowlist: Option<String>,
+    /// Named clean/smudge filters that entries refer to with `filters:`
+    pub filters: Option<BTreeMap<String, FilterDefinition>>,
 }
 
 impl Default for PollenConfig {
@@ -49,54 +91,182 @@ impl Default for PollenConfig {
             max_cache_entries: Some(100),
             auto_commit: Some(false),
             auto_commit_message: Some("Pollen auto-sync".to_string()),
+            backup_location: Some("data".to_string()),
+            trash_max_age: Some(30 * 86400), // 30 days
+            trash_max_size: Some(1024 * 1024 * 1024), // 1 GiB
+            with_deps: Some(false),
+            variables: None,
+            profiles: None,
+            encryption_key_file: None,
+            encryption_passphrase: None,
+            secrets_file: None,
+            secret_command: None,
+            secret_scan: Some("commit".to_string()),
+            secret_allowlist: None,
+            filters: None,
         }
     }
 }
 
+/// Machine-local settings from local.yaml, kept out of the files repository
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LocalConfig {
+    /// Template variables for this machine, overriding those in pollen.yaml
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variables: Option<serde_yaml::Mapping>,
+    /// Profile used when no `--profile` or entry names are given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
 impl PollenDirs {
     /// Initialize the Pollen directory structure
     pub fn new() -> Result<Self, PollenError> {
-        let config_dir = if let Some(pollen_dir) = std::env::var_os("POLLEN_DIR") {
-            // Use POLLEN_DIR environment variable if set
-            PathBuf::from(pollen_dir)
-        } else {
-            // Default to ~/.config/pollen
-            let home_dir = std::env::var_os("HOME")
-                .map(PathBuf::from)
-                .ok_or(PollenError::HomeDirectoryNotSet)?;
-            
-            home_dir.join(".config").join("pollen")
-        };
-        
-        let cache_dir = config_dir.join("cache");
+        let (config_dir, state_dir, data_dir, cache_dir) =
+            if let Some(pollen_dir) = std::env::var_os("POLLEN_DIR") {
+                // POLLEN_DIR overrides every location and keeps everything in one place
+                let root = PathBuf::from(pollen_dir);
+                (root.clone(), root.join("state"), root.join("data"), root.join("cache"))
+            } else {
+                let home_dir = std::env::var_os("HOME")
+                    .map(PathBuf::from)
+                    .ok_or(PollenError::HomeDirectoryNotSet)?;
+
+                (
+                    xdg_base_dir("XDG_CONFIG_HOME", &home_dir, ".config").join("pollen"),
+                    xdg_base_dir("XDG_STATE_HOME", &home_dir, ".local/state").join("pollen"),
+                    xdg_base_dir("XDG_DATA_HOME", &home_dir, ".local/share").join("pollen"),
+                    xdg_base_dir("XDG_CACHE_HOME", &home_dir, ".cache").join("pollen"),
+                )
+            };
+        
+        Self::from_base_dirs(config_dir, state_dir, data_dir, cache_dir)
+    }
+    
+    /// Set up the directory structure under the given config, state, data and cache directories
+    pub(crate) fn from_base_dirs(config_dir: PathBuf, state_dir: PathBuf, data_dir: PathBuf, cache_dir: PathBuf) -> Result<Self, PollenError> {
         let files_dir = config_dir.join("files");
         let track_file = config_dir.join("track.yaml");
         let pollen_config_file = config_dir.join("pollen.yaml");
+        let local_config_file = config_dir.join("local.yaml");
+        let trash_dir = data_dir.join("trash");
+        let operations_file = state_dir.join("operations.json");
+        let lock_file = state_dir.join("pollen.lock");
         
-        let dirs = Self {
+        let mut dirs = Self {
             config_dir,
+            state_dir,
+            data_dir,
+            backup_dir: cache_dir.clone(),
             cache_dir,
+            trash_dir,
             files_dir,
             track_file,
             pollen_config_file,
+            local_config_file,
+            operations_file,
+            lock_file,
         };
         
-        dirs.ensure_directories_exist()?;
+        fs::create_dir_all(&dirs.config_dir)
+            .map_err(PollenError::Io)?;
         dirs.ensure_config_exists()?;
         
+        let config = dirs.load_config()?;
+        if config.backup_location.as_deref() != Some("cache") {
+            dirs.backup_dir = dirs.data_dir.join("backups");
+        }
+        
+        dirs.ensure_directories_exist()?;
+        dirs.migrate_legacy_state()?;
+        
         Ok(dirs)
     }
     
     /// Ensure all required directories exist
     fn ensure_directories_exist(&self) -> Result<(), PollenError> {
-        fs::create_dir_all(&self.config_dir)
-            .map_err(|e| PollenError::Io(e))?;
+        for dir in [
+            &self.config_dir,
+            &self.state_dir,
+            &self.data_dir,
+            &self.cache_dir,
+            &self.backup_dir,
+            &self.trash_dir,
+            &self.files_dir,
+        ] {
+            fs::create_dir_all(dir)
+                .map_err(PollenError::Io)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Move operation history and backups out of the old `<config>/cache` location into the
+    /// state and backup directories
+    fn migrate_legacy_state(&self) -> Result<(), PollenError> {
+        let legacy_dir = self.config_dir.join("cache");
+        let legacy_operations = legacy_dir.join("operations.json");
+        let has_operations = || legacy_operations.exists() && !self.operations_file.exists();
+        let legacy_backups = || -> Vec<(PathBuf, PathBuf)> {
+            fs::read_dir(&legacy_dir)
+                .map(|children| {
+                    children
+                        .flatten()
+                        .filter_map(|child| {
+                            let name = child.file_name().to_string_lossy().into_owned();
+                            let backup_name = legacy_backup_name(&name)?;
+                            Some((child.path(), self.get_backup_file_path(backup_name)))
+                        })
+                        .filter(|(_, destination)| destination.symlink_metadata().is_err())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
         
-        fs::create_dir_all(&self.cache_dir)
-            .map_err(|e| PollenError::Io(e))?;
+        if !has_operations() && legacy_backups().is_empty() {
+            return Ok(());
+        }
         
-        fs::create_dir_all(&self.files_dir)
-            .map_err(|e| PollenError::Io(e))?;
+        // Another pollen run may be migrating or using the history at the same time
+        let _lock = PollenLock::acquire(self, true)?;
+        
+        if has_operations() {
+            move_path(&legacy_operations, &self.operations_file)?;
+            println!(
+                "Migrated operation history to {}",
+                self.operations_file.display()
+            );
+        }
+        
+        let backups = legacy_backups();
+        if !backups.is_empty() {
+            for (legacy_path, backup_path) in &backups {
+                move_path(legacy_path, backup_path)?;
+            }
+            
+            // Undo finds backups through the history, so it has to point at their new location
+            let mut operations = self.get_operations()?;
+            for op_entry in operations.iter_mut().flat_map(|operation| operation.entries.iter_mut()) {
+                let Some(backup) = &op_entry.backup_path else {
+                    continue;
+                };
+                if let Some((_, backup_path)) = backups.iter().find(|(legacy_path, _)| Path::new(backup) == legacy_path) {
+                    op_entry.backup_path = Some(backup_path.display().to_string());
+                }
+            }
+            self.save_operations(&operations)?;
+            
+            println!(
+                "Migrated {} backups to {}",
+                backups.len(),
+                self.backup_dir.display()
+            );
+        }
+        
+        // Only removed once nothing else is left in it, and never when POLLEN_DIR makes it the cache
+        if legacy_dir != self.cache_dir {
+            let _ = fs::remove_dir(&legacy_dir);
+        }
         
         Ok(())
     }
@@ -106,10 +276,10 @@ impl PollenDirs {
         if !self.pollen_config_file.exists() {
             let default_config = PollenConfig::default();
             let config_content = serde_yaml::to_string(&default_config)
-                .map_err(|e| PollenError::Yaml(e))?;
+                .map_err(PollenError::Yaml)?;
             
             fs::write(&self.pollen_config_file, config_content)
-                .map_err(|e| PollenError::Io(e))?;
+                .map_err(PollenError::Io)?;
         }
         
         Ok(())
@@ -124,21 +294,49 @@ impl PollenDirs {
         let mut content = String::new();
         File::open(&self.pollen_config_file)
             .and_then(|mut file| file.read_to_string(&mut content))
-            .map_err(|e| PollenError::Io(e))?;
+            .map_err(PollenError::Io)?;
         
         let config: PollenConfig = serde_yaml::from_str(&content)
-            .map_err(|e| PollenError::Yaml(e))?;
+            .map_err(PollenError::Yaml)?;
         
         Ok(config)
     }
     
+    /// Load the machine-local settings, which are optional
+    pub fn load_local_config(&self) -> Result<LocalConfig, PollenError> {
+        if !self.local_config_file.exists() {
+            return Ok(LocalConfig::default());
+        }
+        
+        let content = fs::read_to_string(&self.local_config_file)
+            .map_err(PollenError::Io)?;
+        
+        // An empty local.yaml is valid
+        if content.trim().is_empty() {
+            return Ok(LocalConfig::default());
+        }
+        
+        serde_yaml::from_str(&content).map_err(PollenError::Yaml)
+    }
+    
+    /// Save the machine-local settings
+    pub fn save_local_config(&self, local: &LocalConfig) -> Result<(), PollenError> {
+        let content = serde_yaml::to_string(local)
+            .map_err(PollenError::Yaml)?;
+        
+        fs::write(&self.local_config_file, content)
+            .map_err(PollenError::Io)?;
+        
+        Ok(())
+    }
+    
     /// Save the pollen configuration
     pub fn save_config(&self, config: &PollenConfig) -> Result<(), PollenError> {
         let config_content = serde_yaml::to_string(config)
-            .map_err(|e| PollenError::Yaml(e))?;
+            .map_err(PollenError::Yaml)?;
         
         fs::write(&self.pollen_config_file, config_content)
-            .map_err(|e| PollenError::Io(e))?;
+            .map_err(PollenError::Io)?;
         
         Ok(())
     }
@@ -169,7 +367,8 @@ impl PollenDirs {
 # This file defines which configuration files and directories to track
 #
 # Environment variables:
-#   POLLEN_DIR       - Custom directory location (default: ~/.config/pollen)
+#   POLLEN_DIR       - Custom directory location for config, state, data and cache
+#                      (default: XDG base directories, e.g. ~/.config/pollen)
 #   POLLEN_UNDO_LIMIT - Maximum number of undo operations to keep (default: 10)
 #
 #                      __
@@ -200,7 +399,7 @@ impl PollenDirs {
 "#;
             
             fs::write(&self.track_file, default_content)
-                .map_err(|e| PollenError::Io(e))?;
+                .map_err(PollenError::Io)?;
         }
         
         Ok(())
@@ -211,6 +410,30 @@ impl PollenDirs {
         self.cache_dir.join(format!("{}.cache", key))
     }
 
+    /// Get a backup file path for a given key
+    pub fn get_backup_file_path(&self, key: &str) -> PathBuf {
+        self.backup_dir.join(key)
+    }
+    
+    /// Pick an unused backup path for `name`, `<name>_<timestamp>.backup`, numbered when another
+    /// backup of `name` was already taken in the same second
+    pub fn new_backup_file_path(&self, name: &str) -> Result<PathBuf, PollenError> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| PollenError::Io(std::io::Error::other(
+                "Failed to get system time"
+            )))?
+            .as_secs();
+        
+        let mut backup_path = self.get_backup_file_path(&format!("{}_{}.backup", name, timestamp));
+        let mut counter = 1;
+        while backup_path.symlink_metadata().is_ok() {
+            backup_path = self.get_backup_file_path(&format!("{}_{}-{}.backup", name, timestamp, counter));
+            counter += 1;
+        }
+        Ok(backup_path)
+    }
+
     /// Get a file path within the files directory
     pub fn get_files_path(&self, filename: &str) -> PathBuf {
         self.files_dir.join(filename)
@@ -292,7 +515,7 @@ desktop.ini
         
         // Try to initialize git repo
         let output = std::process::Command::new("git")
-            .args(&["init"])
+            .args(["init"])
             .current_dir(&self.files_dir)
             .output();
             
@@ -327,12 +550,14 @@ desktop.ini
             ));
         }
         
+        self.scan_uncommitted()?;
+        
         // Stage all changes
         let add_output = std::process::Command::new("git")
-            .args(&["add", "."])
+            .args(["add", "."])
             .current_dir(&self.files_dir)
             .output()
-            .map_err(|e| PollenError::Io(e))?;
+            .map_err(PollenError::Io)?;
             
         if !add_output.status.success() {
             return Err(PollenError::InvalidEndpoint(
@@ -342,10 +567,10 @@ desktop.ini
         
         // Check if there are changes to commit
         let status_output = std::process::Command::new("git")
-            .args(&["diff", "--cached", "--quiet"])
+            .args(["diff", "--cached", "--quiet"])
             .current_dir(&self.files_dir)
             .output()
-            .map_err(|e| PollenError::Io(e))?;
+            .map_err(PollenError::Io)?;
             
         if status_output.status.success() {
             // No changes to commit
@@ -354,10 +579,10 @@ desktop.ini
         
         // Commit changes
         let commit_output = std::process::Command::new("git")
-            .args(&["commit", "-m", message])
+            .args(["commit", "-m", message])
             .current_dir(&self.files_dir)
             .output()
-            .map_err(|e| PollenError::Io(e))?;
+            .map_err(PollenError::Io)?;
             
         if !commit_output.status.success() {
             return Err(PollenError::InvalidEndpoint(
@@ -368,9 +593,57 @@ desktop.ini
         Ok(())
     }
 
+    /// Refuse to go on when new or changed files in the files directory look like they hold secrets
+    fn scan_uncommitted(&self) -> Result<(), PollenError> {
+        let config = self.load_config()?;
+        if ScanMode::from_config(&config)? == ScanMode::Off {
+            return Ok(());
+        }
+        
+        // Changes in the worktree, and those already staged, which the commit picks up as well
+        let listings: [&[&str]; 2] = [
+            &["ls-files", "-z", "--modified", "--others", "--exclude-standard"],
+            &["diff", "--cached", "--name-only", "-z", "--diff-filter=d"],
+        ];
+        let mut changed: Vec<String> = Vec::new();
+        for args in listings {
+            let output = std::process::Command::new("git")
+                .args(args)
+                .current_dir(&self.files_dir)
+                .output()
+                .map_err(PollenError::Io)?;
+            if !output.status.success() {
+                return Err(PollenError::InvalidEndpoint(
+                    "Failed to list changed files".to_string()
+                ));
+            }
+            
+            changed.extend(
+                String::from_utf8_lossy(&output.stdout)
+                    .split('\0')
+                    .filter(|path| !path.is_empty())
+                    .map(str::to_string)
+            );
+        }
+        changed.sort();
+        changed.dedup();
+        
+        let allowlist = Allowlist::from_config(self, &config)?;
+        let findings: Vec<_> = changed
+            .iter()
+            .flat_map(|path| scan_path(&self.files_dir.join(path), path, &allowlist))
+            .collect();
+        
+        if findings.is_empty() {
+            Ok(())
+        } else {
+            Err(PollenError::SecretsFound(report(&findings, &allowlist)))
+        }
+    }
+    
     /// Save an operation to the operation history
     pub fn save_operation(&self, operation: &Operation) -> Result<(), PollenError> {
-        let operation_file = self.cache_dir.join("operations.json");
+        let operation_file = &self.operations_file;
         
         // Get the undo limit from environment variable or use default
         let undo_limit = std::env::var("POLLEN_UNDO_LIMIT")
@@ -380,15 +653,25 @@ desktop.ini
         
         // Load existing operations
         let mut operations: Vec<Operation> = if operation_file.exists() {
-            let content = std::fs::read_to_string(&operation_file)
+            let content = std::fs::read_to_string(operation_file)
                 .map_err(PollenError::Io)?;
             serde_json::from_str(&content).unwrap_or_default()
         } else {
             Vec::new()
         };
         
+        // Several operations can run within the same second, so the ID counts them
+        let sequence = operations
+            .iter()
+            .filter(|existing| existing.timestamp == operation.timestamp)
+            .filter_map(|existing| existing.id.rsplit_once('-')?.1.parse::<u32>().ok())
+            .max()
+            .map_or(0, |sequence| sequence + 1);
+        let mut operation = operation.clone();
+        operation.id = format!("{}-{}", operation.timestamp, sequence);
+        
         // Add new operation
-        operations.push(operation.clone());
+        operations.push(operation);
         
         // Keep only the last N operations based on the limit
         if operations.len() > undo_limit {
@@ -399,38 +682,59 @@ desktop.ini
         let content = serde_json::to_string_pretty(&operations)
             .map_err(|e| PollenError::InvalidMapping(format!("JSON serialization error: {}", e)))?;
         
-        std::fs::write(&operation_file, content)
+        std::fs::write(operation_file, content)
             .map_err(PollenError::Io)?;
         
         Ok(())
     }
     
-    /// Get the last operation from history
-    pub fn get_last_operation(&self) -> Result<Option<Operation>, PollenError> {
-        let operation_file = self.cache_dir.join("operations.json");
+    /// Get the full operation history, oldest first
+    pub fn get_operations(&self) -> Result<Vec<Operation>, PollenError> {
+        let operation_file = &self.operations_file;
         
         if !operation_file.exists() {
-            return Ok(None);
+            return Ok(Vec::new());
         }
         
-        let content = std::fs::read_to_string(&operation_file)
+        let content = std::fs::read_to_string(operation_file)
             .map_err(PollenError::Io)?;
         
-        let operations: Vec<Operation> = serde_json::from_str(&content)
-            .map_err(|e| PollenError::InvalidMapping(format!("JSON deserialization error: {}", e)))?;
+        serde_json::from_str(&content)
+            .map_err(|e| PollenError::InvalidMapping(format!("JSON deserialization error: {}", e)))
+    }
+    
+    /// Replace the operation history
+    fn save_operations(&self, operations: &[Operation]) -> Result<(), PollenError> {
+        let content = serde_json::to_string_pretty(operations)
+            .map_err(|e| PollenError::InvalidMapping(format!("JSON serialization error: {}", e)))?;
         
-        Ok(operations.last().cloned())
+        std::fs::write(&self.operations_file, content)
+            .map_err(PollenError::Io)
+    }
+    
+    /// Get the last operation from history
+    pub fn get_last_operation(&self) -> Result<Option<Operation>, PollenError> {
+        Ok(self.get_operations()?.last().cloned())
+    }
+    
+    /// Replace the last operation in history, keeping its ID
+    pub fn replace_last_operation(&self, operation: &Operation) -> Result<(), PollenError> {
+        let mut operations = self.get_operations()?;
+        if let Some(last) = operations.last_mut() {
+            *last = operation.clone();
+        }
+        self.save_operations(&operations)
     }
     
     /// Remove the last operation from history
     pub fn remove_last_operation(&self) -> Result<(), PollenError> {
-        let operation_file = self.cache_dir.join("operations.json");
+        let operation_file = &self.operations_file;
         
         if !operation_file.exists() {
             return Ok(());
         }
         
-        let content = std::fs::read_to_string(&operation_file)
+        let content = std::fs::read_to_string(operation_file)
             .map_err(PollenError::Io)?;
         
         let mut operations: Vec<Operation> = serde_json::from_str(&content)
@@ -442,7 +746,7 @@ desktop.ini
             let content = serde_json::to_string_pretty(&operations)
                 .map_err(|e| PollenError::InvalidMapping(format!("JSON serialization error: {}", e)))?;
             
-            std::fs::write(&operation_file, content)
+            std::fs::write(operation_file, content)
                 .map_err(PollenError::Io)?;
         }
         
@@ -450,18 +754,60 @@ desktop.ini
     }
 }
 
+/// The name a legacy `<config>/cache` backup keeps in the backup directory, if the file is one.
+/// Backups used to be cache files, named `<entry>_<timestamp>.backup.cache`.
+fn legacy_backup_name(name: &str) -> Option<&str> {
+    let name = name.strip_suffix(".cache").unwrap_or(name);
+    name.ends_with(".backup").then_some(name)
+}
+
+/// Resolve an XDG base directory, falling back to a path under the home directory
+fn xdg_base_dir(variable: &str, home_dir: &Path, fallback: &str) -> PathBuf {
+    match std::env::var_os(variable).map(PathBuf::from) {
+        // The spec requires these paths to be absolute; relative values are ignored
+        Some(path) if path.is_absolute() => path,
+        _ => home_dir.join(fallback),
+    }
+}
+
 /// Represents an operation that was performed
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Operation {
+    /// Unique ID, `<timestamp>-<sequence>`; assigned when the operation is saved
+    #[serde(default)]
+    pub id: String,
     pub operation_type: OperationType,
     pub timestamp: u64,
     pub entries: Vec<OperationEntry>,
 }
 
+impl Operation {
+    /// The operation's ID, or its timestamp for operations recorded before IDs existed
+    pub fn id(&self) -> String {
+        if self.id.is_empty() {
+            self.timestamp.to_string()
+        } else {
+            self.id.clone()
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OperationType {
     Gather,
     Scatter,
+    Restore,
+}
+
+impl OperationType {
+    /// Lowercase name used in output
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationType::Gather => "gather",
+            OperationType::Scatter => "scatter",
+            OperationType::Restore => "restore",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -470,4 +816,59 @@ pub struct OperationEntry {
     pub source_path: String,
     pub target_path: String,
     pub backup_path: Option<String>,
+    /// The operation changed only the entry's managed block, and the backup holds the previous block
+    #[serde(default)]
+    pub managed_block: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xdg_dirs_must_be_absolute() {
+        let home = Path::new("/home/user");
+        let variable = "POLLEN_TEST_XDG_STATE_HOME";
+
+        std::env::remove_var(variable);
+        assert_eq!(xdg_base_dir(variable, home, ".local/state"), home.join(".local/state"));
+        std::env::set_var(variable, "relative/state");
+        assert_eq!(xdg_base_dir(variable, home, ".local/state"), home.join(".local/state"));
+        std::env::set_var(variable, "/var/state");
+        assert_eq!(xdg_base_dir(variable, home, ".local/state"), PathBuf::from("/var/state"));
+        std::env::remove_var(variable);
+    }
+
+    #[test]
+    fn legacy_history_and_backups_are_migrated() {
+        let root = std::env::temp_dir().join(format!("pollen-dirs-migrate-{}", std::process::id()));
+        let legacy_dir = root.join("config/cache");
+        fs::create_dir_all(&legacy_dir).unwrap();
+        fs::write(legacy_dir.join(".zshrc_1700000000.backup"), "old zshrc").unwrap();
+        fs::write(legacy_dir.join("templates.cache"), "disposable").unwrap();
+        let operations = vec![Operation {
+            id: String::new(),
+            operation_type: OperationType::Scatter,
+            timestamp: 1700000000,
+            entries: vec![OperationEntry {
+                entry_name: ".zshrc".to_string(),
+                source_path: "/home/user/.zshrc".to_string(),
+                target_path: "/home/user/.config/pollen/files/.zshrc".to_string(),
+                backup_path: Some(legacy_dir.join(".zshrc_1700000000.backup").display().to_string()),
+                managed_block: false,
+            }],
+        }];
+        fs::write(legacy_dir.join("operations.json"), serde_json::to_string(&operations).unwrap()).unwrap();
+
+        let dirs = PollenDirs::from_base_dirs(root.join("config"), root.join("state"), root.join("data"), root.join("cache")).unwrap();
+
+        let backup_path = dirs.backup_dir.join(".zshrc_1700000000.backup");
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), "old zshrc");
+        let migrated = dirs.get_operations().unwrap();
+        assert_eq!(migrated[0].entries[0].backup_path.as_deref(), Some(backup_path.display().to_string().as_str()));
+        assert!(!legacy_dir.join("operations.json").exists());
+        // What is not history or a backup is left where it was
+        assert!(legacy_dir.join("templates.cache").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
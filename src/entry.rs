@@ -1,4 +1,5 @@
-use std::path::PathBuf;
+use crate::{assemble::Assembly, condition::Condition, host::HostFacts, merge::Merge};
+use std::{fmt, path::PathBuf};
 
 /// Represents a configuration entry (file or directory) to be managed by Pollen
 #[derive(Debug, Clone)]
@@ -11,10 +12,85 @@ pub struct Entry {
     pub run_before: Option<String>,
     /// Command to run after processing this entry
     pub run_after: Option<String>,
+    /// Command to run before scattering this entry, after `run_before`
+    pub before_scatter: Option<String>,
+    /// Command to run after scattering this entry, before `run_after`
+    pub after_scatter: Option<String>,
+    /// Command to run before gathering this entry, after `run_before`
+    pub before_gather: Option<String>,
+    /// Command to run after gathering this entry, before `run_after`
+    pub after_gather: Option<String>,
+    /// Command to run before undo reverts this entry
+    pub before_undo: Option<String>,
+    /// Command to run after undo reverted this entry
+    pub after_undo: Option<String>,
     /// Names of entries this entry depends on
     pub depends_on: Vec<String>,
+    /// Entries this entry depends on when they exist and apply to this machine
+    pub optional_depends_on: Vec<String>,
+    /// Entries this entry is processed after when they are processed too, without depending on them
+    pub after: Vec<String>,
+    /// Entries that must not be scattered together with this entry
+    pub conflicts_with: Vec<String>,
     /// Alias for this entry (optional shorter name)
     pub alias_as: Option<String>,
+    /// Whether the stored copy is a template rendered on scatter
+    pub template: bool,
+    /// Fragments the target is assembled from, instead of a stored copy
+    pub assemble: Option<Assembly>,
+    /// Whether pollen only owns a marked block inside the target file instead of the whole file
+    pub managed_block: bool,
+    /// How the stored document is merged key by key into the target, instead of replacing it
+    pub merge: Option<Merge>,
+    /// Whether the stored copy is encrypted, so it can be committed without exposing its content
+    pub encrypt: bool,
+    /// Names of filters from pollen.yaml the content passes through between system and files directory
+    pub filters: Vec<String>,
+    /// Condition deciding whether the entry applies to this machine, including inherited ones
+    pub when: Option<Condition>,
+    /// Tags for selecting groups of entries, including those inherited from parent directories
+    pub tags: Vec<String>,
+    /// Track file and line the entry is defined at, when parsed from a file
+    pub source: Option<EntrySource>,
+}
+
+/// Where in the track files an entry is defined
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntrySource {
+    /// Track file, as given on the command line or in `include`
+    pub file: PathBuf,
+    /// 1-based line of the entry's name
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for EntrySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.file.display(), line),
+            None => write!(f, "{}", self.file.display()),
+        }
+    }
+}
+
+/// What an entry's hooks run for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookOperation {
+    Gather,
+    Scatter,
+    /// Restore puts content on the system like scatter, so it runs the scatter hooks
+    Restore,
+    Undo,
+}
+
+impl HookOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookOperation::Gather => "gather",
+            HookOperation::Scatter => "scatter",
+            HookOperation::Restore => "restore",
+            HookOperation::Undo => "undo",
+        }
+    }
 }
 
 /// Arguments for creating a new Entry
@@ -31,8 +107,26 @@ impl Entry {
             path: entry.path,
             run_before: None,
             run_after: None,
+            before_scatter: None,
+            after_scatter: None,
+            before_gather: None,
+            after_gather: None,
+            before_undo: None,
+            after_undo: None,
             depends_on: Vec::new(),
+            optional_depends_on: Vec::new(),
+            after: Vec::new(),
+            conflicts_with: Vec::new(),
             alias_as: None,
+            template: false,
+            assemble: None,
+            managed_block: false,
+            merge: None,
+            encrypt: false,
+            filters: Vec::new(),
+            when: None,
+            tags: Vec::new(),
+            source: None,
         }
     }
 
@@ -48,6 +142,42 @@ impl Entry {
         self
     }
 
+    /// Set the command to run before scattering this entry
+    pub fn with_before_scatter(mut self, command: String) -> Self {
+        self.before_scatter = Some(command);
+        self
+    }
+
+    /// Set the command to run after scattering this entry
+    pub fn with_after_scatter(mut self, command: String) -> Self {
+        self.after_scatter = Some(command);
+        self
+    }
+
+    /// Set the command to run before gathering this entry
+    pub fn with_before_gather(mut self, command: String) -> Self {
+        self.before_gather = Some(command);
+        self
+    }
+
+    /// Set the command to run after gathering this entry
+    pub fn with_after_gather(mut self, command: String) -> Self {
+        self.after_gather = Some(command);
+        self
+    }
+
+    /// Set the command to run before undo reverts this entry
+    pub fn with_before_undo(mut self, command: String) -> Self {
+        self.before_undo = Some(command);
+        self
+    }
+
+    /// Set the command to run after undo reverted this entry
+    pub fn with_after_undo(mut self, command: String) -> Self {
+        self.after_undo = Some(command);
+        self
+    }
+
     /// Add a dependency for this entry
     pub fn add_dependency(mut self, dependency: String) -> Self {
         self.depends_on.push(dependency);
@@ -60,12 +190,117 @@ impl Entry {
         self
     }
 
+    /// Set the entries this entry optionally depends on
+    pub fn with_optional_dependencies(mut self, dependencies: Vec<String>) -> Self {
+        self.optional_depends_on = dependencies;
+        self
+    }
+
+    /// Set the entries this entry is ordered after
+    pub fn with_after(mut self, after: Vec<String>) -> Self {
+        self.after = after;
+        self
+    }
+
+    /// Set the entries this entry conflicts with
+    pub fn with_conflicts(mut self, conflicts: Vec<String>) -> Self {
+        self.conflicts_with = conflicts;
+        self
+    }
+
     /// Set an alias for this entry
     pub fn with_alias(mut self, alias: String) -> Self {
         self.alias_as = Some(alias);
         self
     }
 
+    /// Mark this entry as a template
+    pub fn with_template(mut self, template: bool) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// Assemble this entry from fragments
+    pub fn with_assembly(mut self, assembly: Assembly) -> Self {
+        self.assemble = Some(assembly);
+        self
+    }
+
+    /// Only manage a marked block inside the target file
+    pub fn with_managed_block(mut self, managed_block: bool) -> Self {
+        self.managed_block = managed_block;
+        self
+    }
+
+    /// Merge the stored document into the target instead of replacing it
+    pub fn with_merge(mut self, merge: Merge) -> Self {
+        self.merge = Some(merge);
+        self
+    }
+
+    /// Keep the stored copy of this entry encrypted
+    pub fn with_encrypt(mut self, encrypt: bool) -> Self {
+        self.encrypt = encrypt;
+        self
+    }
+
+    /// Set the filters the content passes through
+    pub fn with_filters(mut self, filters: Vec<String>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Set the condition deciding whether this entry applies
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.when = Some(condition);
+        self
+    }
+
+    /// Set the tags of this entry
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Record where this entry is defined
+    pub fn with_source(mut self, source: EntrySource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Commands to run before processing this entry for `operation`, in order, with the option
+    /// each comes from. `run_before` applies to gather, scatter and restore, not to undo.
+    pub fn before_hooks(&self, operation: HookOperation) -> Vec<(&'static str, &str)> {
+        let (general, specific) = match operation {
+            HookOperation::Gather => (&self.run_before, ("before_gather", &self.before_gather)),
+            HookOperation::Scatter | HookOperation::Restore => (&self.run_before, ("before_scatter", &self.before_scatter)),
+            HookOperation::Undo => (&None, ("before_undo", &self.before_undo)),
+        };
+        [("run_before", general), specific]
+            .into_iter()
+            .filter_map(|(option, command)| command.as_deref().map(|command| (option, command)))
+            .collect()
+    }
+
+    /// Commands to run after processing this entry for `operation`, in order, with the option
+    /// each comes from. `run_after` applies to gather, scatter and restore, not to undo.
+    pub fn after_hooks(&self, operation: HookOperation) -> Vec<(&'static str, &str)> {
+        let (specific, general) = match operation {
+            HookOperation::Gather => (("after_gather", &self.after_gather), &self.run_after),
+            HookOperation::Scatter | HookOperation::Restore => (("after_scatter", &self.after_scatter), &self.run_after),
+            HookOperation::Undo => (("after_undo", &self.after_undo), &None),
+        };
+        [specific, ("run_after", general)]
+            .into_iter()
+            .filter_map(|(option, command)| command.as_deref().map(|command| (option, command)))
+            .collect()
+    }
+
+    /// Whether the entry applies to the machine described by `facts`
+    pub fn is_enabled(&self, facts: &HostFacts) -> bool {
+        self.when.as_ref().map(|condition| condition.evaluate(facts)).unwrap_or(true)
+    }
+
     /// Get the display name (alias if available, otherwise the entry name)
     pub fn get_display_name(&self) -> &str {
         self.alias_as.as_ref().unwrap_or(&self.name)
@@ -19,6 +19,20 @@ pub enum PollenError {
     CircularDependency(String),
     /// Missing dependency
     MissingDependency(String),
+    /// Entries that conflict with each other were selected together
+    Conflict(String),
+    /// Another pollen process holds the lock
+    Locked(String),
+    /// Template rendering failed
+    Template(String),
+    /// Encrypting or decrypting a stored copy failed
+    Encryption(String),
+    /// Resolving a secret placeholder failed
+    Secret(String),
+    /// Secret scanning found something that looks like a secret
+    SecretsFound(String),
+    /// A clean or smudge filter failed
+    Filter(String),
 }
 
 impl fmt::Display for PollenError {
@@ -32,6 +46,13 @@ impl fmt::Display for PollenError {
             PollenError::InvalidMapping(msg) => write!(f, "Invalid mapping structure: {}", msg),
             PollenError::CircularDependency(msg) => write!(f, "Circular dependency detected: {}", msg),
             PollenError::MissingDependency(msg) => write!(f, "Missing dependency: {}", msg),
+            PollenError::Conflict(msg) => write!(f, "Conflicting entries: {}", msg),
+            PollenError::Locked(msg) => write!(f, "Pollen is already running: {}", msg),
+            PollenError::Template(msg) => write!(f, "Template error: {}", msg),
+            PollenError::Encryption(msg) => write!(f, "Encryption error: {}", msg),
+            PollenError::Secret(msg) => write!(f, "Secret error: {}", msg),
+            PollenError::SecretsFound(msg) => write!(f, "Possible secrets found:{}", msg),
+            PollenError::Filter(msg) => write!(f, "Filter error: {}", msg),
         }
     }
 }
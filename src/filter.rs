@@ -0,0 +1,170 @@
+//! Filters transform content between the system and the files directory, like git's clean and
+//! smudge filters: `clean` runs on what gather takes from the system, `smudge` on what scatter
+//! deploys. Both read the content on stdin and write the result to stdout.
+
+use crate::{dirs::PollenConfig, error::PollenError};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+/// A filter as written under `filters` in pollen.yaml
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterDefinition {
+    /// Command turning the content on the system into the stored content, run on gather
+    #[serde(default)]
+    pub clean: Option<String>,
+    /// Command turning the stored content into the content on the system, run on scatter
+    #[serde(default)]
+    pub smudge: Option<String>,
+}
+
+/// Which way content passes through an entry's filters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterDirection {
+    /// From the system into the files directory
+    Clean,
+    /// From the files directory onto the system
+    Smudge,
+}
+
+/// A filter command ready to run, named after its filter for error messages
+pub struct FilterCommand {
+    pub name: String,
+    pub command: String,
+}
+
+/// The commands of the filters named by an entry for `direction`, in the order they run.
+///
+/// Smudge commands run in the order the filters are listed and clean commands in reverse, so
+/// cleaning undoes smudging step by step.
+pub fn filter_commands(names: &[String], direction: FilterDirection, config: &PollenConfig) -> Result<Vec<FilterCommand>, PollenError> {
+    let mut commands = Vec::new();
+    for name in names {
+        let definition = config
+            .filters
+            .as_ref()
+            .and_then(|filters| filters.get(name))
+            .ok_or_else(|| PollenError::Filter(format!(
+                "Unknown filter '{}' (define it under filters in pollen.yaml)", name
+            )))?;
+        let command = match direction {
+            FilterDirection::Clean => &definition.clean,
+            FilterDirection::Smudge => &definition.smudge,
+        };
+        if let Some(command) = command {
+            commands.push(FilterCommand { name: name.clone(), command: command.clone() });
+        }
+    }
+
+    if direction == FilterDirection::Clean {
+        commands.reverse();
+    }
+    Ok(commands)
+}
+
+/// Pass a file, or every file in a directory tree, through `commands` into `destination`
+pub fn filter_path(source: &Path, destination: &Path, commands: &[FilterCommand]) -> Result<(), PollenError> {
+    if source.is_dir() {
+        fs::create_dir_all(destination).map_err(PollenError::Io)?;
+        for entry in fs::read_dir(source).map_err(PollenError::Io)? {
+            let entry = entry.map_err(PollenError::Io)?;
+            filter_path(&entry.path(), &destination.join(entry.file_name()), commands)?;
+        }
+        return Ok(());
+    }
+
+    let mut content = fs::read(source).map_err(PollenError::Io)?;
+    for command in commands {
+        content = run_filter(command, &content)
+            .map_err(|e| PollenError::Filter(format!("{} ({}): {}", command.name, source.display(), e)))?;
+    }
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    fs::write(destination, content).map_err(PollenError::Io)?;
+
+    let permissions = fs::metadata(source).map_err(PollenError::Io)?.permissions();
+    fs::set_permissions(destination, permissions).map_err(PollenError::Io)?;
+
+    Ok(())
+}
+
+fn run_filter(command: &FilterCommand, input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command.command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run '{}': {}", command.command, e))?;
+
+    // The command may write before it has read everything, so stdin is fed from another thread
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_vec();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let written = writer.map(|writer| writer.join());
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("'{}' failed: {}", command.command, message.lines().next().unwrap_or("").trim()));
+    }
+    match written {
+        // A filter that does not need all of its input may close stdin early
+        Some(Ok(Err(e))) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e.to_string()),
+        Some(Err(_)) => Err(format!("writing to '{}' failed", command.command)),
+        _ => Ok(output.stdout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> PollenConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn command(command: &str) -> FilterCommand {
+        FilterCommand { name: "test".to_string(), command: command.to_string() }
+    }
+
+    #[test]
+    fn clean_runs_in_reverse_order() {
+        let config = config("filters: {a: {clean: clean-a, smudge: smudge-a}, b: {smudge: smudge-b}, c: {clean: clean-c}}");
+        let names = ["a".to_string(), "b".to_string(), "c".to_string()];
+
+        let smudge = filter_commands(&names, FilterDirection::Smudge, &config).unwrap();
+        let clean = filter_commands(&names, FilterDirection::Clean, &config).unwrap();
+        assert_eq!(smudge.iter().map(|c| c.command.as_str()).collect::<Vec<_>>(), ["smudge-a", "smudge-b"]);
+        assert_eq!(clean.iter().map(|c| c.command.as_str()).collect::<Vec<_>>(), ["clean-c", "clean-a"]);
+    }
+
+    #[test]
+    fn unknown_filter_is_an_error() {
+        let error = filter_commands(&["missing".to_string()], FilterDirection::Clean, &config("verbose: false"));
+        assert!(error.is_err_and(|e| e.to_string().contains("Unknown filter 'missing'")));
+    }
+
+    #[test]
+    fn content_passes_through_stdin_and_stdout() {
+        assert_eq!(run_filter(&command("tr a-z A-Z"), b"hello\n").unwrap(), b"HELLO\n");
+        // Filters that ignore their input must not fail on a closed pipe
+        let input = vec![b'x'; 1 << 20];
+        assert_eq!(run_filter(&command("echo done"), &input).unwrap(), b"done\n");
+    }
+
+    #[test]
+    fn failing_filter_reports_its_stderr() {
+        let error = run_filter(&command("echo broken >&2; exit 1"), b"").unwrap_err();
+        assert_eq!(error, "'echo broken >&2; exit 1' failed: broken");
+    }
+}
@@ -0,0 +1,100 @@
+/// Facts about the machine Pollen is running on
+#[derive(Debug, Clone)]
+pub struct HostFacts {
+    /// Short hostname, without any domain part
+    pub hostname: String,
+    /// Name of the current user
+    pub username: String,
+    /// Operating system family, e.g. "linux" or "macos"
+    pub os: String,
+    /// CPU architecture, e.g. "x86_64" or "aarch64"
+    pub arch: String,
+    /// Linux distribution ID from os-release, e.g. "ubuntu" or "arch"
+    pub distro: Option<String>,
+    /// Distributions this one derives from (os-release `ID_LIKE`)
+    pub distro_like: Vec<String>,
+}
+
+impl HostFacts {
+    /// Detect facts about the current machine
+    pub fn detect() -> Self {
+        let (distro, distro_like) = detect_distro();
+        HostFacts {
+            hostname: detect_hostname(),
+            username: detect_username(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            distro,
+            distro_like,
+        }
+    }
+}
+
+fn detect_hostname() -> String {
+    let hostname = system_hostname()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .unwrap_or_else(|| "localhost".to_string());
+
+    hostname
+        .split('.')
+        .next()
+        .unwrap_or(&hostname)
+        .trim()
+        .to_string()
+}
+
+#[cfg(unix)]
+fn system_hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for its full length and gethostname NUL-terminates on success
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return None;
+    }
+
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    let hostname = String::from_utf8_lossy(&buffer[..end]).to_string();
+    if hostname.is_empty() {
+        None
+    } else {
+        Some(hostname)
+    }
+}
+
+#[cfg(not(unix))]
+fn system_hostname() -> Option<String> {
+    None
+}
+
+fn detect_username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Read the distribution ID and ID_LIKE from os-release
+fn detect_distro() -> (Option<String>, Vec<String>) {
+    let content = ["/etc/os-release", "/usr/lib/os-release"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .unwrap_or_default();
+
+    let mut distro = None;
+    let mut distro_like = Vec::new();
+
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches(['"', '\'']);
+        match key.trim() {
+            "ID" if !value.is_empty() => distro = Some(value.to_lowercase()),
+            "ID_LIKE" => distro_like = value.split_whitespace().map(str::to_lowercase).collect(),
+            _ => {}
+        }
+    }
+
+    (distro, distro_like)
+}
@@ -3,10 +3,32 @@ pub mod dirs;
 pub mod config;
 pub mod entry;
 pub mod yaml_ext;
+pub mod lock;
+pub mod trash;
+pub mod host;
+pub mod condition;
+pub mod profile;
+pub mod alternate;
+pub mod overlay;
+pub mod assemble;
+pub mod block;
+pub mod merge;
+pub mod crypt;
+pub mod filter;
+pub mod secret;
+pub mod scan;
+pub mod tag;
+pub mod template;
 pub mod cli;
 
 pub use error::PollenError;
-pub use dirs::{PollenDirs, PollenConfig, Operation, OperationType, OperationEntry};
+pub use dirs::{PollenDirs, PollenConfig, LocalConfig, Operation, OperationType, OperationEntry};
 pub use config::ConfigParser;
-pub use entry::{Entry, EntryArgument};
+pub use entry::{Entry, EntryArgument, EntrySource, HookOperation};
+pub use lock::{PollenLock, LockHolder};
+pub use trash::{Trash, TrashItem};
+pub use host::HostFacts;
+pub use condition::Condition;
+pub use profile::{Profiles, ProfileDefinition};
+pub use tag::TagExpression;
 pub use cli::run;
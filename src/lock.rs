@@ -0,0 +1,165 @@
+use crate::{dirs::PollenDirs, error::PollenError};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{Seek, Write},
+    path::Path,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// How long to sleep between attempts when waiting for another process
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Information about the process holding the lock, stored in the lock file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockHolder {
+    /// Process ID of the holder
+    pub pid: u32,
+    /// Command line of the holder
+    pub command: String,
+    /// Unix timestamp when the lock was taken
+    pub started: u64,
+}
+
+/// Advisory lock preventing concurrent pollen runs from mutating the same state.
+///
+/// The lock is an exclusive OS lock on the lock file, so the kernel releases it when the holder
+/// exits, however it exits. The lock file itself is never removed: unlinking a file another
+/// process may be about to lock would let two processes hold "the" lock at once.
+///
+/// The lock is released when this value is dropped.
+#[derive(Debug)]
+pub struct PollenLock {
+    file: File,
+}
+
+impl PollenLock {
+    /// Take the lock, optionally waiting for another process to release it
+    pub fn acquire(dirs: &PollenDirs, wait: bool) -> Result<Self, PollenError> {
+        let path = &dirs.lock_file;
+        let mut file = open_lock_file(path)?;
+        let holder = LockHolder {
+            pid: std::process::id(),
+            command: std::env::args().collect::<Vec<_>>().join(" "),
+            started: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        let mut announced_wait = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => {
+                    let content = serde_json::to_string(&holder)
+                        .map_err(|e| PollenError::InvalidMapping(format!("JSON serialization error: {}", e)))?;
+                    file.set_len(0).map_err(PollenError::Io)?;
+                    file.rewind().map_err(PollenError::Io)?;
+                    file.write_all(content.as_bytes()).map_err(PollenError::Io)?;
+                    return Ok(PollenLock { file });
+                }
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => return Err(PollenError::Io(e)),
+            }
+
+            let description = match read_holder(path) {
+                Some(current) => format!("PID {} ({})", current.pid, current.command),
+                None => "another pollen process".to_string(),
+            };
+
+            if !wait {
+                return Err(PollenError::Locked(format!(
+                    "{} holds {}. Re-run with --wait to wait for it to finish",
+                    description,
+                    path.display()
+                )));
+            }
+
+            if !announced_wait {
+                println!("Waiting for lock held by {}...", description);
+                announced_wait = true;
+            }
+            thread::sleep(WAIT_INTERVAL);
+        }
+    }
+
+    /// Read the current holder of the lock, if the lock is held
+    pub fn holder(dirs: &PollenDirs) -> Option<LockHolder> {
+        let file = File::open(&dirs.lock_file).ok()?;
+
+        // A holder that exited leaves its details behind, but not the lock
+        match file.try_lock() {
+            Ok(()) => None,
+            Err(TryLockError::WouldBlock) => read_holder(&dirs.lock_file),
+            Err(TryLockError::Error(_)) => None,
+        }
+    }
+}
+
+impl Drop for PollenLock {
+    fn drop(&mut self) {
+        // Clear the holder details before closing the file releases the lock
+        let _ = self.file.set_len(0);
+    }
+}
+
+fn open_lock_file(path: &Path) -> Result<File, PollenError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(PollenError::Io)
+}
+
+fn read_holder(path: &Path) -> Option<LockHolder> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirs(name: &str) -> PollenDirs {
+        let root = std::env::temp_dir().join(format!("pollen-lock-{}-{}", name, std::process::id()));
+        PollenDirs::from_base_dirs(root.join("config"), root.join("state"), root.join("data"), root.join("cache")).unwrap()
+    }
+
+    #[test]
+    fn second_holder_is_refused_until_the_lock_is_dropped() {
+        let dirs = dirs("refused");
+        let lock = PollenLock::acquire(&dirs, false).unwrap();
+        assert_eq!(PollenLock::holder(&dirs).map(|holder| holder.pid), Some(std::process::id()));
+
+        let error = PollenLock::acquire(&dirs, false).unwrap_err();
+        assert!(matches!(error, PollenError::Locked(_)));
+        assert!(error.to_string().contains(&format!("PID {}", std::process::id())));
+
+        drop(lock);
+        assert!(PollenLock::holder(&dirs).is_none());
+        drop(PollenLock::acquire(&dirs, false).unwrap());
+        let _ = fs::remove_dir_all(dirs.config_dir.parent().unwrap());
+    }
+
+    #[test]
+    fn waiting_takes_the_lock_once_it_is_released() {
+        let dirs = dirs("wait");
+        let lock = PollenLock::acquire(&dirs, false).unwrap();
+        let release = thread::spawn(move || {
+            thread::sleep(WAIT_INTERVAL * 2);
+            drop(lock);
+        });
+
+        let waited = PollenLock::acquire(&dirs, true);
+        release.join().unwrap();
+        assert!(waited.is_ok());
+        drop(waited);
+        let _ = fs::remove_dir_all(dirs.config_dir.parent().unwrap());
+    }
+}
@@ -0,0 +1,545 @@
+use crate::{error::PollenError, yaml_ext::CanForceIntoString};
+use serde_json::{Map, Value};
+use std::{collections::HashMap, fmt};
+
+/// Document format of an entry merged key by key instead of copied, from `merge:`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeFormat {
+    Json,
+    Yaml,
+    Toml,
+    Ini,
+}
+
+/// How an entry's stored document is merged into the target, from `merge:` and `ignore_keys:`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merge {
+    pub format: MergeFormat,
+    /// Dotted key paths pollen never writes or gathers, including everything below them
+    pub ignore_keys: Vec<String>,
+}
+
+impl MergeFormat {
+    /// Parse the value of a `merge:` option
+    pub fn parse(value: &serde_yaml::Value) -> Result<MergeFormat, PollenError> {
+        match value.force_into_string()?.to_lowercase().as_str() {
+            "json" => Ok(MergeFormat::Json),
+            "yaml" | "yml" => Ok(MergeFormat::Yaml),
+            "toml" => Ok(MergeFormat::Toml),
+            "ini" => Ok(MergeFormat::Ini),
+            other => Err(PollenError::InvalidOption(format!(
+                "Unknown merge format '{}' (expected json, yaml, toml or ini)", other
+            ))),
+        }
+    }
+}
+
+/// Parse the value of an `ignore_keys:` option, a dotted key path or a list of them
+pub fn parse_ignore_keys(value: &serde_yaml::Value) -> Result<Vec<String>, PollenError> {
+    let keys = match value {
+        serde_yaml::Value::Sequence(sequence) => sequence
+            .iter()
+            .map(|key| key.force_into_string())
+            .collect::<Result<Vec<_>, _>>()?,
+        other => vec![other.force_into_string()?],
+    };
+
+    if let Some(key) = keys.iter().find(|key| key.is_empty() || key.starts_with('.') || key.ends_with('.')) {
+        return Err(PollenError::InvalidOption(format!("Invalid key path '{}' in ignore_keys", key)));
+    }
+    Ok(keys)
+}
+
+impl fmt::Display for MergeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeFormat::Json => write!(f, "json"),
+            MergeFormat::Yaml => write!(f, "yaml"),
+            MergeFormat::Toml => write!(f, "toml"),
+            MergeFormat::Ini => write!(f, "ini"),
+        }
+    }
+}
+
+impl Merge {
+    /// The target document with the stored document merged in: stored keys win, other keys stay.
+    ///
+    /// None when the target already holds every stored key. INI targets are edited in place, so
+    /// their comments, ordering and duplicate keys survive; JSON targets with comments, which
+    /// rewriting would drop, are refused instead.
+    pub fn merge(&self, target: Option<&str>, stored: &str) -> Result<Option<String>, String> {
+        let mut document = match target {
+            Some(target) => self.parse_document(target).map_err(|e| format!("on the system: {}", e))?,
+            None => Value::Object(Map::new()),
+        };
+        let mut stored_document = self.parse_document(stored).map_err(|e| format!("in the stored copy: {}", e))?;
+        self.strip_ignored(&mut stored_document, "");
+
+        let original = document.clone();
+        merge_values(&mut document, stored_document);
+        let Some(target) = target else {
+            return self.write_document(&document, stored).map(Some);
+        };
+        if document == original {
+            return Ok(None);
+        }
+
+        match self.format {
+            MergeFormat::Ini => Ok(Some(update_ini(target, &document))),
+            MergeFormat::Json if strip_comments(target) != target => Err(
+                "on the system: the file has comments, which merging would remove. Move them out of the file or drop merge".to_string()
+            ),
+            _ => self.write_document(&document, target).map(Some),
+        }
+    }
+
+    /// The keys pollen manages taken from the system document, laid out like the stored document.
+    ///
+    /// Those are the keys of the stored document, or every key when nothing is stored yet, and
+    /// never the ignored ones. Keys removed on the system are dropped.
+    pub fn capture(&self, system: &str, stored: Option<&str>) -> Result<String, String> {
+        let mut document = self.parse_document(system).map_err(|e| format!("on the system: {}", e))?;
+        if let Some(stored) = stored {
+            let shape = self.parse_document(stored).map_err(|e| format!("in the stored copy: {}", e))?;
+            document = project(&document, &shape).unwrap_or(Value::Object(Map::new()));
+        }
+        self.strip_ignored(&mut document, "");
+        self.write_document(&document, stored.unwrap_or(system))
+    }
+
+    /// The stored document as `capture` would lay it out, for comparing the two
+    pub fn normalize(&self, stored: &str) -> Result<String, String> {
+        let mut document = self.parse_document(stored).map_err(|e| format!("in the stored copy: {}", e))?;
+        self.strip_ignored(&mut document, "");
+        self.write_document(&document, stored)
+    }
+
+    fn parse_document(&self, text: &str) -> Result<Value, String> {
+        // An empty file is an empty document in every format
+        if text.trim().is_empty() {
+            return Ok(Value::Object(Map::new()));
+        }
+
+        let document = match self.format {
+            MergeFormat::Json => serde_json::from_str(&strip_json_comments(text)).map_err(|e| e.to_string())?,
+            MergeFormat::Yaml => {
+                let value: serde_yaml::Value = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+                serde_json::to_value(value).map_err(|e| e.to_string())?
+            }
+            MergeFormat::Toml => {
+                let value: toml::Value = toml::from_str(text).map_err(|e| e.to_string())?;
+                serde_json::to_value(value).map_err(|e| e.to_string())?
+            }
+            MergeFormat::Ini => parse_ini(text),
+        };
+
+        if !document.is_object() {
+            return Err(format!("the top level of a {} document to merge must be a mapping", self.format));
+        }
+        Ok(document)
+    }
+
+    fn write_document(&self, document: &Value, like: &str) -> Result<String, String> {
+        let mut text = match self.format {
+            MergeFormat::Json => {
+                let indent = json_indent(like);
+                let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+                let mut output = Vec::new();
+                let mut serializer = serde_json::Serializer::with_formatter(&mut output, formatter);
+                serde::Serialize::serialize(document, &mut serializer).map_err(|e| e.to_string())?;
+                String::from_utf8(output).map_err(|e| e.to_string())?
+            }
+            MergeFormat::Yaml => serde_yaml::to_string(document).map_err(|e| e.to_string())?,
+            MergeFormat::Toml => {
+                // Going through toml::Value turns datetimes back into datetimes
+                let value: toml::Value = serde_json::from_value(document.clone()).map_err(|e| e.to_string())?;
+                toml::to_string(&value).map_err(|e| e.to_string())?
+            }
+            MergeFormat::Ini => write_ini(document),
+        };
+
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    fn strip_ignored(&self, value: &mut Value, path: &str) {
+        let Value::Object(mapping) = value else {
+            return;
+        };
+
+        mapping.retain(|key, _| !self.is_ignored(&join_path(path, key)));
+        for (key, value) in mapping.iter_mut() {
+            let path = join_path(path, key);
+            if self.ignore_keys.iter().any(|ignored| ignored.starts_with(&format!("{}.", path))) {
+                self.strip_ignored(value, &path);
+            }
+        }
+    }
+
+    fn is_ignored(&self, path: &str) -> bool {
+        self.ignore_keys.iter().any(|ignored| ignored == path)
+    }
+}
+
+/// Keys often contain dots themselves ("editor.fontSize"), so paths are matched as joined strings
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Merge `stored` into `target`, recursing into mappings both have and replacing everything else
+fn merge_values(target: &mut Value, stored: Value) {
+    match (target, stored) {
+        (Value::Object(target), Value::Object(stored)) => {
+            for (key, value) in stored {
+                match target.get_mut(&key) {
+                    Some(existing) if existing.is_object() && value.is_object() => merge_values(existing, value),
+                    _ => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, stored) => *target = stored,
+    }
+}
+
+/// The parts of `system` at the keys of `shape`, in the order of `shape`
+fn project(system: &Value, shape: &Value) -> Option<Value> {
+    match (system, shape) {
+        (Value::Object(system), Value::Object(shape)) => {
+            let mut projected = Map::new();
+            for (key, shape) in shape {
+                if let Some(value) = system.get(key) {
+                    // A mapping that became a value on the system is taken as it is now
+                    let value = project(value, shape).unwrap_or_else(|| value.clone());
+                    projected.insert(key.clone(), value);
+                }
+            }
+            Some(Value::Object(projected))
+        }
+        _ => None,
+    }
+}
+
+/// The indentation of the first indented line, so rewritten files keep their style
+fn json_indent(text: &str) -> String {
+    text.lines()
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .find(|indent| !indent.is_empty())
+        .unwrap_or("  ")
+        .to_string()
+}
+
+/// Remove `//` and `/* */` comments and trailing commas, which settings files often contain
+fn strip_json_comments(text: &str) -> String {
+    let output = strip_comments(text);
+
+    // A comma followed only by whitespace and a closing bracket is dropped
+    let mut cleaned = String::with_capacity(output.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in output.char_indices() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' && output[index + 1..].trim_start().starts_with(['}', ']']) {
+            continue;
+        }
+        cleaned.push(c);
+    }
+    cleaned
+}
+
+/// Remove `//` and `/* */` comments outside of strings
+fn strip_comments(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            output.push(c);
+            match c {
+                '\\' => output.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                output.push(c);
+            }
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|&next| next != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+/// INI files become a mapping of top-level keys and one mapping per section, all values strings
+fn parse_ini(text: &str) -> Value {
+    let mut document = Map::new();
+    let mut section: Option<String> = None;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            let name = name.trim().to_string();
+            document.entry(name.clone()).or_insert_with(|| Value::Object(Map::new()));
+            section = Some(name);
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').unwrap_or((line, ""));
+        let (key, value) = (key.trim().to_string(), Value::String(value.trim().to_string()));
+        let mapping = match &section {
+            Some(name) => document.get_mut(name).and_then(Value::as_object_mut),
+            None => Some(&mut document),
+        };
+        if let Some(mapping) = mapping {
+            mapping.insert(key, value);
+        }
+    }
+
+    Value::Object(document)
+}
+
+fn write_ini(document: &Value) -> String {
+    let Value::Object(document) = document else {
+        return String::new();
+    };
+
+    let mut output = String::new();
+    for (key, value) in document.iter().filter(|(_, value)| !value.is_object()) {
+        output.push_str(&format!("{} = {}\n", key, ini_scalar(value)));
+    }
+    for (name, section) in document.iter().filter_map(|(name, value)| value.as_object().map(|section| (name, section))) {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&format!("[{}]\n", name));
+        for (key, value) in section {
+            output.push_str(&format!("{} = {}\n", key, ini_scalar(value)));
+        }
+    }
+    output
+}
+
+/// Rewrite the values of `document` into an INI file, leaving every other line as it is.
+///
+/// A key set more than once only has its last line changed, since that is the one that counts.
+/// Keys the file lacks go at the end of their section, and sections it lacks at the end of the file.
+fn update_ini(text: &str, document: &Value) -> String {
+    let Value::Object(document) = document else {
+        return text.to_string();
+    };
+
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut last_key_line: HashMap<String, usize> = HashMap::new();
+    let mut section_end: Vec<(Option<String>, usize)> = Vec::new();
+    let mut section: Option<String> = None;
+
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = Some(name.trim().to_string());
+        } else {
+            let key = trimmed.split_once('=').map_or(trimmed, |(key, _)| key).trim();
+            last_key_line.insert(ini_key(section.as_deref(), key), index);
+        }
+        match section_end.iter_mut().find(|(name, _)| *name == section) {
+            Some((_, end)) => *end = index,
+            None => section_end.push((section.clone(), index)),
+        }
+    }
+
+    let section_values = |name: Option<&str>| -> Vec<(String, String)> {
+        let mapping = match name {
+            Some(name) => document.get(name).and_then(Value::as_object),
+            None => Some(document),
+        };
+        mapping
+            .into_iter()
+            .flatten()
+            .filter(|(_, value)| !value.is_object())
+            .map(|(key, value)| (key.clone(), ini_scalar(value)))
+            .collect()
+    };
+
+    // Changed values are replaced after the `=`, keeping the key and spacing as written
+    let mut insertions: Vec<(usize, String)> = Vec::new();
+    let mut appended = String::new();
+    let mut names: Vec<Option<String>> = vec![None];
+    names.extend(document.iter().filter(|(_, value)| value.is_object()).map(|(name, _)| Some(name.clone())));
+    for name in names {
+        let mut missing = Vec::new();
+        for (key, value) in section_values(name.as_deref()) {
+            match last_key_line.get(&ini_key(name.as_deref(), &key)) {
+                Some(&index) => {
+                    let line = &mut lines[index];
+                    if let Some(equals) = line.find('=') {
+                        let spacing = line[equals + 1..].len() - line[equals + 1..].trim_start().len();
+                        let prefix = line[..equals + 1 + spacing].to_string();
+                        if line[prefix.len()..].trim() != value {
+                            *line = format!("{}{}", prefix, value);
+                        }
+                    } else if !value.is_empty() {
+                        *line = format!("{} = {}", line.trim_end(), value);
+                    }
+                }
+                None => missing.push(format!("{} = {}", key, value)),
+            }
+        }
+
+        match section_end.iter().find(|(section, _)| *section == name) {
+            Some((_, end)) => insertions.extend(missing.into_iter().map(|line| (*end + 1, line))),
+            // Top-level keys the file lacks go before its first section
+            None if name.is_none() => insertions.extend(missing.into_iter().map(|line| (0, line))),
+            None => {
+                if !text.is_empty() || !appended.is_empty() {
+                    appended.push('\n');
+                }
+                appended.push_str(&format!("[{}]\n", name.unwrap_or_default()));
+                for line in missing {
+                    appended.push_str(&line);
+                    appended.push('\n');
+                }
+            }
+        }
+    }
+
+    // Inserting from the bottom up keeps the earlier line numbers valid
+    insertions.sort_by_key(|(index, _)| *index);
+    for (index, line) in insertions.into_iter().rev() {
+        lines.insert(index, line);
+    }
+
+    let mut output = lines.join("\n");
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(&appended);
+    output
+}
+
+/// Where a key lives in an INI file, for looking up its line
+fn ini_key(section: Option<&str>, key: &str) -> String {
+    match section {
+        Some(section) => format!("[{}]{}", section, key),
+        None => key.to_string(),
+    }
+}
+
+fn ini_scalar(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(format: MergeFormat, ignore_keys: &[&str]) -> Merge {
+        Merge { format, ignore_keys: ignore_keys.iter().map(|key| key.to_string()).collect() }
+    }
+
+    #[test]
+    fn stored_keys_win_and_other_keys_stay() {
+        let json = merge(MergeFormat::Json, &[]);
+        let merged = json.merge(Some("{\n  \"a\": 1,\n  \"b\": {\"x\": 1}\n}"), "{\"b\": {\"y\": 2}, \"c\": 3}").unwrap().unwrap();
+        let merged: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(merged, serde_json::json!({"a": 1, "b": {"x": 1, "y": 2}, "c": 3}));
+    }
+
+    #[test]
+    fn unchanged_target_is_not_rewritten() {
+        let json = merge(MergeFormat::Json, &[]);
+        assert_eq!(json.merge(Some("{\"a\": 1, \"b\": 2}"), "{\"a\": 1}").unwrap(), None);
+        let ini = merge(MergeFormat::Ini, &[]);
+        assert_eq!(ini.merge(Some("; note\n[core]\nx=1\n"), "[core]\nx = 1\n").unwrap(), None);
+    }
+
+    #[test]
+    fn ignored_keys_are_neither_written_nor_captured() {
+        let json = merge(MergeFormat::Json, &["editor.fontSize", "window"]);
+        let stored = "{\"editor.fontSize\": 14, \"window\": {\"zoom\": 1}, \"theme\": \"dark\"}";
+        let merged: Value = serde_json::from_str(&json.merge(None, stored).unwrap().unwrap()).unwrap();
+        assert_eq!(merged, serde_json::json!({"theme": "dark"}));
+
+        let captured: Value = serde_json::from_str(&json.capture(stored, None).unwrap()).unwrap();
+        assert_eq!(captured, serde_json::json!({"theme": "dark"}));
+    }
+
+    #[test]
+    fn capture_keeps_only_stored_keys() {
+        let json = merge(MergeFormat::Json, &[]);
+        let captured = json.capture("{\"a\": 2, \"local\": true}", Some("{\"a\": 1, \"gone\": 1}")).unwrap();
+        let captured: Value = serde_json::from_str(&captured).unwrap();
+        assert_eq!(captured, serde_json::json!({"a": 2}));
+    }
+
+    #[test]
+    fn json_comments_are_read_but_not_rewritten() {
+        let json = merge(MergeFormat::Json, &[]);
+        let target = "{\n  // font\n  \"a\": 1, /* size */\n  \"b\": \"http://x\",\n}";
+        assert_eq!(json.merge(Some(target), "{\"a\": 1}").unwrap(), None);
+        assert!(json.merge(Some(target), "{\"a\": 2}").unwrap_err().contains("comments"));
+    }
+
+    #[test]
+    fn ini_is_edited_in_place() {
+        let ini = merge(MergeFormat::Ini, &[]);
+        let target = "; top comment\nname=old\n\n[core]\n# keep me\nkey = 1\nkey = 2\nother = x\n";
+        let stored = "name = new\n[core]\nkey = 3\nadded = y\n[extra]\nz = 1\n";
+        assert_eq!(
+            ini.merge(Some(target), stored).unwrap().unwrap(),
+            "; top comment\nname=new\n\n[core]\n# keep me\nkey = 1\nkey = 3\nother = x\nadded = y\n\n[extra]\nz = 1\n"
+        );
+    }
+
+    #[test]
+    fn top_level_ini_keys_go_before_the_first_section() {
+        let ini = merge(MergeFormat::Ini, &[]);
+        assert_eq!(ini.merge(Some("[core]\nx = 1\n"), "root = 1\n").unwrap().unwrap(), "root = 1\n[core]\nx = 1\n");
+    }
+
+    #[test]
+    fn top_level_must_be_a_mapping() {
+        let yaml = merge(MergeFormat::Yaml, &[]);
+        assert!(yaml.merge(None, "- a\n- b\n").unwrap_err().contains("must be a mapping"));
+    }
+}
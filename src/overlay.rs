@@ -0,0 +1,198 @@
+use crate::{alternate::stored_name, dirs::PollenDirs, entry::Entry, error::PollenError, host::HostFacts};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Directory inside the files directory holding one overlay tree per host or profile
+pub const OVERLAYS_DIR: &str = "overlays";
+
+/// An overlay tree for an entry, layered over its stored copy on scatter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    /// Overlay name: the hostname or profile it applies to
+    pub name: String,
+    /// The entry inside the overlay, `overlays/<name>/<stored name>`
+    pub path: PathBuf,
+}
+
+/// Overlays that apply on this machine, least specific first: the profile, then the host.
+///
+/// Overlay directories are matched to the hostname case-insensitively, like host alternates.
+pub fn overlay_names(dirs: &PollenDirs, facts: &HostFacts, profile: Option<&str>) -> Vec<String> {
+    let available: Vec<String> = fs::read_dir(dirs.files_dir.join(OVERLAYS_DIR))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut names: Vec<String> = Vec::new();
+    if let Some(profile) = profile.filter(|profile| available.iter().any(|name| name == profile)) {
+        names.push(profile.to_string());
+    }
+    if let Some(host) = available.iter().find(|name| name.eq_ignore_ascii_case(&facts.hostname)) {
+        if !names.contains(host) {
+            names.push(host.clone());
+        }
+    }
+    names
+}
+
+/// Layers of an entry present in the files directory, least specific first
+pub fn entry_layers(dirs: &PollenDirs, entry: &Entry, overlays: &[String]) -> Vec<Layer> {
+    overlays
+        .iter()
+        .map(|name| Layer {
+            name: name.clone(),
+            path: dirs.files_dir.join(OVERLAYS_DIR).join(name).join(stored_name(entry)),
+        })
+        .filter(|layer| layer.path.exists())
+        .collect()
+}
+
+/// Copy `base` to `destination`, then copy every layer over it in order
+pub fn apply_layers(base: &Path, layers: &[Layer], destination: &Path) -> Result<(), PollenError> {
+    remove_path(destination)?;
+    copy_tree(base, destination)?;
+    for layer in layers {
+        // A file layer, or a layer replacing a file entry with a directory, replaces the whole entry
+        if layer.path.is_file() || !destination.is_dir() {
+            remove_path(destination)?;
+        }
+        copy_tree(&layer.path, destination)?;
+    }
+    Ok(())
+}
+
+/// For every file an entry deploys, the layer it comes from, or None for the base stored copy.
+///
+/// Keys are paths relative to the entry; a file entry has the single key "".
+pub fn file_layers(base: &Path, layers: &[Layer]) -> BTreeMap<PathBuf, Option<String>> {
+    let mut files: BTreeMap<PathBuf, Option<String>> =
+        relative_files(base).into_iter().map(|path| (path, None)).collect();
+
+    for layer in layers {
+        if layer.path.is_file() {
+            files.clear();
+        }
+        for path in relative_files(&layer.path) {
+            files.insert(path, Some(layer.name.clone()));
+        }
+    }
+    files
+}
+
+/// Files below `root` relative to it, sorted; a file yields the single path ""
+pub fn relative_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if root.is_file() {
+        files.push(PathBuf::new());
+    } else {
+        collect_files(root, Path::new(""), &mut files);
+    }
+    files.sort();
+    files
+}
+
+fn collect_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(root.join(relative)) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = relative.join(entry.file_name());
+        if entry.path().is_dir() {
+            collect_files(root, &path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn copy_tree(source: &Path, destination: &Path) -> Result<(), PollenError> {
+    if source.is_dir() {
+        fs::create_dir_all(destination)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+    } else if source.exists() {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(source, destination)?;
+    }
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> Result<(), PollenError> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.symlink_metadata().is_ok() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn layer(root: &Path, name: &str) -> Layer {
+        Layer { name: name.to_string(), path: root.join(name) }
+    }
+
+    #[test]
+    fn layers_replace_files_and_keep_the_rest() {
+        let root = std::env::temp_dir().join(format!("pollen-overlay-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write(&root.join("base/config"), "base");
+        write(&root.join("base/themes/dark"), "dark");
+        write(&root.join("work/config"), "work");
+        write(&root.join("laptop/themes/light"), "light");
+        let layers = [layer(&root, "work"), layer(&root, "laptop")];
+
+        let sources = file_layers(&root.join("base"), &layers);
+        assert_eq!(sources.get(Path::new("config")), Some(&Some("work".to_string())));
+        assert_eq!(sources.get(Path::new("themes/dark")), Some(&None));
+        assert_eq!(sources.get(Path::new("themes/light")), Some(&Some("laptop".to_string())));
+
+        let destination = root.join("deployed");
+        apply_layers(&root.join("base"), &layers, &destination).unwrap();
+        assert_eq!(
+            relative_files(&destination),
+            [PathBuf::from("config"), PathBuf::from("themes/dark"), PathBuf::from("themes/light")]
+        );
+        assert_eq!(fs::read_to_string(destination.join("config")).unwrap(), "work");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn file_layer_replaces_the_whole_entry() {
+        let root = std::env::temp_dir().join(format!("pollen-overlay-file-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write(&root.join("base/a"), "a");
+        write(&root.join("host"), "single file");
+        let layers = [layer(&root, "host")];
+
+        let sources = file_layers(&root.join("base"), &layers);
+        assert_eq!(sources.into_iter().collect::<Vec<_>>(), [(PathBuf::new(), Some("host".to_string()))]);
+
+        let destination = root.join("deployed");
+        apply_layers(&root.join("base"), &layers, &destination).unwrap();
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "single file");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
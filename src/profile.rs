@@ -0,0 +1,229 @@
+use crate::{entry::Entry, error::PollenError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Prefix for referring to another profile inside a profile
+pub const PROFILE_PREFIX: &str = "profile:";
+
+/// Prefix for referring to every entry with a tag inside a profile
+pub const TAG_PREFIX: &str = "tag:";
+
+/// A profile as written in pollen.yaml or track.yaml
+///
+/// Either a plain list of entries to include, or separate `include` and `exclude` lists.
+/// Items are entry names, aliases, `profile:<name>` and `tag:<name>` references or `*` for every entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProfileDefinition {
+    Entries(Vec<String>),
+    Rules {
+        #[serde(default)]
+        include: Vec<String>,
+        #[serde(default)]
+        exclude: Vec<String>,
+    },
+}
+
+impl ProfileDefinition {
+    fn include(&self) -> &[String] {
+        match self {
+            ProfileDefinition::Entries(entries) => entries,
+            ProfileDefinition::Rules { include, .. } => include,
+        }
+    }
+
+    fn exclude(&self) -> &[String] {
+        match self {
+            ProfileDefinition::Entries(_) => &[],
+            ProfileDefinition::Rules { exclude, .. } => exclude,
+        }
+    }
+}
+
+/// All profiles known to Pollen, by name
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    definitions: BTreeMap<String, ProfileDefinition>,
+}
+
+impl Profiles {
+    /// Combine profiles from several sources, refusing the same name twice
+    pub fn from_sources(sources: Vec<BTreeMap<String, ProfileDefinition>>) -> Result<Self, PollenError> {
+        let mut definitions = BTreeMap::new();
+        for source in sources {
+            for (name, definition) in source {
+                if definitions.insert(name.clone(), definition).is_some() {
+                    return Err(PollenError::InvalidOption(format!(
+                        "Profile '{}' is defined in both pollen.yaml and track.yaml", name
+                    )));
+                }
+            }
+        }
+        Ok(Profiles { definitions })
+    }
+
+    /// Names of all profiles, sorted
+    pub fn names(&self) -> Vec<&str> {
+        self.definitions.keys().map(String::as_str).collect()
+    }
+
+    /// Whether a profile with this name exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.definitions.contains_key(name)
+    }
+
+    /// Select the entries of a profile, adding everything they depend on.
+    /// The result keeps the order of `entries`.
+    pub fn select(&self, name: &str, entries: Vec<Entry>) -> Result<Vec<Entry>, PollenError> {
+        let mut selected = self.resolve(name, &entries, &mut Vec::new())?;
+        add_dependencies(&mut selected, &entries);
+
+        Ok(entries.into_iter().filter(|entry| selected.contains(&entry.name)).collect())
+    }
+
+    /// Resolve a profile to entry names. `stack` holds the profiles being resolved, to catch cycles.
+    fn resolve(&self, name: &str, entries: &[Entry], stack: &mut Vec<String>) -> Result<HashSet<String>, PollenError> {
+        let definition = self.definitions.get(name).ok_or_else(|| {
+            let known = self.names().join(", ");
+            PollenError::InvalidOption(if known.is_empty() {
+                format!("Unknown profile '{}' (no profiles are defined)", name)
+            } else {
+                format!("Unknown profile '{}' (known profiles: {})", name, known)
+            })
+        })?;
+
+        if stack.iter().any(|profile| profile == name) {
+            stack.push(name.to_string());
+            return Err(PollenError::CircularDependency(format!(
+                "Profiles include each other: {}", stack.join(" -> ")
+            )));
+        }
+        stack.push(name.to_string());
+
+        let mut selected = HashSet::new();
+        for item in definition.include() {
+            selected.extend(self.resolve_item(name, item, entries, stack)?);
+        }
+        for item in definition.exclude() {
+            for excluded in self.resolve_item(name, item, entries, stack)? {
+                selected.remove(&excluded);
+            }
+        }
+
+        stack.pop();
+        Ok(selected)
+    }
+
+    fn resolve_item(&self, profile: &str, item: &str, entries: &[Entry], stack: &mut Vec<String>) -> Result<HashSet<String>, PollenError> {
+        if let Some(other) = item.strip_prefix(PROFILE_PREFIX) {
+            return self.resolve(other, entries, stack);
+        }
+
+        if let Some(tag) = item.strip_prefix(TAG_PREFIX) {
+            let tagged: HashSet<String> = entries
+                .iter()
+                .filter(|entry| entry.tags.iter().any(|t| t == tag))
+                .map(|entry| entry.name.clone())
+                .collect();
+
+            if tagged.is_empty() {
+                return Err(PollenError::InvalidOption(format!(
+                    "Profile '{}' refers to tag '{}', which no entry has", profile, tag
+                )));
+            }
+            return Ok(tagged);
+        }
+
+        if item == "*" {
+            return Ok(entries.iter().map(|entry| entry.name.clone()).collect());
+        }
+
+        let matched: HashSet<String> = entries
+            .iter()
+            .filter(|entry| entry.matches_name(item))
+            .map(|entry| entry.name.clone())
+            .collect();
+
+        if matched.is_empty() {
+            return Err(PollenError::InvalidOption(format!(
+                "Profile '{}' refers to '{}', which is not an entry or alias", profile, item
+            )));
+        }
+
+        Ok(matched)
+    }
+}
+
+/// Add the transitive dependencies of the selected entries, optional ones included when they exist
+pub fn add_dependencies(selected: &mut HashSet<String>, entries: &[Entry]) {
+    let mut pending: Vec<String> = selected.iter().cloned().collect();
+
+    while let Some(name) = pending.pop() {
+        let Some(entry) = entries.iter().find(|entry| entry.name == name) else {
+            continue;
+        };
+
+        for dependency in entry.depends_on.iter().chain(&entry.optional_depends_on) {
+            if let Some(resolved) = entries.iter().find(|entry| entry.matches_name(dependency)) {
+                if selected.insert(resolved.name.clone()) {
+                    pending.push(resolved.name.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::EntryArgument;
+    use std::path::PathBuf;
+
+    fn entry(name: &str) -> Entry {
+        Entry::new(EntryArgument { name: name.to_string(), path: PathBuf::from("/home/user").join(name) })
+    }
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            entry("zsh").with_tags(vec!["shell".to_string()]),
+            entry("starship").with_tags(vec!["shell".to_string()]),
+            entry("nvim").with_alias("editor".to_string()).with_dependencies(vec!["ripgrep".to_string()]),
+            entry("ripgrep"),
+            entry("i3").with_tags(vec!["gui".to_string()]),
+        ]
+    }
+
+    fn profiles(yaml: &str) -> Profiles {
+        Profiles::from_sources(vec![serde_yaml::from_str(yaml).unwrap()]).unwrap()
+    }
+
+    fn names(entries: Vec<Entry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn profiles_combine_tags_aliases_and_other_profiles() {
+        let profiles = profiles("{cli: [tag:shell, editor], server: {include: [profile:cli], exclude: [starship]}}");
+        assert_eq!(names(profiles.select("cli", entries()).unwrap()), ["zsh", "starship", "nvim", "ripgrep"]);
+        assert_eq!(names(profiles.select("server", entries()).unwrap()), ["zsh", "nvim", "ripgrep"]);
+    }
+
+    #[test]
+    fn everything_but_excluded_entries() {
+        let profiles = profiles("{headless: {include: ['*'], exclude: [tag:gui]}}");
+        assert_eq!(names(profiles.select("headless", entries()).unwrap()), ["zsh", "starship", "nvim", "ripgrep"]);
+    }
+
+    #[test]
+    fn broken_profiles_are_errors() {
+        let profiles = profiles("{a: [profile:b], b: [profile:a], typo: [zhs], untagged: [tag:nope]}");
+        let error = profiles.select("a", entries()).unwrap_err();
+        assert!(error.to_string().contains("a -> b -> a"), "{}", error);
+        assert!(profiles.select("typo", entries()).unwrap_err().to_string().contains("'zhs'"));
+        assert!(profiles.select("untagged", entries()).unwrap_err().to_string().contains("tag 'nope'"));
+        assert!(profiles.select("missing", entries()).unwrap_err().to_string().contains("known profiles: a, b, typo, untagged"));
+
+        let twice = Profiles::from_sources(vec![serde_yaml::from_str("{a: [zsh]}").unwrap(), serde_yaml::from_str("{a: [nvim]}").unwrap()]);
+        assert!(twice.is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! Secret placeholders: `{{ secret "name" }}` in a stored file stands for a value kept out of the
+//! files directory.
+//!
+//! Scatter writes the value in place of the placeholder, and gather turns the value back into the
+//! placeholder, so the value never reaches the files directory or its repository.
+
+use crate::{
+    crypt::{expand_home, write_private},
+    dirs::{PollenConfig, PollenDirs},
+    error::PollenError,
+};
+use serde_yaml::{Mapping, Value};
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::Range,
+    path::Path,
+    process::Command,
+    sync::Mutex,
+};
+
+/// Environment variables holding secrets, e.g. `POLLEN_SECRET_GITHUB_TOKEN` for `github_token`
+const ENV_PREFIX: &str = "POLLEN_SECRET_";
+
+/// Secrets file used when pollen.yaml names none, next to pollen.yaml
+const DEFAULT_SECRETS_FILE: &str = "secrets.yaml";
+
+/// Values printed by `secret_command`, by the command that printed them
+static COMMAND_VALUES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// A secret placeholder as it appears in a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    /// The placeholder as written, braces included
+    pub text: String,
+    /// The name of the secret it stands for
+    pub name: String,
+}
+
+/// Where secret values come from: the environment, the secrets file and `secret_command`
+pub struct Secrets {
+    file: BTreeMap<String, String>,
+    command: Option<String>,
+}
+
+impl Secrets {
+    /// The secret sources configured in pollen.yaml
+    pub fn from_config(dirs: &PollenDirs, config: &PollenConfig) -> Result<Secrets, PollenError> {
+        let path = match &config.secrets_file {
+            Some(path) => expand_home(path),
+            None => dirs.config_dir.join(DEFAULT_SECRETS_FILE),
+        };
+
+        // Secrets inside the files directory would end up in the repository they are kept out of
+        if path.starts_with(&dirs.files_dir) {
+            return Err(PollenError::Secret(format!(
+                "The secrets file {} is inside the files directory; move it elsewhere", path.display()
+            )));
+        }
+
+        let file = if path.is_file() {
+            load_secrets_file(&path)?
+        } else if config.secrets_file.is_some() {
+            return Err(PollenError::Secret(format!("Secrets file {} does not exist", path.display())));
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Secrets { file, command: config.secret_command.clone() })
+    }
+
+    /// The value of the secret `name`, from the environment, the secrets file or `secret_command`, in that order
+    pub fn get(&self, name: &str) -> Result<String, PollenError> {
+        if let Ok(value) = std::env::var(env_name(name)) {
+            return Ok(value);
+        }
+        if let Some(value) = self.file.get(name) {
+            return Ok(value.clone());
+        }
+
+        match &self.command {
+            Some(command) => run_secret_command(command, name),
+            None => Err(PollenError::Secret(format!(
+                "No value for secret '{}' (set {}, add it to the secrets file or configure secret_command)",
+                name, env_name(name)
+            ))),
+        }
+    }
+
+    /// `text` with every placeholder replaced by its secret's value
+    pub fn resolve(&self, text: &str) -> Result<String, PollenError> {
+        let mut output = String::with_capacity(text.len());
+        let mut position = 0;
+        for (range, placeholder) in find_placeholders(text) {
+            output.push_str(&text[position..range.start]);
+            output.push_str(&self.get(&placeholder.name)?);
+            position = range.end;
+        }
+        output.push_str(&text[position..]);
+        Ok(output)
+    }
+
+    /// `text` with the values of the secrets behind `placeholders` turned back into placeholders.
+    ///
+    /// Only secrets the stored copy already references are concealed, so other secrets that
+    /// happen to appear in a file are left to secret scanning instead of being replaced blindly.
+    pub fn conceal(&self, text: &str, placeholders: &[Placeholder]) -> Result<String, PollenError> {
+        let mut replacements: Vec<(String, String)> = Vec::new();
+        let mut names: Vec<&str> = Vec::new();
+        for placeholder in placeholders {
+            if !names.contains(&placeholder.name.as_str()) {
+                replacements.push((self.get(&placeholder.name)?, placeholder.text.clone()));
+                names.push(&placeholder.name);
+            }
+        }
+
+        // Longer values first, so a secret containing another is replaced as a whole
+        replacements.retain(|(value, _)| !value.is_empty());
+        replacements.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+        if replacements.is_empty() {
+            return Ok(text.to_string());
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            match replacements.iter().find(|(value, _)| rest.starts_with(value.as_str())) {
+                Some((value, placeholder)) => {
+                    output.push_str(placeholder);
+                    rest = &rest[value.len()..];
+                }
+                None => {
+                    output.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// The secret named by a `{{ }}` expression such as `secret "github_token"`, if it is a placeholder
+pub fn placeholder_name(expression: &str) -> Option<&str> {
+    let rest = expression.trim().strip_prefix("secret")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let rest = rest.trim();
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let name = rest.strip_prefix(quote)?.strip_suffix(quote)?;
+    is_valid_name(name).then_some(name)
+}
+
+/// Every placeholder in `text`, in order
+pub fn placeholders(text: &str) -> Vec<Placeholder> {
+    find_placeholders(text).into_iter().map(|(_, placeholder)| placeholder).collect()
+}
+
+/// Every placeholder in a file or in the files of a directory tree
+pub fn path_placeholders(path: &Path) -> Vec<Placeholder> {
+    if path.is_dir() {
+        let Ok(children) = fs::read_dir(path) else {
+            return Vec::new();
+        };
+        return children.flatten().flat_map(|child| path_placeholders(&child.path())).collect();
+    }
+
+    fs::read_to_string(path).map(|text| placeholders(&text)).unwrap_or_default()
+}
+
+/// Copy a file or directory tree to `destination` with every placeholder resolved.
+/// Files that are not valid UTF-8 are copied unchanged, and every file is only readable by its owner.
+pub fn resolve_path(source: &Path, destination: &Path, secrets: &Secrets) -> Result<(), PollenError> {
+    transform_path(source, destination, true, &|text| secrets.resolve(text))
+}
+
+/// Copy a file or directory tree to `destination` with secret values turned back into placeholders
+pub fn conceal_path(source: &Path, destination: &Path, secrets: &Secrets, placeholders: &[Placeholder]) -> Result<(), PollenError> {
+    transform_path(source, destination, false, &|text| secrets.conceal(text, placeholders))
+}
+
+fn transform_path(
+    source: &Path,
+    destination: &Path,
+    private: bool,
+    transform: &dyn Fn(&str) -> Result<String, PollenError>,
+) -> Result<(), PollenError> {
+    if source.is_dir() {
+        fs::create_dir_all(destination).map_err(PollenError::Io)?;
+        for entry in fs::read_dir(source).map_err(PollenError::Io)? {
+            let entry = entry.map_err(PollenError::Io)?;
+            transform_path(&entry.path(), &destination.join(entry.file_name()), private, transform)?;
+        }
+        return Ok(());
+    }
+
+    let bytes = fs::read(source).map_err(PollenError::Io)?;
+    let output = match String::from_utf8(bytes) {
+        Ok(text) => transform(&text)
+            .map_err(|e| match e {
+                PollenError::Secret(message) => PollenError::Secret(format!("{}: {}", source.display(), message)),
+                other => other,
+            })?
+            .into_bytes(),
+        Err(e) => e.into_bytes(),
+    };
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    if private {
+        return write_private(destination, &output);
+    }
+    fs::write(destination, output).map_err(PollenError::Io)?;
+
+    let permissions = fs::metadata(source).map_err(PollenError::Io)?.permissions();
+    fs::set_permissions(destination, permissions).map_err(PollenError::Io)?;
+
+    Ok(())
+}
+
+fn find_placeholders(text: &str) -> Vec<(Range<usize>, Placeholder)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = text[offset..].find("{{").map(|start| offset + start) {
+        let Some(end) = text[start + 2..].find("}}").map(|end| start + 2 + end + 2) else {
+            break;
+        };
+        match placeholder_name(&text[start + 2..end - 2]) {
+            Some(name) => {
+                let placeholder = Placeholder { text: text[start..end].to_string(), name: name.to_string() };
+                found.push((start..end, placeholder));
+                offset = end;
+            }
+            None => offset = start + 2,
+        }
+    }
+
+    found
+}
+
+/// Names end up in environment variable names and `secret_command`, so they are kept plain
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c))
+}
+
+fn env_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("{}{}", ENV_PREFIX, name)
+}
+
+fn load_secrets_file(path: &Path) -> Result<BTreeMap<String, String>, PollenError> {
+    let content = fs::read_to_string(path).map_err(PollenError::Io)?;
+    if content.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let mapping: Mapping = serde_yaml::from_str(&content).map_err(PollenError::Yaml)?;
+    let mut secrets = BTreeMap::new();
+    for (name, value) in mapping {
+        let value = match value {
+            Value::String(value) => value,
+            Value::Number(value) => value.to_string(),
+            _ => {
+                return Err(PollenError::Secret(format!(
+                    "Secret {:?} in {} must be a string", name, path.display()
+                )));
+            }
+        };
+        let Value::String(name) = name else {
+            return Err(PollenError::Secret(format!("Secret names in {} must be strings", path.display())));
+        };
+        secrets.insert(name, value);
+    }
+    Ok(secrets)
+}
+
+/// Run `secret_command` with `{name}` replaced by the secret's name, taking its output as the value.
+///
+/// Each command runs at most once per pollen run, since it may prompt or hit a password manager.
+fn run_secret_command(command: &str, name: &str) -> Result<String, PollenError> {
+    let command = command.replace("{name}", name);
+    let mut values = COMMAND_VALUES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(value) = values.get(&command) {
+        return Ok(value.clone());
+    }
+    let value = run_command(&command, name)?;
+    values.insert(command, value.clone());
+    Ok(value)
+}
+
+fn run_command(command: &str, name: &str) -> Result<String, PollenError> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| PollenError::Secret(format!("Cannot run '{}': {}", command, e)))?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(PollenError::Secret(format!(
+            "'{}' failed for secret '{}': {}", command, name, message.lines().next().unwrap_or("").trim()
+        )));
+    }
+
+    let value = String::from_utf8(output.stdout)
+        .map_err(|_| PollenError::Secret(format!("'{}' printed a value for '{}' that is not text", command, name)))?;
+    let value = value.strip_suffix('\n').map(|value| value.strip_suffix('\r').unwrap_or(value)).unwrap_or(&value);
+    if value.is_empty() {
+        return Err(PollenError::Secret(format!("'{}' printed nothing for secret '{}'", command, name)));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets(pairs: &[(&str, &str)], command: Option<&str>) -> Secrets {
+        Secrets {
+            file: pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            command: command.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn placeholder_names_are_parsed() {
+        assert_eq!(placeholder_name(" secret \"github_token\" "), Some("github_token"));
+        assert_eq!(placeholder_name("secret 'api.key'"), Some("api.key"));
+        assert_eq!(placeholder_name("secrets \"x\""), None);
+        assert_eq!(placeholder_name("secret \"-rf\""), None);
+        assert_eq!(placeholder_name("secret \"a b\""), None);
+    }
+
+    #[test]
+    fn placeholders_are_found_among_other_braces() {
+        let found = placeholders("{{ user }} {{ secret \"a\" }} {{secret 'b'}}");
+        let names: Vec<&str> = found.iter().map(|placeholder| placeholder.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(found[1].text, "{{secret 'b'}}");
+    }
+
+    #[test]
+    fn resolve_and_conceal_round_trip() {
+        let secrets = secrets(&[("token", "abc123")], None);
+        let stored = "token = {{ secret \"token\" }}\n";
+        let resolved = secrets.resolve(stored).unwrap();
+        assert_eq!(resolved, "token = abc123\n");
+        assert_eq!(secrets.conceal(&resolved, &placeholders(stored)).unwrap(), stored);
+    }
+
+    #[test]
+    fn only_referenced_secrets_are_concealed() {
+        let secrets = secrets(&[("token", "abc123"), ("short", "1")], None);
+        let text = "abc123 and version 1\n";
+        assert_eq!(secrets.conceal(text, &[]).unwrap(), text);
+        let concealed = secrets.conceal(text, &placeholders("{{ secret \"token\" }}")).unwrap();
+        assert_eq!(concealed, "{{ secret \"token\" }} and version 1\n");
+    }
+
+    #[test]
+    fn missing_secret_is_an_error() {
+        let error = secrets(&[], None).resolve("{{ secret \"nope\" }}").unwrap_err();
+        assert!(error.to_string().contains("POLLEN_SECRET_NOPE"));
+    }
+
+    #[test]
+    fn secret_command_runs_once_per_secret() {
+        let counter = std::env::temp_dir().join(format!("pollen-secret-test-{}", std::process::id()));
+        let command = format!("echo run >> '{}'; echo value-{{name}}", counter.display());
+        let secrets = secrets(&[], Some(&command));
+
+        let text = "{{ secret \"cached\" }} {{ secret \"cached\" }}";
+        assert_eq!(secrets.resolve(text).unwrap(), "value-cached value-cached");
+        assert_eq!(secrets.get("cached").unwrap(), "value-cached");
+
+        let runs = fs::read_to_string(&counter).unwrap();
+        let _ = fs::remove_file(&counter);
+        assert_eq!(runs.lines().count(), 1);
+    }
+
+    #[test]
+    fn resolved_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("pollen-secret-modes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stored = dir.join("netrc");
+        fs::write(&stored, "password {{ secret \"pw\" }}\n").unwrap();
+        fs::set_permissions(&stored, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let secrets = secrets(&[("pw", "hunter2")], None);
+        resolve_path(&stored, &dir.join("resolved"), &secrets).unwrap();
+        conceal_path(&dir.join("resolved"), &dir.join("concealed"), &secrets, &path_placeholders(&stored)).unwrap();
+
+        let mode = |name: &str| fs::metadata(dir.join(name)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("resolved"), 0o600);
+        assert_eq!(fs::read_to_string(dir.join("concealed")).unwrap(), fs::read_to_string(&stored).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
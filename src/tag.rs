@@ -0,0 +1,184 @@
+use crate::error::PollenError;
+use std::fmt;
+
+/// A boolean expression over entry tags, such as `shell and not gui` or `editor or (shell, git)`
+///
+/// `,` is an alternative spelling of `or`, so `--tag shell,editor` selects either tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagExpression {
+    Tag(String),
+    And(Box<TagExpression>, Box<TagExpression>),
+    Or(Box<TagExpression>, Box<TagExpression>),
+    Not(Box<TagExpression>),
+}
+
+impl TagExpression {
+    /// Parse a tag expression
+    pub fn parse(input: &str) -> Result<TagExpression, PollenError> {
+        let tokens = tokenize(input);
+        if tokens.is_empty() {
+            return Err(PollenError::InvalidOption("Empty tag expression".to_string()));
+        }
+
+        let mut parser = Parser { tokens: &tokens, position: 0, input };
+        let expression = parser.parse_or()?;
+        if let Some(token) = tokens.get(parser.position) {
+            return Err(parser.error(&format!("unexpected '{}'", token)));
+        }
+        Ok(expression)
+    }
+
+    /// Whether an entry with these tags is selected
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagExpression::Tag(tag) => tags.iter().any(|t| t == tag),
+            TagExpression::And(left, right) => left.matches(tags) && right.matches(tags),
+            TagExpression::Or(left, right) => left.matches(tags) || right.matches(tags),
+            TagExpression::Not(inner) => !inner.matches(tags),
+        }
+    }
+
+    /// Every tag name the expression refers to
+    pub fn tag_names(&self) -> Vec<&str> {
+        match self {
+            TagExpression::Tag(tag) => vec![tag.as_str()],
+            TagExpression::And(left, right) | TagExpression::Or(left, right) => {
+                let mut names = left.tag_names();
+                names.extend(right.tag_names());
+                names
+            }
+            TagExpression::Not(inner) => inner.tag_names(),
+        }
+    }
+}
+
+impl fmt::Display for TagExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagExpression::Tag(tag) => write!(f, "{}", tag),
+            TagExpression::And(left, right) => write!(f, "({} and {})", left, right),
+            TagExpression::Or(left, right) => write!(f, "({} or {})", left, right),
+            TagExpression::Not(inner) => write!(f, "not {}", inner),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for c in input.chars() {
+        match c {
+            '(' | ')' | ',' | '!' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                tokens.push(c.to_string());
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    position: usize,
+    input: &'a str,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> PollenError {
+        PollenError::InvalidOption(format!("Invalid tag expression '{}': {}", self.input, message))
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn parse_or(&mut self) -> Result<TagExpression, PollenError> {
+        let mut expression = self.parse_and()?;
+        while matches!(self.peek(), Some("or") | Some(",")) {
+            self.position += 1;
+            expression = TagExpression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<TagExpression, PollenError> {
+        let mut expression = self.parse_not()?;
+        while self.peek() == Some("and") {
+            self.position += 1;
+            expression = TagExpression::And(Box::new(expression), Box::new(self.parse_not()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> Result<TagExpression, PollenError> {
+        if matches!(self.peek(), Some("not") | Some("!")) {
+            self.position += 1;
+            return Ok(TagExpression::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<TagExpression, PollenError> {
+        let token = self.peek().ok_or_else(|| self.error("expected a tag"))?.to_string();
+        self.position += 1;
+
+        match token.as_str() {
+            "(" => {
+                let expression = self.parse_or()?;
+                if self.peek() != Some(")") {
+                    return Err(self.error("missing ')'"));
+                }
+                self.position += 1;
+                Ok(expression)
+            }
+            ")" | "," | "and" | "or" => Err(self.error(&format!("expected a tag, found '{}'", token))),
+            _ => Ok(TagExpression::Tag(token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expression = TagExpression::parse("shell or editor and not gui").unwrap();
+        assert_eq!(expression.to_string(), "(shell or (editor and not gui))");
+        assert!(expression.matches(&tags(&["shell", "gui"])));
+        assert!(!expression.matches(&tags(&["editor", "gui"])));
+    }
+
+    #[test]
+    fn comma_and_bang_are_alternative_spellings() {
+        let expression = TagExpression::parse("!gui,(shell, git)").unwrap();
+        assert!(expression.matches(&tags(&[])));
+        assert!(expression.matches(&tags(&["gui", "git"])));
+        assert!(!expression.matches(&tags(&["gui"])));
+        assert_eq!(expression.tag_names(), vec!["gui", "shell", "git"]);
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        for input in ["", "shell and", "(shell", "shell)", "or git"] {
+            assert!(TagExpression::parse(input).is_err(), "{:?} parsed", input);
+        }
+    }
+}
@@ -0,0 +1,694 @@
+use crate::{
+    dirs::{PollenConfig, PollenDirs},
+    error::PollenError,
+    host::HostFacts,
+    secret::placeholder_name,
+    yaml_ext::DeepMerge,
+};
+use serde_yaml::{Mapping, Value};
+use std::{fs, path::Path};
+
+/// Build the variables available to templates.
+///
+/// Later sources override earlier ones: built-ins (`hostname`, `username`, `os`, `arch`, `distro`),
+/// `variables` in pollen.yaml, then `variables` in the machine-local local.yaml.
+/// Environment variables are read as templates name them, e.g. `{{ env.EDITOR }}`, rather than
+/// handed over wholesale.
+pub fn template_variables(dirs: &PollenDirs, config: &PollenConfig, facts: &HostFacts) -> Result<Mapping, PollenError> {
+    let mut variables = Mapping::new();
+    variables.insert("hostname".into(), facts.hostname.clone().into());
+    variables.insert("username".into(), facts.username.clone().into());
+    variables.insert("os".into(), facts.os.clone().into());
+    variables.insert("arch".into(), facts.arch.clone().into());
+    variables.insert("distro".into(), facts.distro.clone().unwrap_or_default().into());
+
+    if let Some(config_variables) = &config.variables {
+        variables.deep_merge(config_variables);
+    }
+
+    if let Some(local_variables) = &dirs.load_local_config()?.variables {
+        variables.deep_merge(local_variables);
+    }
+
+    Ok(variables)
+}
+
+/// Render a template file or every file in a directory tree into `destination`.
+/// Files that are not valid UTF-8 are copied unchanged.
+pub fn render_path(source: &Path, destination: &Path, variables: &Mapping) -> Result<(), PollenError> {
+    if source.is_dir() {
+        fs::create_dir_all(destination).map_err(PollenError::Io)?;
+        for entry in fs::read_dir(source).map_err(PollenError::Io)? {
+            let entry = entry.map_err(PollenError::Io)?;
+            render_path(&entry.path(), &destination.join(entry.file_name()), variables)?;
+        }
+        return Ok(());
+    }
+
+    let bytes = fs::read(source).map_err(PollenError::Io)?;
+    let output = match String::from_utf8(bytes) {
+        Ok(text) => render(&text, variables)
+            .map_err(|e| PollenError::Template(format!("{}: {}", source.display(), e)))?
+            .into_bytes(),
+        Err(e) => e.into_bytes(),
+    };
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(PollenError::Io)?;
+    }
+    fs::write(destination, output).map_err(PollenError::Io)?;
+
+    let permissions = fs::metadata(source).map_err(PollenError::Io)?.permissions();
+    fs::set_permissions(destination, permissions).map_err(PollenError::Io)?;
+
+    Ok(())
+}
+
+/// Render template source.
+///
+/// Supports `{{ expression }}`, `{% if %}`/`{% elif %}`/`{% else %}`/`{% endif %}`,
+/// `{% for item in list %}`/`{% endfor %}` and `{# comments #}`. A line holding only a
+/// block tag or comment is removed entirely so tags do not leave blank lines behind.
+/// Text between `{% raw %}` and `{% endraw %}` is copied as it is, braces included.
+/// Secret placeholders such as `{{ secret "token" }}` are kept as they are.
+pub fn render(source: &str, variables: &Mapping) -> Result<String, String> {
+    let tokens = tokenize(source)?;
+    let mut position = 0;
+    let (nodes, terminator) = parse_block(&tokens, &mut position, &[])?;
+    if let Some(tag) = terminator {
+        return Err(format!("unexpected {{% {} %}} on line {}", tag.keyword, tag.line));
+    }
+
+    let mut scopes = vec![variables.clone()];
+    let mut output = String::new();
+    render_nodes(&nodes, &mut scopes, &mut output)?;
+    Ok(output)
+}
+
+enum Token {
+    Text(String),
+    Expression { body: String, line: usize },
+    Tag { body: String, line: usize },
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut rest = source;
+    let mut consumed = 0;
+    // Whether `text` begins at the start of a line, so a tag with only whitespace before it owns its line
+    let mut text_at_line_start = true;
+
+    while let Some(start) = find_opening(rest) {
+        let opening = &rest[start..start + 2];
+        let closing = match opening {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let line = source[..consumed + start].matches('\n').count() + 1;
+        let body_start = start + 2;
+        let body_end = rest[body_start..]
+            .find(closing)
+            .map(|offset| body_start + offset)
+            .ok_or_else(|| format!("unclosed '{}' on line {}", opening, line))?;
+        let mut end = body_end + 2;
+
+        text.push_str(&rest[..start]);
+
+        // Block tags and comments alone on their line swallow the whole line
+        if opening != "{{" {
+            let line_start = text.rfind('\n').map(|index| index + 1).unwrap_or(0);
+            let before_blank = text[line_start..].trim().is_empty();
+            let after = &rest[end..];
+            let line_end = after.find('\n').map(|index| index + 1).unwrap_or(after.len());
+            let after_blank = after[..line_end].trim().is_empty();
+
+            if before_blank && after_blank && (line_start > 0 || text_at_line_start) {
+                text.truncate(line_start);
+                end += line_end;
+            }
+        }
+        text_at_line_start = source[..consumed + end].ends_with('\n') || consumed + end == 0;
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(std::mem::take(&mut text)));
+        }
+
+        let body = rest[body_start..body_end].trim().to_string();
+        match opening {
+            "{{" => tokens.push(Token::Expression { body, line }),
+            "{%" if body == "raw" => {
+                let (raw, raw_end) = find_endraw(&rest[end..])
+                    .ok_or_else(|| format!("unclosed {{% raw %}} from line {}", line))?;
+                if !raw.is_empty() {
+                    tokens.push(Token::Text(raw.to_string()));
+                }
+                end += raw_end;
+                text_at_line_start = source[..consumed + end].ends_with('\n');
+            }
+            "{%" => tokens.push(Token::Tag { body, line }),
+            _ => {}
+        }
+
+        consumed += end;
+        rest = &rest[end..];
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+
+    Ok(tokens)
+}
+
+/// The literal text of a raw block and the length of `text` up to and including its
+/// `{% endraw %}`, which swallows its line when it stands alone on it
+fn find_endraw(text: &str) -> Option<(&str, usize)> {
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find("{%").map(|start| offset + start) {
+        let close = text[start + 2..].find("%}").map(|close| start + 2 + close)?;
+        if text[start + 2..close].trim() != "endraw" {
+            offset = start + 2;
+            continue;
+        }
+
+        let mut raw = &text[..start];
+        let mut end = close + 2;
+        let line_start = raw.rfind('\n').map(|index| index + 1);
+        let after = &text[end..];
+        let line_end = after.find('\n').map(|index| index + 1).unwrap_or(after.len());
+        if let Some(line_start) = line_start {
+            if raw[line_start..].trim().is_empty() && after[..line_end].trim().is_empty() {
+                raw = &raw[..line_start];
+                end += line_end;
+            }
+        }
+        return Some((raw, end));
+    }
+    None
+}
+
+fn find_opening(text: &str) -> Option<usize> {
+    ["{{", "{%", "{#"]
+        .iter()
+        .filter_map(|opening| text.find(opening))
+        .min()
+}
+
+enum Node {
+    Text(String),
+    Expression { expression: String, line: usize },
+    If { branches: Vec<(String, usize, Vec<Node>)>, otherwise: Vec<Node> },
+    For { variable: String, iterable: String, line: usize, body: Vec<Node> },
+}
+
+struct BlockTag {
+    keyword: String,
+    arguments: String,
+    line: usize,
+}
+
+/// Parse nodes until one of the `terminators` tags (returned) or the end of input
+fn parse_block(tokens: &[Token], position: &mut usize, terminators: &[&str]) -> Result<(Vec<Node>, Option<BlockTag>), String> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.get(*position) {
+        *position += 1;
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text.clone())),
+            Token::Expression { body, line } => nodes.push(Node::Expression {
+                expression: body.clone(),
+                line: *line,
+            }),
+            Token::Tag { body, line } => {
+                let (keyword, arguments) = match body.split_once(char::is_whitespace) {
+                    Some((keyword, arguments)) => (keyword.to_string(), arguments.trim().to_string()),
+                    None => (body.clone(), String::new()),
+                };
+                let tag = BlockTag { keyword, arguments, line: *line };
+
+                match tag.keyword.as_str() {
+                    "if" => nodes.push(parse_if(tokens, position, tag)?),
+                    "for" => nodes.push(parse_for(tokens, position, tag)?),
+                    keyword if terminators.contains(&keyword) => return Ok((nodes, Some(tag))),
+                    "elif" | "else" | "endif" | "endfor" => return Ok((nodes, Some(tag))),
+                    other => return Err(format!("unknown tag '{}' on line {}", other, tag.line)),
+                }
+            }
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn parse_if(tokens: &[Token], position: &mut usize, tag: BlockTag) -> Result<Node, String> {
+    let opening_line = tag.line;
+    let mut branches = Vec::new();
+    let mut condition = (tag.arguments, tag.line);
+
+    loop {
+        let (body, terminator) = parse_block(tokens, position, &["elif", "else", "endif"])?;
+        let terminator = terminator.ok_or_else(|| format!("unclosed {{% if %}} from line {}", opening_line))?;
+        branches.push((condition.0, condition.1, body));
+
+        match terminator.keyword.as_str() {
+            "elif" => condition = (terminator.arguments, terminator.line),
+            "else" => {
+                let (otherwise, end) = parse_block(tokens, position, &["endif"])?;
+                match end {
+                    Some(end) if end.keyword == "endif" => return Ok(Node::If { branches, otherwise }),
+                    Some(end) => return Err(format!("unexpected {{% {} %}} on line {}", end.keyword, end.line)),
+                    None => return Err(format!("unclosed {{% if %}} from line {}", opening_line)),
+                }
+            }
+            "endif" => return Ok(Node::If { branches, otherwise: Vec::new() }),
+            other => return Err(format!("unexpected {{% {} %}} on line {}", other, terminator.line)),
+        }
+    }
+}
+
+fn parse_for(tokens: &[Token], position: &mut usize, tag: BlockTag) -> Result<Node, String> {
+    let (variable, iterable) = tag
+        .arguments
+        .split_once(" in ")
+        .map(|(variable, iterable)| (variable.trim().to_string(), iterable.trim().to_string()))
+        .ok_or_else(|| format!("expected '{{% for item in list %}}' on line {}", tag.line))?;
+
+    let (body, end) = parse_block(tokens, position, &["endfor"])?;
+    match end {
+        Some(end) if end.keyword == "endfor" => Ok(Node::For { variable, iterable, line: tag.line, body }),
+        Some(end) => Err(format!("unexpected {{% {} %}} on line {}", end.keyword, end.line)),
+        None => Err(format!("unclosed {{% for %}} from line {}", tag.line)),
+    }
+}
+
+fn render_nodes(nodes: &[Node], scopes: &mut Vec<Mapping>, output: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            // Secret placeholders are left for scatter to resolve after rendering
+            Node::Expression { expression, .. } if placeholder_name(expression).is_some() => {
+                output.push_str(&format!("{{{{ {} }}}}", expression));
+            }
+            Node::Expression { expression, line } => {
+                let value = evaluate(expression, scopes).map_err(|e| format!("{} on line {}", e, line))?;
+                output.push_str(&display_value(&value).map_err(|e| {
+                    format!("'{}' {} on line {}", expression, e, line)
+                })?);
+            }
+            Node::If { branches, otherwise } => {
+                let mut matched = false;
+                for (condition, line, body) in branches {
+                    let value = evaluate(condition, scopes).map_err(|e| format!("{} on line {}", e, line))?;
+                    if is_truthy(&value) {
+                        render_nodes(body, scopes, output)?;
+                        matched = true;
+                        break;
+                    }
+                }
+                if !matched {
+                    render_nodes(otherwise, scopes, output)?;
+                }
+            }
+            Node::For { variable, iterable, line, body } => {
+                let items = match evaluate(iterable, scopes).map_err(|e| format!("{} on line {}", e, line))? {
+                    Value::Sequence(items) => items,
+                    Value::Mapping(mapping) => mapping.keys().cloned().collect(),
+                    Value::Null => Vec::new(),
+                    _ => return Err(format!("'{}' is not a list on line {}", iterable, line)),
+                };
+
+                for item in items {
+                    let mut scope = Mapping::new();
+                    scope.insert(variable.clone().into(), item);
+                    scopes.push(scope);
+                    let result = render_nodes(body, scopes, output);
+                    scopes.pop();
+                    result?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn display_value(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(string) => Ok(string.clone()),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::Bool(boolean) => Ok(boolean.to_string()),
+        Value::Null => Err("is undefined".to_string()),
+        Value::Sequence(_) => Err("is a list and cannot be printed".to_string()),
+        Value::Mapping(_) => Err("is a mapping and cannot be printed".to_string()),
+        Value::Tagged(tagged) => display_value(&tagged.value),
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(boolean) => *boolean,
+        Value::String(string) => !string.is_empty(),
+        Value::Number(number) => number.as_f64().map(|n| n != 0.0).unwrap_or(true),
+        Value::Sequence(items) => !items.is_empty(),
+        Value::Mapping(mapping) => !mapping.is_empty(),
+        Value::Tagged(tagged) => is_truthy(&tagged.value),
+    }
+}
+
+/// Look up a dotted variable path, innermost scope first. Undefined paths are `Null`.
+///
+/// `env.NAME` reads the environment variable `NAME` unless a variable called `env` is set.
+fn lookup(path: &str, scopes: &[Mapping]) -> Value {
+    let mut segments = path.split('.');
+    let first = segments.next().unwrap_or_default();
+
+    let Some(mut current) = scopes.iter().rev().find_map(|scope| scope.get(first)) else {
+        return match (first, segments.next(), segments.next()) {
+            ("env", Some(name), None) => std::env::var(name).map(Value::String).unwrap_or(Value::Null),
+            _ => Value::Null,
+        };
+    };
+
+    for segment in segments {
+        let next = match current {
+            Value::Mapping(mapping) => mapping.get(segment),
+            Value::Sequence(items) => segment.parse::<usize>().ok().and_then(|index| items.get(index)),
+            _ => None,
+        };
+        match next {
+            Some(next) => current = next,
+            None => return Value::Null,
+        }
+    }
+
+    current.clone()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExpressionToken {
+    Literal(String),
+    Word(String),
+    Operator(&'static str),
+}
+
+fn tokenize_expression(expression: &str) -> Result<Vec<ExpressionToken>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => {
+                            if let Some(escaped) = chars.next() {
+                                literal.push(escaped);
+                            }
+                        }
+                        Some(next) if next == c => break,
+                        Some(next) => literal.push(next),
+                        None => return Err(format!("unterminated string in '{}'", expression)),
+                    }
+                }
+                tokens.push(ExpressionToken::Literal(literal));
+            }
+            '(' | ')' | '[' | ']' | ',' => {
+                chars.next();
+                tokens.push(ExpressionToken::Operator(match c {
+                    '(' => "(",
+                    ')' => ")",
+                    '[' => "[",
+                    ']' => "]",
+                    _ => ",",
+                }));
+            }
+            '=' | '!' => {
+                chars.next();
+                if chars.next() != Some('=') {
+                    return Err(format!("expected '==' or '!=' in '{}'", expression));
+                }
+                tokens.push(ExpressionToken::Operator(if c == '=' { "==" } else { "!=" }));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "()[],=!\"'".contains(next) {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(ExpressionToken::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Evaluate an expression: literals, `[lists]`, variable paths, `==`, `!=`, `in`, `not`,
+/// `and`, `or` and parentheses
+fn evaluate(expression: &str, scopes: &[Mapping]) -> Result<Value, String> {
+    let tokens = tokenize_expression(expression)?;
+    if tokens.is_empty() {
+        return Err("empty expression".to_string());
+    }
+
+    let mut parser = ExpressionParser { tokens: &tokens, position: 0, scopes };
+    let value = parser.parse_or()?;
+    if parser.position != tokens.len() {
+        return Err(format!("unexpected '{}' in '{}'", token_text(&tokens[parser.position]), expression));
+    }
+    Ok(value)
+}
+
+fn token_text(token: &ExpressionToken) -> String {
+    match token {
+        ExpressionToken::Literal(literal) => format!("\"{}\"", literal),
+        ExpressionToken::Word(word) => word.clone(),
+        ExpressionToken::Operator(operator) => operator.to_string(),
+    }
+}
+
+struct ExpressionParser<'a> {
+    tokens: &'a [ExpressionToken],
+    position: usize,
+    scopes: &'a [Mapping],
+}
+
+impl ExpressionParser<'_> {
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(ExpressionToken::Word(w)) if w == word)
+    }
+
+    fn parse_or(&mut self) -> Result<Value, String> {
+        let mut value = self.parse_and()?;
+        while self.peek_word("or") {
+            self.position += 1;
+            let right = self.parse_and()?;
+            value = Value::Bool(is_truthy(&value) || is_truthy(&right));
+        }
+        Ok(value)
+    }
+
+    fn parse_and(&mut self) -> Result<Value, String> {
+        let mut value = self.parse_not()?;
+        while self.peek_word("and") {
+            self.position += 1;
+            let right = self.parse_not()?;
+            value = Value::Bool(is_truthy(&value) && is_truthy(&right));
+        }
+        Ok(value)
+    }
+
+    fn parse_not(&mut self) -> Result<Value, String> {
+        if self.peek_word("not") {
+            self.position += 1;
+            let value = self.parse_not()?;
+            return Ok(Value::Bool(!is_truthy(&value)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Value, String> {
+        let left = self.parse_operand()?;
+
+        match self.tokens.get(self.position) {
+            Some(ExpressionToken::Operator(operator @ ("==" | "!="))) => {
+                self.position += 1;
+                let right = self.parse_operand()?;
+                let equal = values_equal(&left, &right);
+                Ok(Value::Bool(if *operator == "==" { equal } else { !equal }))
+            }
+            Some(ExpressionToken::Word(word)) if word == "in" => {
+                self.position += 1;
+                let right = self.parse_operand()?;
+                Ok(Value::Bool(contains(&right, &left)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Value, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.position += 1;
+
+        match token {
+            ExpressionToken::Literal(literal) => Ok(Value::String(literal.clone())),
+            ExpressionToken::Operator("(") => {
+                let value = self.parse_or()?;
+                match self.tokens.get(self.position) {
+                    Some(ExpressionToken::Operator(")")) => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            ExpressionToken::Operator("[") => {
+                let mut items = Vec::new();
+                loop {
+                    if let Some(ExpressionToken::Operator("]")) = self.tokens.get(self.position) {
+                        self.position += 1;
+                        return Ok(Value::Sequence(items));
+                    }
+                    items.push(self.parse_operand()?);
+                    match self.tokens.get(self.position) {
+                        Some(ExpressionToken::Operator(",")) => self.position += 1,
+                        Some(ExpressionToken::Operator("]")) => {}
+                        _ => return Err("missing ']'".to_string()),
+                    }
+                }
+            }
+            ExpressionToken::Operator(operator) => Err(format!("unexpected '{}'", operator)),
+            ExpressionToken::Word(word) => Ok(match word.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => {
+                    if let Ok(number) = word.parse::<i64>() {
+                        Value::Number(number.into())
+                    } else if let Ok(number) = word.parse::<f64>() {
+                        Value::Number(number.into())
+                    } else {
+                        lookup(word, self.scopes)
+                    }
+                }
+            }),
+        }
+    }
+}
+
+fn values_equal(left: &Value, right: &Value) -> bool {
+    if left == right {
+        return true;
+    }
+    // Compare scalars by their text so `version == "2"` matches a numeric 2
+    match (display_value(left), display_value(right)) {
+        (Ok(left), Ok(right)) => left == right,
+        _ => false,
+    }
+}
+
+fn contains(haystack: &Value, needle: &Value) -> bool {
+    match haystack {
+        Value::Sequence(items) => items.iter().any(|item| values_equal(item, needle)),
+        Value::Mapping(mapping) => mapping.keys().any(|key| values_equal(key, needle)),
+        Value::String(string) => display_value(needle).map(|needle| string.contains(&needle)).unwrap_or(false),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn substitutes_variables_and_paths() {
+        let vars = variables("name: ada\nshell:\n  path: /bin/zsh\nlist: [a, b]");
+        assert_eq!(render("{{ name }} uses {{ shell.path }}", &vars).unwrap(), "ada uses /bin/zsh");
+        assert_eq!(render("{{ list.1 }}", &vars).unwrap(), "b");
+    }
+
+    #[test]
+    fn undefined_variable_is_an_error() {
+        let error = render("a\n{{ missing }}", &Mapping::new()).unwrap_err();
+        assert_eq!(error, "'missing' is undefined on line 2");
+    }
+
+    #[test]
+    fn conditions_choose_a_branch() {
+        let vars = variables("os: linux\nversion: 2");
+        let template = "{% if os == \"macos\" %}mac{% elif os in [\"linux\", \"bsd\"] %}unix{% else %}other{% endif %}";
+        assert_eq!(render(template, &vars).unwrap(), "unix");
+        assert_eq!(render("{% if version == \"2\" and not missing %}yes{% endif %}", &vars).unwrap(), "yes");
+        assert_eq!(render("{% if missing %}yes{% endif %}", &vars).unwrap(), "");
+    }
+
+    #[test]
+    fn loops_over_lists_and_mappings() {
+        let vars = variables("items: [a, b]\nmap: {x: 1, y: 2}");
+        assert_eq!(render("{% for i in items %}[{{ i }}]{% endfor %}", &vars).unwrap(), "[a][b]");
+        assert_eq!(render("{% for k in map %}{{ k }}{% endfor %}", &vars).unwrap(), "xy");
+    }
+
+    #[test]
+    fn tags_alone_on_a_line_leave_no_blank_line() {
+        let vars = variables("on: true");
+        let template = "first\n{% if on %}\nmiddle\n{% endif %}\n{# note #}\nlast\n";
+        assert_eq!(render(template, &vars).unwrap(), "first\nmiddle\nlast\n");
+    }
+
+    #[test]
+    fn raw_blocks_keep_braces() {
+        let vars = variables("name: ada");
+        let template = "{{ name }}\n{% raw %}\nlocal x = \"{{ y }}\" {% if %}\n{% endraw %}\nend\n";
+        assert_eq!(render(template, &vars).unwrap(), "ada\nlocal x = \"{{ y }}\" {% if %}\nend\n");
+        assert_eq!(render("a{% raw %}{{b}}{% endraw %}c", &vars).unwrap(), "a{{b}}c");
+    }
+
+    #[test]
+    fn unclosed_blocks_are_errors() {
+        assert_eq!(render("{% raw %}{{", &Mapping::new()).unwrap_err(), "unclosed {% raw %} from line 1");
+        assert_eq!(render("{% if x %}", &Mapping::new()).unwrap_err(), "unclosed {% if %} from line 1");
+        assert_eq!(render("{{ x", &Mapping::new()).unwrap_err(), "unclosed '{{' on line 1");
+        assert_eq!(render("{% bogus %}", &Mapping::new()).unwrap_err(), "unknown tag 'bogus' on line 1");
+    }
+
+    #[test]
+    fn env_reads_only_named_variables() {
+        std::env::set_var("POLLEN_TEMPLATE_TEST_EDITOR", "nvim");
+        assert_eq!(render("{{ env.POLLEN_TEMPLATE_TEST_EDITOR }}", &Mapping::new()).unwrap(), "nvim");
+        assert_eq!(render("{% for name in env %}{{ name }}{% endfor %}", &Mapping::new()).unwrap(), "");
+        assert_eq!(render("{% if env.POLLEN_TEMPLATE_TEST_UNSET %}set{% endif %}", &Mapping::new()).unwrap(), "");
+    }
+
+    #[test]
+    fn env_variable_overrides_environment() {
+        let vars = variables("env: {HOME: /custom}");
+        assert_eq!(render("{{ env.HOME }}", &vars).unwrap(), "/custom");
+    }
+
+    #[test]
+    fn secret_placeholders_pass_through() {
+        assert_eq!(
+            render("token={{ secret \"github_token\" }}", &Mapping::new()).unwrap(),
+            "token={{ secret \"github_token\" }}"
+        );
+    }
+}
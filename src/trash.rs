@@ -0,0 +1,301 @@
+use crate::{cli::utils::copy_path, dirs::PollenDirs, error::PollenError};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Name of the metadata file inside each trash item directory
+const INFO_FILE: &str = "info.json";
+
+/// Name the trashed file or directory is stored under inside its item directory
+const CONTENT_NAME: &str = "content";
+
+/// Metadata recorded for every item moved to the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    /// Identifier used by `pollen trash restore`
+    pub id: String,
+    /// Where the item was before it was trashed
+    pub original_path: String,
+    /// Unix timestamp when the item was trashed
+    pub trashed_at: u64,
+    /// Total size in bytes
+    pub size: u64,
+}
+
+/// Trash area for files Pollen removes or replaces on behalf of the user
+pub struct Trash {
+    dir: PathBuf,
+    max_age: Option<u64>,
+    max_size: Option<u64>,
+}
+
+impl Trash {
+    /// Open the trash in the Pollen data directory, using the limits from pollen.yaml
+    pub fn new(dirs: &PollenDirs) -> Result<Self, PollenError> {
+        let config = dirs.load_config()?;
+        Ok(Trash {
+            dir: dirs.trash_dir.clone(),
+            max_age: config.trash_max_age,
+            max_size: config.trash_max_size,
+        })
+    }
+
+    /// Move a file or directory into the trash instead of deleting it
+    pub fn move_to_trash(&self, path: &Path) -> Result<TrashItem, PollenError> {
+        let trashed_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        // Several items can be trashed within the same second
+        let mut counter = 0;
+        let (id, item_dir) = loop {
+            let id = format!("{}-{}", trashed_at, counter);
+            let item_dir = self.dir.join(&id);
+            if !item_dir.exists() {
+                break (id, item_dir);
+            }
+            counter += 1;
+        };
+
+        fs::create_dir_all(&item_dir).map_err(PollenError::Io)?;
+
+        let item = TrashItem {
+            id,
+            original_path: path.display().to_string(),
+            trashed_at,
+            size: path_size(path),
+        };
+
+        // Metadata first, so an item never sits in the trash without its original path
+        write_info(&item_dir, &item)?;
+        if let Err(e) = move_path(path, &item_dir.join(CONTENT_NAME)) {
+            let _ = fs::remove_dir_all(&item_dir);
+            return Err(e);
+        }
+
+        self.enforce_limits(&item.id)?;
+
+        Ok(item)
+    }
+
+    /// List trashed items, oldest first
+    pub fn list(&self) -> Result<Vec<TrashItem>, PollenError> {
+        let mut items = Vec::new();
+
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(items),
+            Err(e) => return Err(PollenError::Io(e)),
+        };
+
+        for entry in read_dir.flatten() {
+            // Items whose move never completed hold no content
+            if entry.path().join(CONTENT_NAME).symlink_metadata().is_err() {
+                continue;
+            }
+            let info_path = entry.path().join(INFO_FILE);
+            if let Ok(content) = fs::read_to_string(&info_path) {
+                if let Ok(item) = serde_json::from_str::<TrashItem>(&content) {
+                    items.push(item);
+                }
+            }
+        }
+
+        items.sort_by(|a, b| a.trashed_at.cmp(&b.trashed_at).then_with(|| a.id.cmp(&b.id)));
+        Ok(items)
+    }
+
+    /// Move a trashed item back to where it came from
+    pub fn restore(&self, id: &str) -> Result<TrashItem, PollenError> {
+        let item = self
+            .list()?
+            .into_iter()
+            .find(|item| item.id == id)
+            .ok_or_else(|| PollenError::InvalidOption(format!("No trash item with ID '{}'", id)))?;
+
+        let original_path = PathBuf::from(&item.original_path);
+        if original_path.symlink_metadata().is_ok() {
+            return Err(PollenError::InvalidOption(format!(
+                "Cannot restore '{}': {} already exists",
+                id, item.original_path
+            )));
+        }
+
+        if let Some(parent) = original_path.parent() {
+            fs::create_dir_all(parent).map_err(PollenError::Io)?;
+        }
+
+        let item_dir = self.dir.join(&item.id);
+        move_path(&item_dir.join(CONTENT_NAME), &original_path)?;
+        fs::remove_dir_all(&item_dir).map_err(PollenError::Io)?;
+
+        Ok(item)
+    }
+
+    /// Permanently delete everything in the trash, returning the number of items removed
+    pub fn empty(&self) -> Result<usize, PollenError> {
+        let items = self.list()?;
+        for item in &items {
+            self.delete_item(&item.id)?;
+        }
+        Ok(items.len())
+    }
+
+    /// Delete items older than `trash_max_age`, then the oldest items until the trash fits in
+    /// `trash_max_size`. The item identified by `keep` is never deleted.
+    fn enforce_limits(&self, keep: &str) -> Result<usize, PollenError> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut items = self.list()?;
+        let mut removed = 0;
+
+        if let Some(max_age) = self.max_age {
+            for item in items.iter().filter(|item| item.id != keep) {
+                if now.saturating_sub(item.trashed_at) > max_age {
+                    self.delete_item(&item.id)?;
+                    removed += 1;
+                }
+            }
+            items.retain(|item| item.id == keep || now.saturating_sub(item.trashed_at) <= max_age);
+        }
+
+        if let Some(max_size) = self.max_size {
+            let mut total: u64 = items.iter().map(|item| item.size).sum();
+            for item in items.iter().filter(|item| item.id != keep) {
+                if total <= max_size {
+                    break;
+                }
+                self.delete_item(&item.id)?;
+                total = total.saturating_sub(item.size);
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn delete_item(&self, id: &str) -> Result<(), PollenError> {
+        fs::remove_dir_all(self.dir.join(id)).map_err(PollenError::Io)
+    }
+}
+
+fn write_info(item_dir: &Path, item: &TrashItem) -> Result<(), PollenError> {
+    let content = serde_json::to_string_pretty(item)
+        .map_err(|e| PollenError::InvalidMapping(format!("JSON serialization error: {}", e)))?;
+    fs::write(item_dir.join(INFO_FILE), content).map_err(PollenError::Io)
+}
+
+/// Move a path, copying and deleting when source and destination are on different filesystems
+pub(crate) fn move_path(source: &Path, destination: &Path) -> Result<(), PollenError> {
+    if fs::rename(source, destination).is_ok() {
+        return Ok(());
+    }
+
+    let metadata = source.symlink_metadata().map_err(PollenError::Io)?;
+    if metadata.file_type().is_symlink() {
+        copy_symlink(source, destination)?;
+        fs::remove_file(source).map_err(PollenError::Io)?;
+    } else if metadata.is_dir() {
+        copy_path(source, destination)?;
+        fs::remove_dir_all(source).map_err(PollenError::Io)?;
+    } else {
+        copy_path(source, destination)?;
+        fs::remove_file(source).map_err(PollenError::Io)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, destination: &Path) -> Result<(), PollenError> {
+    let link_target = fs::read_link(source).map_err(PollenError::Io)?;
+    std::os::unix::fs::symlink(link_target, destination).map_err(PollenError::Io)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, destination: &Path) -> Result<(), PollenError> {
+    fs::copy(source, destination).map_err(PollenError::Io)?;
+    Ok(())
+}
+
+/// Total size of a file or directory tree in bytes, without following symlinks
+fn path_size(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| path_size(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trash(root: &Path, max_age: Option<u64>, max_size: Option<u64>) -> Trash {
+        Trash { dir: root.join("trash"), max_age, max_size }
+    }
+
+    fn file(root: &Path, name: &str, content: &str) -> PathBuf {
+        let path = root.join("home").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn trashed_files_and_links_come_back() {
+        let root = std::env::temp_dir().join(format!("pollen-trash-restore-{}", std::process::id()));
+        let trash = trash(&root, None, None);
+        let path = file(&root, ".zshrc", "zsh");
+        let link = root.join("home/.profile");
+        std::os::unix::fs::symlink("missing", &link).unwrap();
+
+        let item = trash.move_to_trash(&path).unwrap();
+        let link_item = trash.move_to_trash(&link).unwrap();
+        assert!(!path.exists() && link.symlink_metadata().is_err());
+        assert_eq!(trash.list().unwrap().len(), 2);
+
+        // Nothing is overwritten on the way back
+        file(&root, ".zshrc", "new zsh");
+        assert!(trash.restore(&item.id).is_err());
+        fs::remove_file(&path).unwrap();
+
+        trash.restore(&item.id).unwrap();
+        trash.restore(&link_item.id).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "zsh");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("missing"));
+        assert!(trash.list().unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn limits_drop_old_items_but_never_the_newest() {
+        let root = std::env::temp_dir().join(format!("pollen-trash-limits-{}", std::process::id()));
+        let trash = trash(&root, Some(3600), Some(4));
+
+        let old = trash.move_to_trash(&file(&root, "old", "a")).unwrap();
+        let aged = TrashItem { trashed_at: 1, ..old };
+        write_info(&trash.dir.join(&aged.id), &aged).unwrap();
+        let kept = trash.move_to_trash(&file(&root, "kept", "bb")).unwrap();
+        let ids = |trash: &Trash| trash.list().unwrap().into_iter().map(|item| item.id).collect::<Vec<_>>();
+        assert_eq!(ids(&trash), [kept.id]);
+
+        // Over the size limit on its own, and still kept because it was trashed last
+        let large = trash.move_to_trash(&file(&root, "large", "ccccc")).unwrap();
+        assert_eq!(ids(&trash), [large.id]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 use crate::error::PollenError;
 use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use yaml_rust2::{parser::{Event, MarkedEventReceiver, Parser}, scanner::Marker};
 
 /// Extension trait for serde_yaml::Value to provide convenient string conversion
 pub trait CanForceIntoString {
@@ -18,6 +20,42 @@ impl CanForceIntoString for Value {
     }
 }
 
+/// Extension trait for serde_yaml::Value to provide strict boolean conversion
+pub trait CanForceIntoBool {
+    fn force_into_bool(&self) -> Result<bool, PollenError>;
+}
+
+impl CanForceIntoBool for Value {
+    fn force_into_bool(&self) -> Result<bool, PollenError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(PollenError::InvalidMapping(format!(
+                "Expected a boolean value, got {:?}",
+                self
+            ))),
+        }
+    }
+}
+
+/// Extension trait for serde_yaml::Mapping to merge another mapping into it
+pub trait DeepMerge {
+    /// Merge `other` into `self`; nested mappings are merged key by key, anything else is replaced
+    fn deep_merge(&mut self, other: &Mapping);
+}
+
+impl DeepMerge for Mapping {
+    fn deep_merge(&mut self, other: &Mapping) {
+        for (key, value) in other {
+            match (self.get_mut(key), value) {
+                (Some(Value::Mapping(existing)), Value::Mapping(incoming)) => existing.deep_merge(incoming),
+                _ => {
+                    self.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
 /// Extension trait for serde_yaml::Mapping to determine endpoints and extract data
 pub trait Endpoint {
     /// Determine if a mapping represents an endpoint or a directory to recurse into
@@ -48,30 +86,44 @@ impl Endpoint for Mapping {
 
                 let sequence = value.as_sequence().unwrap();
                 
-                // If every submapping is a valid option, this is an endpoint
-                for (_index, mapping) in sequence.iter().enumerate() {
-                    if !mapping.is_mapping() {
+                // An endpoint's list holds only options; a list of strings reads as an option only
+                // when another item shows this is an entry rather than a directory
+                let mut ambiguous = None;
+                let mut has_option = false;
+                for mapping in sequence.iter() {
+                    let Some(mapping) = mapping.as_mapping() else {
                         return Ok(false);
-                    }
+                    };
 
-                    let mapping = mapping.as_mapping().unwrap();
-                    for key in mapping.keys() {
+                    for (key, value) in mapping {
                         if !key.is_string() {
                             return Err(PollenError::InvalidEndpoint(format!(
                                 "Expected a string key, got {:?}", key
                             )));
                         }
 
-                        const VALID_OPTIONS: [&str; 4] = ["run_before", "run_after", "depends_on", "alias_as"];
                         let key_str = key.force_into_string()?;
-                        let is_valid_option = VALID_OPTIONS.contains(&key_str.as_str());
-
-                        if !is_valid_option {
-                            return Ok(false);
+                        match option_reading(&key_str, value) {
+                            OptionReading::Option => has_option = true,
+                            OptionReading::Ambiguous => {
+                                ambiguous.get_or_insert(key_str);
+                            }
+                            OptionReading::Content => return Ok(false),
                         }
                     }
                 }
-                Ok(true)
+
+                match ambiguous {
+                    Some(option) if !has_option => {
+                        let name = self.get_key()?;
+                        Err(PollenError::InvalidEndpoint(format!(
+                            "'{name}' could be an entry with the {option} option or a directory holding '{option}'. \
+                             Give the option one value per item (- {option}: value), \
+                             or write the directory as a mapping ({name}: {{{option}: [...]}})"
+                        )))
+                    }
+                    _ => Ok(true),
+                }
             }
             None => Ok(false),
         }
@@ -100,3 +152,149 @@ impl Endpoint for Mapping {
         Ok(self.values().next().unwrap())
     }
 }
+
+/// How a `key: value` item in an entry's list reads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionReading {
+    /// Only an option can look like this
+    Option,
+    /// A list of strings, which is both a list option and a directory of files
+    Ambiguous,
+    /// Not an option, so the item is a directory
+    Content,
+}
+
+/// Read `key: value` as an option only when the value has that option's shape.
+///
+/// Options that predate directory names clashing with them are always options, as they always were.
+pub fn option_reading(key: &str, value: &Value) -> OptionReading {
+    const ALWAYS_OPTIONS: [&str; 4] = ["run_before", "run_after", "depends_on", "alias_as"];
+    const LIST_OPTIONS: [&str; 7] = [
+        "optional_depends_on", "after", "conflicts_with", "tags", "filters", "ignore_keys", "assemble_from",
+    ];
+    const SCALAR_OPTIONS: [&str; 10] = [
+        "before_scatter", "after_scatter", "before_gather", "after_gather", "before_undo", "after_undo",
+        "template", "managed_block", "merge", "encrypt",
+    ];
+
+    if ALWAYS_OPTIONS.contains(&key) {
+        return OptionReading::Option;
+    }
+    let is_option = LIST_OPTIONS.contains(&key) || SCALAR_OPTIONS.contains(&key) || key == "when";
+    if !is_option {
+        return OptionReading::Content;
+    }
+
+    match value {
+        // Directories are lists or mappings, so a scalar is always an option, checked when it is parsed
+        Value::Sequence(_) | Value::Mapping(_) => {}
+        _ => return OptionReading::Option,
+    }
+
+    match (key, value) {
+        ("when", Value::Mapping(_)) | ("assemble_from", Value::Mapping(_)) => OptionReading::Option,
+        (_, Value::Sequence(items)) if LIST_OPTIONS.contains(&key) => {
+            if items.iter().all(Value::is_string) {
+                OptionReading::Ambiguous
+            } else if key == "assemble_from" && items.iter().all(|item| item.is_string() || item.is_mapping()) {
+                // Fragments with conditions are mappings of file and when
+                OptionReading::Option
+            } else {
+                OptionReading::Content
+            }
+        }
+        _ => OptionReading::Content,
+    }
+}
+
+/// Lines of a YAML document's nodes, in the shape of its `Value`, to tell where an entry is defined.
+///
+/// A sequence has one child per item. A mapping has one child per key, holding the key's line and
+/// the children of its value. Unknown lines are 0.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTree {
+    pub line: usize,
+    pub children: Vec<LineTree>,
+}
+
+static NO_LINES: LineTree = LineTree { line: 0, children: Vec::new() };
+
+impl LineTree {
+    /// Read the lines of the first document in `content`; a document that cannot be read has none
+    pub fn read(content: &str) -> LineTree {
+        let mut reader = LineReader::default();
+        match Parser::new_from_str(content).load(&mut reader, false) {
+            Ok(()) => reader.root.unwrap_or_default(),
+            Err(_) => LineTree::default(),
+        }
+    }
+
+    /// The lines of the child at `index`, or none when the document had no such child
+    pub fn child(&self, index: usize) -> &LineTree {
+        self.children.get(index).unwrap_or(&NO_LINES)
+    }
+
+    /// The line, when it is known
+    pub fn known_line(&self) -> Option<usize> {
+        (self.line > 0).then_some(self.line)
+    }
+}
+
+/// A sequence or mapping being read, with the line of the key waiting for its value
+struct OpenNode {
+    node: LineTree,
+    anchor: usize,
+    is_mapping: bool,
+    key_line: Option<usize>,
+}
+
+#[derive(Default)]
+struct LineReader {
+    open: Vec<OpenNode>,
+    anchors: HashMap<usize, LineTree>,
+    root: Option<LineTree>,
+}
+
+impl LineReader {
+    fn close(&mut self, node: LineTree, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+
+        match self.open.last_mut() {
+            Some(parent) if parent.is_mapping => match parent.key_line.take() {
+                None => parent.key_line = Some(node.line),
+                Some(line) => parent.node.children.push(LineTree { line, children: node.children }),
+            },
+            Some(parent) => parent.node.children.push(node),
+            None => {
+                self.root.get_or_insert(node);
+            }
+        }
+    }
+}
+
+impl MarkedEventReceiver for LineReader {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let line = mark.line();
+        match event {
+            Event::Scalar(_, _, anchor, _) => self.close(LineTree { line, children: Vec::new() }, anchor),
+            Event::Alias(anchor) => {
+                let node = self.anchors.get(&anchor).cloned().unwrap_or_default();
+                self.close(node, 0);
+            }
+            Event::SequenceStart(anchor, _) | Event::MappingStart(anchor, _) => self.open.push(OpenNode {
+                node: LineTree { line, children: Vec::new() },
+                anchor,
+                is_mapping: matches!(event, Event::MappingStart(..)),
+                key_line: None,
+            }),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some(open) = self.open.pop() {
+                    self.close(open.node, open.anchor);
+                }
+            }
+            _ => {}
+        }
+    }
+}